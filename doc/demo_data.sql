@@ -0,0 +1,11 @@
+-- demo data for the users of the test db, run after the migrations:
+-- sqlite3 target/demo.db < doc/demo_data.sql
+
+UPDATE users SET attributes = '{"department":"R&D","cost_center":"CC-1001"}' WHERE id = 'max';
+
+UPDATE users SET
+  address = '{"formatted":"Königstraße 1, 70173 Stuttgart, Germany","street_address":"Königstraße 1","locality":"Stuttgart","postal_code":"70173","country":"DE"}',
+  email_verified = 1
+WHERE id = 'max';
+
+UPDATE users SET nickname = 'Maxy', preferred_username = 'maxy', zoneinfo = 'Europe/Berlin', updated_at = CURRENT_TIMESTAMP WHERE id = 'max';
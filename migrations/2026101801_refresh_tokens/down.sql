@@ -0,0 +1,5 @@
+DROP INDEX idx_oauth_tokens_family;
+
+ALTER TABLE oauth_tokens DROP COLUMN revoked;
+ALTER TABLE oauth_tokens DROP COLUMN parent;
+ALTER TABLE oauth_tokens DROP COLUMN family;
@@ -0,0 +1,7 @@
+-- refresh token rotation: all tokens issued from the same grant share a family
+
+ALTER TABLE oauth_tokens ADD COLUMN family VARCHAR;
+ALTER TABLE oauth_tokens ADD COLUMN parent VARCHAR; -- the refresh token this one was rotated from
+ALTER TABLE oauth_tokens ADD COLUMN revoked BOOLEAN NOT NULL DEFAULT 0;
+
+CREATE INDEX idx_oauth_tokens_family ON oauth_tokens (family);
@@ -0,0 +1,4 @@
+ALTER TABLE oauth_sessions DROP COLUMN code_challenge_method;
+ALTER TABLE oauth_sessions DROP COLUMN code_challenge;
+
+ALTER TABLE oauth_clients DROP COLUMN require_pkce;
@@ -0,0 +1,6 @@
+-- PKCE (RFC 7636)
+
+ALTER TABLE oauth_clients ADD COLUMN require_pkce BOOLEAN NOT NULL DEFAULT 0;
+
+ALTER TABLE oauth_sessions ADD COLUMN code_challenge VARCHAR;
+ALTER TABLE oauth_sessions ADD COLUMN code_challenge_method VARCHAR; -- 'S256' or 'plain'
@@ -0,0 +1,3 @@
+-- client types (https://www.rfc-editor.org/rfc/rfc6749#section-2.1): 'confidential' or 'public'
+
+ALTER TABLE oauth_clients ADD COLUMN client_type VARCHAR NOT NULL DEFAULT 'confidential';
@@ -0,0 +1,6 @@
+-- client authentication methods (https://openid.net/specs/openid-connect-core-1_0.html#ClientAuthentication)
+
+ALTER TABLE oauth_clients ADD COLUMN token_endpoint_auth_method VARCHAR NOT NULL DEFAULT 'client_secret_basic';
+ALTER TABLE oauth_clients ADD COLUMN jwks VARCHAR; -- JWK Set (json) used for private_key_jwt
+
+UPDATE oauth_clients SET token_endpoint_auth_method = 'none' WHERE client_type = 'public';
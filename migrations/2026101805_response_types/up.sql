@@ -0,0 +1,3 @@
+-- response types the client may request (json array), e.g. '["code", "id_token token"]'
+
+ALTER TABLE oauth_clients ADD COLUMN response_types VARCHAR NOT NULL DEFAULT '["code"]';
@@ -0,0 +1 @@
+ALTER TABLE oauth_clients DROP COLUMN authorization_signed_response_alg;
@@ -0,0 +1,3 @@
+-- JWT Secured Authorization Response Mode (https://openid.net/specs/oauth-v2-jarm.html)
+
+ALTER TABLE oauth_clients ADD COLUMN authorization_signed_response_alg VARCHAR; -- default: the id_token signing alg
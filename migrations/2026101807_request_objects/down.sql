@@ -0,0 +1 @@
+ALTER TABLE oauth_clients DROP COLUMN require_signed_request_object;
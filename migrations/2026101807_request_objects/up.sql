@@ -0,0 +1,3 @@
+-- signed request objects (https://www.rfc-editor.org/rfc/rfc9101)
+
+ALTER TABLE oauth_clients ADD COLUMN require_signed_request_object BOOLEAN NOT NULL DEFAULT 0;
@@ -0,0 +1,9 @@
+-- Pushed Authorization Requests (https://www.rfc-editor.org/rfc/rfc9126)
+
+CREATE TABLE pushed_requests (
+  request_uri VARCHAR NOT NULL PRIMARY KEY,
+  client_id VARCHAR NOT NULL,
+  params VARCHAR NOT NULL, -- the authorization request parameters (json)
+  expiration TIMESTAMP NOT NULL,
+    FOREIGN KEY (client_id) REFERENCES oauth_clients(id)
+);
@@ -0,0 +1,3 @@
+-- the max_age applied when the authorization request has none (seconds)
+
+ALTER TABLE oauth_clients ADD COLUMN default_max_age BIGINT;
@@ -0,0 +1,2 @@
+ALTER TABLE oauth_sessions DROP COLUMN amr;
+ALTER TABLE oauth_sessions DROP COLUMN acr;
@@ -0,0 +1,4 @@
+-- the authentication context class & the (space separated) methods of the login, issued in the id_token
+
+ALTER TABLE oauth_sessions ADD COLUMN acr TEXT;
+ALTER TABLE oauth_sessions ADD COLUMN amr TEXT;
@@ -0,0 +1,2 @@
+ALTER TABLE oauth_tokens DROP COLUMN claims;
+ALTER TABLE oauth_sessions DROP COLUMN claims;
@@ -0,0 +1,4 @@
+-- the `claims` authorization request parameter (json), kept with the code and the issued tokens
+
+ALTER TABLE oauth_sessions ADD COLUMN claims TEXT;
+ALTER TABLE oauth_tokens ADD COLUMN claims TEXT;
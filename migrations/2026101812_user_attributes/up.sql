@@ -0,0 +1,3 @@
+-- extra user attributes (json object), released as claims through the configured claims mapping
+
+ALTER TABLE users ADD COLUMN attributes TEXT;
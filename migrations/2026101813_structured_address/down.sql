@@ -0,0 +1,4 @@
+ALTER TABLE users DROP COLUMN phone_number_verified;
+ALTER TABLE users DROP COLUMN email_verified;
+
+UPDATE users SET address = json_extract(address, '$.formatted') WHERE address IS NOT NULL;
@@ -0,0 +1,7 @@
+-- the address is stored as the OIDC address claim (json): formatted, street_address, locality, region, postal_code, country
+-- the free text addresses become the formatted one
+
+UPDATE users SET address = json_object('formatted', address) WHERE address IS NOT NULL;
+
+ALTER TABLE users ADD COLUMN email_verified BOOLEAN NOT NULL DEFAULT 0;
+ALTER TABLE users ADD COLUMN phone_number_verified BOOLEAN NOT NULL DEFAULT 0;
@@ -0,0 +1,8 @@
+ALTER TABLE users DROP COLUMN updated_at;
+ALTER TABLE users DROP COLUMN zoneinfo;
+ALTER TABLE users DROP COLUMN gender;
+ALTER TABLE users DROP COLUMN website;
+ALTER TABLE users DROP COLUMN picture;
+ALTER TABLE users DROP COLUMN profile;
+ALTER TABLE users DROP COLUMN nickname;
+ALTER TABLE users DROP COLUMN middle_name;
@@ -0,0 +1,10 @@
+-- the remaining standard claims of the profile scope (https://openid.net/specs/openid-connect-core-1_0.html#StandardClaims)
+
+ALTER TABLE users ADD COLUMN middle_name TEXT;
+ALTER TABLE users ADD COLUMN nickname TEXT;
+ALTER TABLE users ADD COLUMN profile TEXT; -- URL of the profile page
+ALTER TABLE users ADD COLUMN picture TEXT; -- URL of the profile picture
+ALTER TABLE users ADD COLUMN website TEXT;
+ALTER TABLE users ADD COLUMN gender TEXT;
+ALTER TABLE users ADD COLUMN zoneinfo TEXT; -- format: "Europe/Berlin"
+ALTER TABLE users ADD COLUMN updated_at TIMESTAMP;
@@ -0,0 +1,3 @@
+ALTER TABLE oauth_clients DROP COLUMN userinfo_encrypted_response_enc;
+ALTER TABLE oauth_clients DROP COLUMN userinfo_encrypted_response_alg;
+ALTER TABLE oauth_clients DROP COLUMN userinfo_signed_response_alg;
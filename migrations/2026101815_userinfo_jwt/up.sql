@@ -0,0 +1,5 @@
+-- signed and/or encrypted userinfo responses (https://openid.net/specs/openid-connect-registration-1_0.html#ClientMetadata)
+
+ALTER TABLE oauth_clients ADD COLUMN userinfo_signed_response_alg TEXT;
+ALTER TABLE oauth_clients ADD COLUMN userinfo_encrypted_response_alg TEXT;
+ALTER TABLE oauth_clients ADD COLUMN userinfo_encrypted_response_enc TEXT;
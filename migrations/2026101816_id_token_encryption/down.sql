@@ -0,0 +1,2 @@
+ALTER TABLE oauth_clients DROP COLUMN id_token_encrypted_response_enc;
+ALTER TABLE oauth_clients DROP COLUMN id_token_encrypted_response_alg;
@@ -0,0 +1,4 @@
+-- encrypted id_tokens (https://openid.net/specs/openid-connect-registration-1_0.html#ClientMetadata)
+
+ALTER TABLE oauth_clients ADD COLUMN id_token_encrypted_response_alg TEXT;
+ALTER TABLE oauth_clients ADD COLUMN id_token_encrypted_response_enc TEXT;
@@ -0,0 +1,3 @@
+-- the id_token signing alg per client, the configured `id_token.signing_alg` if NULL
+
+ALTER TABLE oauth_clients ADD COLUMN id_token_signed_response_alg TEXT;
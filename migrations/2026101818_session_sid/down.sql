@@ -0,0 +1 @@
+ALTER TABLE oauth_sessions DROP COLUMN sid;
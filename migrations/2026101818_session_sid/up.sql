@@ -0,0 +1,3 @@
+-- the SSO session of the authorization, released as `sid` in the id_token
+
+ALTER TABLE oauth_sessions ADD COLUMN sid TEXT;
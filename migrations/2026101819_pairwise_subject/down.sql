@@ -0,0 +1,2 @@
+ALTER TABLE oauth_clients DROP COLUMN sector_identifier_uri;
+ALTER TABLE oauth_clients DROP COLUMN subject_type;
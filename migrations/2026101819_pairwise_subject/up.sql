@@ -0,0 +1,4 @@
+-- pairwise subject identifiers (https://openid.net/specs/openid-connect-core-1_0.html#SubjectIDTypes)
+
+ALTER TABLE oauth_clients ADD COLUMN subject_type TEXT NOT NULL DEFAULT 'public';
+ALTER TABLE oauth_clients ADD COLUMN sector_identifier_uri TEXT;
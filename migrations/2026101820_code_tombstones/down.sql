@@ -0,0 +1,2 @@
+ALTER TABLE oauth_sessions DROP COLUMN family;
+ALTER TABLE oauth_sessions DROP COLUMN consumed;
@@ -0,0 +1,5 @@
+-- consumed authorization codes are kept until they expire, a replay revokes the token family issued from the code
+-- (https://www.rfc-editor.org/rfc/rfc6749#section-4.1.2)
+
+ALTER TABLE oauth_sessions ADD COLUMN consumed BOOLEAN NOT NULL DEFAULT 0;
+ALTER TABLE oauth_sessions ADD COLUMN family TEXT;
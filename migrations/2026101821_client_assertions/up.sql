@@ -0,0 +1,10 @@
+-- the used client assertions (private_key_jwt, client_secret_jwt), a jti is accepted only once
+-- (https://www.rfc-editor.org/rfc/rfc7523#section-3)
+
+CREATE TABLE client_assertions (
+  client_id VARCHAR NOT NULL,
+  jti VARCHAR NOT NULL,
+  expiration TIMESTAMP NOT NULL, -- the exp of the assertion, the jti is kept until then
+    PRIMARY KEY (client_id, jti),
+    FOREIGN KEY (client_id) REFERENCES oauth_clients(id)
+);
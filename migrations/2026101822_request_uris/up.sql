@@ -0,0 +1,4 @@
+-- the request_uri values the client may pass by reference (json array), e.g. '["https://client.example.org/request.jwt"]'
+-- (https://openid.net/specs/openid-connect-registration-1_0.html#ClientMetadata)
+
+ALTER TABLE oauth_clients ADD COLUMN request_uris VARCHAR NOT NULL DEFAULT '[]';
@@ -0,0 +1,4 @@
+-- the `preferred_username` claim of the profile scope, NULL if the user has none
+-- (the login name is the user id, it is never released as `preferred_username`)
+
+ALTER TABLE users ADD COLUMN preferred_username TEXT;
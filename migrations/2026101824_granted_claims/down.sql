@@ -0,0 +1 @@
+DROP TABLE granted_claims;
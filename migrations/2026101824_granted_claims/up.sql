@@ -0,0 +1,10 @@
+-- the claims the user consented to individually (`claims` request parameter), beyond the granted scopes
+
+CREATE TABLE granted_claims (
+  client_id VARCHAR NOT NULL,
+  claim VARCHAR NOT NULL,
+  user_id VARCHAR NOT NULL,
+  PRIMARY KEY (client_id, user_id, claim)
+  FOREIGN KEY (client_id) REFERENCES oauth_clients(id),
+  FOREIGN KEY (user_id) REFERENCES users(id)
+);
@@ -74,7 +74,26 @@ pub struct OauthConfig {
     pub auth_code_exp: i64,
     #[serde(default = "default_token_exp")]
     pub token_exp: i64,
+    #[serde(default = "default_refresh_token_exp")]
+    pub refresh_token_exp: i64,
+    /// lifetime of the `request_uri` of pushed authorization requests, in seconds
+    #[serde(default = "default_par_exp")]
+    pub par_exp: i64,
+    /// authorization requests are only accepted through `/oauth2/par`
+    #[serde(default)]
+    pub require_pushed_authorization_requests: bool,
+    #[serde(default)]
+    pub access_token: Option<AccessTokenConfig>,
     pub id_token: IdTokenConfig,
+    /// the authentication context classes, from the weakest to the strongest
+    #[serde(default)]
+    pub acr: Vec<AcrConfig>,
+    /// custom scopes and the source of the claim values
+    #[serde(default)]
+    pub claims: ClaimsConfig,
+    /// the salt of the pairwise subject identifiers, pairwise clients are rejected if not set
+    #[serde(default)]
+    pub pairwise_salt: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -82,13 +101,45 @@ pub struct AccessTokenConfig {
     #[serde(alias = "type")]
     pub kind: String,
     pub signing_alg: Option<Algorithm>,
+    /// defaults to the keys configured for the id_token
     pub available_signing: Option<HashMap<Algorithm, Vec<String>>>,
+    /// the `aud` of JWT access tokens, defaults to the issuer
+    pub audience: Option<String>,
+}
+
+impl AccessTokenConfig {
+    /// `REF` issues opaque reference tokens, `JWT` signed tokens (RFC 9068)
+    pub fn is_jwt(&self) -> bool {
+        self.kind.eq_ignore_ascii_case("jwt")
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct IdTokenConfig {
     pub signing_alg: Algorithm,
     pub available_signing: HashMap<Algorithm, Vec<String>>,
+    /// audiences added to the `aud` of the id_tokens, next to the client
+    #[serde(default)]
+    pub audiences: Vec<String>,
+}
+
+/// an authentication context class (`acr`), satisfied when all its methods (`amr`) were used at login
+#[derive(Debug, Clone, Deserialize)]
+pub struct AcrConfig {
+    pub value: String,
+    pub amr: Vec<String>,
+}
+
+/// the claims released by the scopes, on top of the standard ones (https://openid.net/specs/openid-connect-core-1_0.html#ScopeClaims)
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct ClaimsConfig {
+    /// the claims released by each scope, a standard scope configured here is replaced
+    #[serde(default)]
+    pub scopes: HashMap<String, Vec<String>>,
+    /// the source of a claim value: a `User` field or `attributes.<name>`. Claims without a mapping
+    /// are read from the field with the same name, or else from the user attribute with the same name
+    #[serde(default)]
+    pub mapping: HashMap<String, String>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -112,6 +163,14 @@ fn default_token_exp() -> i64 {
     3600
 }
 
+fn default_refresh_token_exp() -> i64 {
+    30 * 24 * 3600
+}
+
+fn default_par_exp() -> i64 {
+    60
+}
+
 pub fn load(path: impl AsRef<Path>) -> Result<Config, Box<dyn std::error::Error>> {
     let content = std::fs::read_to_string(path)?;
     let config = serde_yaml::from_str(&content)?;
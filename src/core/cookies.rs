@@ -4,11 +4,24 @@ use actix_web::{HttpRequest, HttpResponse};
 #[derive(Debug, Clone, Deserialize, Default, Serialize)]
 pub struct AuthSessionCookie {
     pub client_id: String,
+    pub response_mode: Option<String>,
+    pub response_type: String,
     pub scopes: String,
     pub redirect_uri: String,
     pub nonce: Option<String>,
     pub state: Option<String>,
+    /// the `prompt` of the authorization request, e.g. `consent` forces the consent step
+    pub prompt: Option<String>,
+    pub acr_values: Option<String>,
+    /// the `claims` request parameter (json)
+    pub claims: Option<String>,
     pub subject: Option<String>,
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: Option<String>,
+    /// the fresh login of `subject` while the consent is still missing, the SSO session is only created after the consent.
+    /// Not set when the user only consents to an existing SSO session
+    #[serde(default)]
+    pub login: Option<SSOCookie>,
 }
 
 #[derive(Debug, Clone, Deserialize, Default, Serialize)]
@@ -17,6 +30,12 @@ pub struct SSOCookie {
     pub subject: String,
     pub client_id: String,
     pub auth_time: i64,
+    /// the authentication methods used at login (RFC 8176)
+    #[serde(default)]
+    pub amr: Vec<String>,
+    /// the id of the SSO session (`sid` of the id_tokens), not set in cookies of older versions
+    #[serde(default)]
+    pub sid: Option<String>,
     //pub scopes: String,
 }
 
@@ -62,7 +62,7 @@ impl ResponseError for AppError {
     }
 }
 
-///
+/// internal (non displayable) error
 #[derive(thiserror::Error, Debug)]
 pub enum InternalError {
     #[error("db connection error")]
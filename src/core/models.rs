@@ -1,5 +1,8 @@
-use super::super::db::schema::{oauth_sessions, oauth_tokens, users};
+use super::super::db::schema::{client_assertions, oauth_sessions, oauth_tokens, pushed_requests};
+use super::config::IdTokenConfig;
 use chrono::NaiveDateTime;
+use jwt::jwk::JwkSet;
+use jwt::Algorithm;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -14,9 +17,88 @@ pub struct OauthClient {
     pub callback_url: Vec<String>,
     // the (space separated) scopes allowed for the client to request
     pub allowed_scopes: String,
+    /// the client must use PKCE (RFC 7636) in the authorization code flow
+    pub require_pkce: bool,
+    /// `confidential` (authenticates with its secret) or `public` (no secret, must use PKCE)
+    pub client_type: String,
+    /// one of `client_secret_basic`, `client_secret_post`, `client_secret_jwt` (needs a plain secret), `private_key_jwt` or `none`
+    pub token_endpoint_auth_method: String,
+    /// the client's public keys (JWK Set), used to verify the JWTs it signs
+    pub jwks: Option<JwkSet>,
+    /// the `response_type` values the client may use, e.g. `code` or `id_token token`
+    pub response_types: Vec<String>,
+    /// the alg of the signed (JARM) authorization responses, the id_token signing alg if not set
+    pub authorization_signed_response_alg: Option<Algorithm>,
+    /// the authorization request must be sent as a signed request object (RFC 9101)
+    pub require_signed_request_object: bool,
+    /// the `max_age` (seconds) applied when the authorization request has none
+    pub default_max_age: Option<i64>,
+    /// the userinfo response is a JWT signed with this alg
+    pub userinfo_signed_response_alg: Option<Algorithm>,
+    /// the userinfo response is encrypted (JWE) to the client's key with this alg, e.g. `RSA-OAEP-256`
+    pub userinfo_encrypted_response_alg: Option<String>,
+    /// the content encryption of the userinfo response, `A128CBC-HS256` if not set
+    pub userinfo_encrypted_response_enc: Option<String>,
+    /// the alg of the id_token signature, the configured `id_token.signing_alg` if not set
+    pub id_token_signed_response_alg: Option<Algorithm>,
+    /// the id_tokens are signed, then encrypted (JWE) to the client's key with this alg, e.g. `RSA-OAEP-256` or `ECDH-ES`
+    pub id_token_encrypted_response_alg: Option<String>,
+    /// the content encryption of the id_tokens, `A128CBC-HS256` if not set
+    pub id_token_encrypted_response_enc: Option<String>,
+    /// `public` (the user id is the `sub`) or `pairwise` (a different `sub` per sector)
+    pub subject_type: String,
+    /// groups clients with different redirect URI hosts into one sector for the pairwise `sub`, it must list the redirect URIs
+    pub sector_identifier_uri: Option<String>,
+    /// the pre-registered `request_uri` values, only those are fetched
+    pub request_uris: Vec<String>,
 }
 
-#[derive(Debug, Clone, Insertable, Queryable)]
+/// the client metadata defaults of the OIDC dynamic client registration, a confidential client of the code flow
+impl Default for OauthClient {
+    fn default() -> Self {
+        OauthClient {
+            id: String::new(),
+            secret: String::new(),
+            name: String::new(),
+            callback_url: vec![],
+            allowed_scopes: String::new(),
+            require_pkce: false,
+            client_type: "confidential".into(),
+            token_endpoint_auth_method: "client_secret_basic".into(),
+            jwks: None,
+            response_types: vec!["code".into()],
+            authorization_signed_response_alg: None,
+            require_signed_request_object: false,
+            default_max_age: None,
+            userinfo_signed_response_alg: None,
+            userinfo_encrypted_response_alg: None,
+            userinfo_encrypted_response_enc: None,
+            id_token_signed_response_alg: None,
+            id_token_encrypted_response_alg: None,
+            id_token_encrypted_response_enc: None,
+            subject_type: "public".into(),
+            sector_identifier_uri: None,
+            request_uris: vec![],
+        }
+    }
+}
+
+impl OauthClient {
+    pub fn is_public(&self) -> bool {
+        self.client_type == "public"
+    }
+
+    pub fn is_pairwise(&self) -> bool {
+        self.subject_type == "pairwise"
+    }
+
+    /// the alg the client's id_tokens are signed with
+    pub fn id_token_signing_alg(&self, cfg: &IdTokenConfig) -> Algorithm {
+        self.id_token_signed_response_alg.unwrap_or(cfg.signing_alg)
+    }
+}
+
+#[derive(Debug, Clone, Default, Insertable, Queryable)]
 #[diesel(table_name = oauth_sessions)]
 pub struct OauthSession {
     /// random generated id of the session
@@ -27,9 +109,23 @@ pub struct OauthSession {
     pub subject: String, // username
     pub expiration: NaiveDateTime,
     pub auth_time: Option<NaiveDateTime>,
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: Option<String>,
+    /// the authentication context class reached at login
+    pub acr: Option<String>,
+    /// the (space separated) authentication methods used at login
+    pub amr: Option<String>,
+    /// the `claims` request parameter (json)
+    pub claims: Option<String>,
+    /// the id of the SSO session the user authenticated in
+    pub sid: Option<String>,
+    /// the code was exchanged, the session is kept until it expires to detect replays
+    pub consumed: bool,
+    /// the token family of the tokens issued from the code
+    pub family: Option<String>,
 }
 
-#[derive(Debug, Clone, Insertable, Queryable)]
+#[derive(Debug, Clone, Default, Insertable, Queryable)]
 #[diesel(table_name = oauth_tokens)]
 pub struct OauthToken {
     pub token: String,
@@ -39,20 +135,89 @@ pub struct OauthToken {
     pub subject: Option<String>, // always set by OIDC?
     pub expiration: Option<i64>,
     pub created: NaiveDateTime,
+    /// groups the tokens issued from the same grant (refresh token rotation)
+    pub family: Option<String>,
+    /// the refresh token this token was issued from
+    pub parent: Option<String>,
+    pub revoked: bool,
+    /// the `claims` request parameter of the grant (json), its `userinfo` member is used by the userinfo endpoint
+    pub claims: Option<String>,
 }
 
-#[derive(Queryable, Insertable, Debug)]
-#[diesel(table_name = users)]
+impl OauthToken {
+    /// a token is active if it was not revoked and did not expire yet
+    pub fn is_active(&self) -> bool {
+        !self.revoked
+            && self
+                .expiration
+                .is_none_or(|secs| self.created + chrono::Duration::seconds(secs) > chrono::Utc::now().naive_utc())
+    }
+}
+
+/// an authorization request pushed by the client, referenced by `request_uri` (RFC 9126)
+#[derive(Debug, Clone, Insertable, Queryable)]
+#[diesel(table_name = pushed_requests)]
+pub struct PushedRequest {
+    pub request_uri: String,
+    pub client_id: String,
+    /// the authorization request parameters (json)
+    pub params: String,
+    pub expiration: NaiveDateTime,
+}
+
+/// a used client assertion, its `jti` is kept until the assertion expires (RFC 7523)
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = client_assertions)]
+pub struct ClientAssertion {
+    pub client_id: String,
+    pub jti: String,
+    pub expiration: NaiveDateTime,
+}
+
+#[derive(Debug, Clone)]
 pub struct User {
     pub id: String,
     pub password: String,
     pub email: Option<String>,
+    pub email_verified: bool,
     pub phone: Option<String>,
+    pub phone_number_verified: bool,
     // profile
     pub given_name: String,
     pub family_name: String,
+    pub middle_name: Option<String>,
+    pub nickname: Option<String>,
+    /// released as `preferred_username`, not the login name (the user id)
+    pub preferred_username: Option<String>,
     pub preferred_display_name: Option<String>, // if not provided should use "given_name family_name"
-    pub address: Option<String>,                // free text
-    pub birthdate: Option<String>,              // format: "YYYY-MM-DD"
-    pub locale: Option<String>,                 // format: "en-US"
+    pub address: Option<Address>,
+    pub birthdate: Option<String>, // format: "YYYY-MM-DD"
+    pub locale: Option<String>,    // format: "en-US"
+    pub profile: Option<String>,   // URL of the profile page
+    pub picture: Option<String>,   // URL of the profile picture
+    pub website: Option<String>,
+    pub gender: Option<String>,
+    pub zoneinfo: Option<String>, // format: "Europe/Berlin"
+    /// the last change of the profile, released as `updated_at` (seconds since epoch)
+    pub updated_at: Option<NaiveDateTime>,
+    /// extra attributes (json object), released as claims through the claims mapping
+    pub attributes: Option<String>,
+}
+
+/// the postal address, as the OIDC `address` claim (https://openid.net/specs/openid-connect-core-1_0.html#AddressClaim)
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Address {
+    /// the full address, formatted for display
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub formatted: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub street_address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locality: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub postal_code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
 }
@@ -72,12 +72,12 @@ impl Secrets {
 }
 
 pub fn verify_password(expected_password: &str, received_password: &str) -> actix_web::Result<(), String> {
-    if received_password.len() == 0 {
+    if received_password.is_empty() {
         Err("no password received")?
     }
 
-    if expected_password.starts_with("{BCRYPT}") {
-        let valid = bcrypt_verify(received_password, &expected_password[8..]).map_err(|e| format!("bcrypt error: {}", e))?;
+    if let Some(hash) = expected_password.strip_prefix("{BCRYPT}") {
+        let valid = bcrypt_verify(received_password, hash).map_err(|e| format!("bcrypt error: {}", e))?;
         if !valid {
             Err("invalid password".to_string())
         } else {
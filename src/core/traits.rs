@@ -9,14 +9,26 @@ use std::collections::HashSet;
 pub trait OauthDatabase {
     fn fetch_client_config(&self, client_id: &str) -> QueryResult<models::OauthClient>;
     fn save_oauth_session(&self, session: models::OauthSession) -> Result<(), InternalError>;
+    /// marks the session of the code consumed, `consumed` is set in the returned session if it was consumed before (a replay)
     fn consume_oauth_session_by_code(&self, code: &str) -> Result<models::OauthSession, InternalError>;
     fn save_oauth_token(&self, data: &models::OauthToken) -> Result<(), InternalError>;
     fn load_token_data(&self, token: &str) -> Result<models::OauthToken, InternalError>;
+    /// revokes the token if it is not revoked yet, `false` if it was already revoked
+    fn revoke_token(&self, token: &str) -> Result<bool, InternalError>;
+    fn revoke_token_family(&self, family: &str) -> Result<usize, InternalError>;
+    fn save_pushed_request(&self, request: &models::PushedRequest) -> Result<(), InternalError>;
+    /// a pushed request can be used only once, and only by the client that pushed it
+    fn consume_pushed_request(&self, request_uri: &str, client_id: &str) -> Result<models::PushedRequest, InternalError>;
+    /// records the `jti` of a client assertion, `false` if it was already used
+    fn save_client_assertion(&self, assertion: &models::ClientAssertion) -> Result<bool, InternalError>;
 }
 
 #[cfg_attr(any(test, feature = "testing"), automock)]
 pub trait UserDatabase {
     fn fetch_user_by_id(&self, mail: &str) -> Result<models::User, InternalError>;
     fn fetch_granted_scopes(&self, cid: &str, uid: &str) -> Result<HashSet<String>, InternalError>;
-    fn save_granted_scopes(&self, uid: &str, cid: &str, scopes: &Vec<String>) -> Result<(), InternalError>;
+    fn save_granted_scopes(&self, uid: &str, cid: &str, scopes: &[String]) -> Result<(), InternalError>;
+    fn fetch_granted_claims(&self, cid: &str, uid: &str) -> Result<HashSet<String>, InternalError>;
+    /// already granted claims are ignored
+    fn save_granted_claims(&self, uid: &str, cid: &str, claims: &[String]) -> Result<(), InternalError>;
 }
@@ -48,6 +48,23 @@ pub fn validate(expr: bool, message: &str) -> Result<String, AppError> {
     Ok(String::new())
 }
 
+/// appends the response parameters to the redirect URI, in the query or in the fragment
+///
+/// # Examples
+/// ```ignore
+/// assert_eq!(redirect_url("https://app/cb", &[("code", "abc")], true)?, "https://app/cb#code=abc");
+/// ```
+pub fn redirect_url(redirect_uri: &str, params: &[(&str, &str)], fragment: bool) -> Result<String, AppError> {
+    let mut url = url::Url::parse(redirect_uri).map_err(|_| AppError::bad_req("'redirect_uri' is invalid"))?;
+    if fragment {
+        let encoded = url::form_urlencoded::Serializer::new(String::new()).extend_pairs(params).finish();
+        url.set_fragment(Some(&encoded));
+    } else {
+        url.query_pairs_mut().extend_pairs(params);
+    }
+    Ok(url.to_string())
+}
+
 pub fn load_file(filename: &str) -> Result<Vec<u8>, Error> {
     use std::io::prelude::*;
     let mut file = std::fs::File::open(filename)?;
@@ -69,4 +86,17 @@ mod tests {
     fn test_parse_basic_auth() {
         assert_eq!(parse_basic_auth("Basic YWRtaW46YWRtaW4="), Some(("admin".to_owned(), "admin".to_owned())));
     }
+
+    #[test]
+    fn test_redirect_url() {
+        let params = [("code", "abc"), ("state", "x y")];
+        assert_eq!(
+            redirect_url("https://app/cb", &params, false).unwrap(),
+            "https://app/cb?code=abc&state=x+y"
+        );
+        assert_eq!(
+            redirect_url("https://app/cb", &params, true).unwrap(),
+            "https://app/cb#code=abc&state=x+y"
+        );
+    }
 }
@@ -4,7 +4,8 @@ use super::core::models;
 use super::core::{OauthDatabase, UserDatabase};
 use diesel::prelude::*;
 use diesel::r2d2::{ConnectionManager, Pool};
-use diesel::result::Error::QueryBuilderError;
+use diesel::result::DatabaseErrorKind;
+use diesel::result::Error::{DatabaseError, QueryBuilderError};
 use r2d2::PooledConnection;
 use std::collections::HashSet;
 
@@ -15,6 +16,23 @@ struct OauthClientRow {
     pub name: String,
     pub callback_url: String,
     pub allowed_scopes: String,
+    pub require_pkce: bool,
+    pub client_type: String,
+    pub token_endpoint_auth_method: String,
+    pub jwks: Option<String>,
+    pub response_types: String,
+    pub authorization_signed_response_alg: Option<String>,
+    pub require_signed_request_object: bool,
+    pub default_max_age: Option<i64>,
+    pub userinfo_signed_response_alg: Option<String>,
+    pub userinfo_encrypted_response_alg: Option<String>,
+    pub userinfo_encrypted_response_enc: Option<String>,
+    pub id_token_encrypted_response_alg: Option<String>,
+    pub id_token_encrypted_response_enc: Option<String>,
+    pub id_token_signed_response_alg: Option<String>,
+    pub subject_type: String,
+    pub sector_identifier_uri: Option<String>,
+    pub request_uris: String,
 }
 
 impl TryFrom<OauthClientRow> for models::OauthClient {
@@ -27,10 +45,102 @@ impl TryFrom<OauthClientRow> for models::OauthClient {
             name: row.name,
             callback_url: serde_json::from_str(&row.callback_url)?,
             allowed_scopes: row.allowed_scopes,
+            require_pkce: row.require_pkce,
+            client_type: row.client_type,
+            token_endpoint_auth_method: row.token_endpoint_auth_method,
+            jwks: row.jwks.as_deref().map(serde_json::from_str).transpose()?,
+            response_types: serde_json::from_str(&row.response_types)?,
+            authorization_signed_response_alg: row
+                .authorization_signed_response_alg
+                .as_deref()
+                .map(|alg| alg.parse().map_err(serde::de::Error::custom))
+                .transpose()?,
+            require_signed_request_object: row.require_signed_request_object,
+            default_max_age: row.default_max_age,
+            userinfo_signed_response_alg: row
+                .userinfo_signed_response_alg
+                .as_deref()
+                .map(|alg| alg.parse().map_err(serde::de::Error::custom))
+                .transpose()?,
+            userinfo_encrypted_response_alg: row.userinfo_encrypted_response_alg,
+            userinfo_encrypted_response_enc: row.userinfo_encrypted_response_enc,
+            id_token_signed_response_alg: row
+                .id_token_signed_response_alg
+                .as_deref()
+                .map(|alg| alg.parse().map_err(serde::de::Error::custom))
+                .transpose()?,
+            id_token_encrypted_response_alg: row.id_token_encrypted_response_alg,
+            id_token_encrypted_response_enc: row.id_token_encrypted_response_enc,
+            subject_type: row.subject_type,
+            sector_identifier_uri: row.sector_identifier_uri,
+            request_uris: serde_json::from_str(&row.request_uris)?,
         })
     }
 }
 
+#[derive(Queryable)]
+struct UserRow {
+    pub id: String,
+    pub password: String,
+    pub email: Option<String>,
+    pub phone: Option<String>,
+    pub given_name: String,
+    pub family_name: String,
+    pub preferred_display_name: Option<String>,
+    pub address: Option<String>,
+    pub birthdate: Option<String>,
+    pub locale: Option<String>,
+    pub attributes: Option<String>,
+    pub email_verified: bool,
+    pub phone_number_verified: bool,
+    pub middle_name: Option<String>,
+    pub nickname: Option<String>,
+    pub profile: Option<String>,
+    pub picture: Option<String>,
+    pub website: Option<String>,
+    pub gender: Option<String>,
+    pub zoneinfo: Option<String>,
+    pub updated_at: Option<chrono::NaiveDateTime>,
+    pub preferred_username: Option<String>,
+}
+
+impl From<UserRow> for models::User {
+    fn from(row: UserRow) -> Self {
+        models::User {
+            id: row.id,
+            password: row.password,
+            email: row.email,
+            email_verified: row.email_verified,
+            phone: row.phone,
+            phone_number_verified: row.phone_number_verified,
+            given_name: row.given_name,
+            family_name: row.family_name,
+            middle_name: row.middle_name,
+            nickname: row.nickname,
+            preferred_username: row.preferred_username,
+            preferred_display_name: row.preferred_display_name,
+            address: row.address.map(parse_address),
+            birthdate: row.birthdate,
+            locale: row.locale,
+            profile: row.profile,
+            picture: row.picture,
+            website: row.website,
+            gender: row.gender,
+            zoneinfo: row.zoneinfo,
+            updated_at: row.updated_at,
+            attributes: row.attributes,
+        }
+    }
+}
+
+/// a plain text address (e.g. written by another tool) is kept as the `formatted` address
+fn parse_address(raw: String) -> models::Address {
+    serde_json::from_str(&raw).unwrap_or_else(|_| models::Address {
+        formatted: Some(raw),
+        ..Default::default()
+    })
+}
+
 //pub mod models;
 pub mod schema;
 
@@ -51,7 +161,7 @@ impl OauthDatabase for DbSqlBridge {
 
         let mut conn = get_connection(self).map_err(|_e| QueryBuilderError(Box::from("failed to get DB conection")))?;
         let row = oauth_clients.find(client_id).first::<OauthClientRow>(&mut conn)?;
-        let item = models::OauthClient::try_from(row).map_err(|e| QueryBuilderError(Box::from(format!("invalid callback_url JSON: {}", e))))?;
+        let item = models::OauthClient::try_from(row).map_err(|e| QueryBuilderError(Box::from(format!("invalid client JSON: {}", e))))?;
 
         trace!("client-config: {:?}", item);
         Ok(item)
@@ -97,21 +207,26 @@ impl OauthDatabase for DbSqlBridge {
 
         let mut conn = get_connection(self)?;
 
+        // the consumed sessions are kept as tombstones until they expire
+        let now = chrono::Utc::now().naive_utc();
+        diesel::delete(oauth_sessions)
+            .filter(consumed.eq(true).and(expiration.lt(now)))
+            .execute(&mut conn)
+            .map_err(|_| InternalError::query_fail("error deleting expired oauth sessions"))?;
+
+        // marking it consumed is atomic: only one of concurrent requests with the same code sees it unused
+        let marked = diesel::update(oauth_sessions.filter(auth_code.eq(code)).filter(consumed.eq(false)))
+            .set(consumed.eq(true))
+            .execute(&mut conn)
+            .map_err(|_| InternalError::query_fail(&format!("error consuming oauth session by code {}", code)))?;
+
         let mut items = oauth_sessions
             .filter(auth_code.eq(code))
             .load::<models::OauthSession>(&mut conn)
             .map_err(|_| InternalError::query_fail(&format!("error loading oauth session by code {}", code)))?;
 
-        diesel::delete(oauth_sessions)
-            .filter(auth_code.eq(code))
-            .execute(&mut conn)
-            .map_err(|_| InternalError::query_fail(&format!("error deleting oauth session by code {}", code)))?;
-
-        if items.is_empty() {
-            return Err(NotFound);
-        }
-
-        let item = items.pop().unwrap();
+        let mut item = items.pop().ok_or(NotFound)?;
+        item.consumed = marked == 0; // already consumed before: a replay
         debug!("oauthSession({}) = {:?}", code, &item);
         Ok(item)
     }
@@ -201,6 +316,95 @@ impl OauthDatabase for DbSqlBridge {
         debug!("oauthToken({}) = {:?}", t, &item);
         Ok(item)
     }
+
+    fn revoke_token(&self, t: &str) -> Result<bool, InternalError> {
+        use self::schema::oauth_tokens::dsl::*;
+        trace!("revoke_token({})...", t);
+
+        let mut conn = get_connection(self)?;
+
+        // only one of concurrent requests revokes the token
+        let revoked_count = diesel::update(oauth_tokens.find(t).filter(revoked.eq(false)))
+            .set(revoked.eq(true))
+            .execute(&mut conn)
+            .map_err(|_| InternalError::query_fail("error revoking token"))?;
+        Ok(revoked_count == 1)
+    }
+
+    fn revoke_token_family(&self, f: &str) -> Result<usize, InternalError> {
+        use self::schema::oauth_tokens::dsl::*;
+        trace!("revoke_token_family({})...", f);
+
+        let mut conn = get_connection(self)?;
+
+        let revoked_count = diesel::update(oauth_tokens.filter(family.eq(f)))
+            .set(revoked.eq(true))
+            .execute(&mut conn)
+            .map_err(|_| InternalError::query_fail(&format!("error revoking token family {}", f)))?;
+
+        debug!("revoked {} tokens of family {}", revoked_count, f);
+        Ok(revoked_count)
+    }
+
+    fn save_pushed_request(&self, data: &models::PushedRequest) -> Result<(), InternalError> {
+        use self::schema::pushed_requests::dsl::*;
+        trace!("saving pushed request {:?}", data);
+
+        let mut conn = get_connection(self)?;
+
+        diesel::insert_into(pushed_requests)
+            .values(data)
+            .execute(&mut conn)
+            .map_err(|_| InternalError::query_fail("error saving pushed request"))?;
+        Ok(())
+    }
+
+    fn consume_pushed_request(&self, uri: &str, cid: &str) -> Result<models::PushedRequest, InternalError> {
+        use self::schema::pushed_requests::dsl::*;
+        trace!("consume_pushed_request({}, {})...", uri, cid);
+
+        let mut conn = get_connection(self)?;
+
+        diesel::delete(pushed_requests)
+            .filter(expiration.lt(chrono::Utc::now().naive_utc()))
+            .execute(&mut conn)
+            .map_err(|_| InternalError::query_fail("error deleting expired pushed requests"))?;
+
+        let mut items = pushed_requests
+            .filter(request_uri.eq(uri).and(client_id.eq(cid)))
+            .load::<models::PushedRequest>(&mut conn)
+            .map_err(|_| InternalError::query_fail(&format!("error loading pushed request {}", uri)))?;
+        let item = items.pop().ok_or(NotFound)?;
+
+        // the delete is atomic: only one of concurrent requests with the same request_uri deletes it
+        let deleted = diesel::delete(pushed_requests.filter(request_uri.eq(uri).and(client_id.eq(cid))))
+            .execute(&mut conn)
+            .map_err(|_| InternalError::query_fail(&format!("error deleting pushed request {}", uri)))?;
+        if deleted == 0 {
+            debug!("pushed request {} already consumed", uri);
+            return Err(NotFound);
+        }
+        Ok(item)
+    }
+
+    fn save_client_assertion(&self, data: &models::ClientAssertion) -> Result<bool, InternalError> {
+        use self::schema::client_assertions::dsl::*;
+        trace!("save_client_assertion({}, {})...", data.client_id, data.jti);
+
+        let mut conn = get_connection(self)?;
+
+        // expired assertions are rejected anyway, their jti is not needed anymore
+        diesel::delete(client_assertions)
+            .filter(expiration.lt(chrono::Utc::now().naive_utc()))
+            .execute(&mut conn)
+            .map_err(|_| InternalError::query_fail("error deleting expired client assertions"))?;
+
+        match diesel::insert_into(client_assertions).values(data).execute(&mut conn) {
+            Ok(_) => Ok(true),
+            Err(DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => Ok(false),
+            Err(_) => Err(InternalError::query_fail("error saving client assertion")),
+        }
+    }
 }
 
 impl UserDatabase for DbSqlBridge {
@@ -212,14 +416,15 @@ impl UserDatabase for DbSqlBridge {
 
         let mut items = users
             .filter(id.eq(uid))
-            .load::<models::User>(&mut conn)
+            .load::<UserRow>(&mut conn)
             .map_err(|_| InternalError::query_fail(&format!("error loading user {}", uid)))?;
 
         if items.is_empty() {
             return Err(NotFound);
         }
 
-        let item = items.pop().ok_or(NotFound)?;
+        let row = items.pop().ok_or(NotFound)?;
+        let item = models::User::from(row);
         debug!("user({}) = {:?}", uid, &item);
         Ok(item)
     }
@@ -241,7 +446,7 @@ impl UserDatabase for DbSqlBridge {
         Ok(items.into_iter().collect())
     }
 
-    fn save_granted_scopes(&self, uid: &str, cid: &str, scopes: &Vec<String>) -> Result<(), InternalError> {
+    fn save_granted_scopes(&self, uid: &str, cid: &str, scopes: &[String]) -> Result<(), InternalError> {
         use self::schema::granted_scopes::dsl::*;
         trace!("save_granted_scopes({}, {}, {:?})...", uid, cid, scopes);
 
@@ -260,6 +465,39 @@ impl UserDatabase for DbSqlBridge {
         debug!("saved {} granted-scopes to user {}: {:?}", inserted, uid, scopes);
         Ok(())
     }
+
+    fn fetch_granted_claims(&self, cid: &str, uid: &str) -> Result<HashSet<String>, InternalError> {
+        use self::schema::granted_claims::dsl::*;
+        trace!("fetch_granted_claims({}, {})...", cid, uid);
+
+        let mut conn = get_connection(self)?;
+
+        let items = granted_claims
+            .select(claim)
+            .filter(user_id.eq(uid))
+            .filter(client_id.eq(cid))
+            .load::<String>(&mut conn)
+            .map_err(|_| InternalError::query_fail(&format!("error loading claims [cid: {}, uid: {}]", cid, uid)))?;
+
+        debug!("loaded claims(cid: {}, uid: {}) = {:?}", cid, uid, &items);
+        Ok(items.into_iter().collect())
+    }
+
+    fn save_granted_claims(&self, uid: &str, cid: &str, claims: &[String]) -> Result<(), InternalError> {
+        use self::schema::granted_claims::dsl::*;
+        trace!("save_granted_claims({}, {}, {:?})...", uid, cid, claims);
+
+        let mut conn = get_connection(self)?;
+
+        let values: Vec<_> = claims.iter().map(|c| (client_id.eq(cid), claim.eq(c), user_id.eq(uid))).collect();
+        let inserted = diesel::insert_or_ignore_into(granted_claims)
+            .values(&values)
+            .execute(&mut conn)
+            .map_err(|_| InternalError::query_fail("error saving granted claims"))?;
+
+        debug!("saved {} granted-claims to user {}: {:?}", inserted, uid, claims);
+        Ok(())
+    }
 }
 
 /*pub fn create_user(& self, msg: CreateUser) -> Result<models::User, String> {
@@ -1,5 +1,21 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    client_assertions (client_id, jti) {
+        client_id -> Text,
+        jti -> Text,
+        expiration -> Timestamp,
+    }
+}
+
+diesel::table! {
+    granted_claims (client_id, claim, user_id) {
+        client_id -> Text,
+        claim -> Text,
+        user_id -> Text,
+    }
+}
+
 diesel::table! {
     granted_scopes (client_id, scope, user_id) {
         client_id -> Text,
@@ -15,6 +31,23 @@ diesel::table! {
         name -> Text,
         callback_url -> Text,
         allowed_scopes -> Text,
+        require_pkce -> Bool,
+        client_type -> Text,
+        token_endpoint_auth_method -> Text,
+        jwks -> Nullable<Text>,
+        response_types -> Text,
+        authorization_signed_response_alg -> Nullable<Text>,
+        require_signed_request_object -> Bool,
+        default_max_age -> Nullable<BigInt>,
+        userinfo_signed_response_alg -> Nullable<Text>,
+        userinfo_encrypted_response_alg -> Nullable<Text>,
+        userinfo_encrypted_response_enc -> Nullable<Text>,
+        id_token_encrypted_response_alg -> Nullable<Text>,
+        id_token_encrypted_response_enc -> Nullable<Text>,
+        id_token_signed_response_alg -> Nullable<Text>,
+        subject_type -> Text,
+        sector_identifier_uri -> Nullable<Text>,
+        request_uris -> Text,
     }
 }
 
@@ -27,6 +60,14 @@ diesel::table! {
         subject -> Text,
         expiration -> Timestamp,
         auth_time -> Nullable<Timestamp>,
+        code_challenge -> Nullable<Text>,
+        code_challenge_method -> Nullable<Text>,
+        acr -> Nullable<Text>,
+        amr -> Nullable<Text>,
+        claims -> Nullable<Text>,
+        sid -> Nullable<Text>,
+        consumed -> Bool,
+        family -> Nullable<Text>,
     }
 }
 
@@ -39,6 +80,19 @@ diesel::table! {
         subject -> Nullable<Text>,
         expiration -> Nullable<BigInt>,
         created -> Timestamp,
+        family -> Nullable<Text>,
+        parent -> Nullable<Text>,
+        revoked -> Bool,
+        claims -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    pushed_requests (request_uri) {
+        request_uri -> Text,
+        client_id -> Text,
+        params -> Text,
+        expiration -> Timestamp,
     }
 }
 
@@ -54,10 +108,34 @@ diesel::table! {
         address -> Nullable<Text>,
         birthdate -> Nullable<Text>,
         locale -> Nullable<Text>,
+        attributes -> Nullable<Text>,
+        email_verified -> Bool,
+        phone_number_verified -> Bool,
+        middle_name -> Nullable<Text>,
+        nickname -> Nullable<Text>,
+        profile -> Nullable<Text>,
+        picture -> Nullable<Text>,
+        website -> Nullable<Text>,
+        gender -> Nullable<Text>,
+        zoneinfo -> Nullable<Text>,
+        updated_at -> Nullable<Timestamp>,
+        preferred_username -> Nullable<Text>,
     }
 }
 
+diesel::joinable!(granted_claims -> oauth_clients (client_id));
 diesel::joinable!(granted_scopes -> oauth_clients (client_id));
 diesel::joinable!(oauth_tokens -> oauth_clients (client_id));
+diesel::joinable!(client_assertions -> oauth_clients (client_id));
+diesel::joinable!(pushed_requests -> oauth_clients (client_id));
 
-diesel::allow_tables_to_appear_in_same_query!(granted_scopes, oauth_clients, oauth_sessions, oauth_tokens, users,);
+diesel::allow_tables_to_appear_in_same_query!(
+    client_assertions,
+    granted_claims,
+    granted_scopes,
+    oauth_clients,
+    oauth_sessions,
+    oauth_tokens,
+    pushed_requests,
+    users,
+);
@@ -4,6 +4,10 @@ use super::core::models::OauthSession;
 use super::core::AppState;
 use crate::core::cookies::{fill_cookie_jar, set_cookies_from_jar, AuthSessionCookie, SSOCookie};
 use crate::core::secrets::verify_password;
+use crate::oidc::acr::{self, AcrRequest};
+use crate::oidc::authorize::{self, AuthResponse};
+use crate::oidc::claims;
+use crate::oidc::token;
 use actix_web::cookie::Cookie;
 use actix_web::http::header::CONTENT_LOCATION;
 use actix_web::http::StatusCode;
@@ -13,8 +17,7 @@ use chrono::DateTime;
 use chrono::{offset::Utc, Duration};
 use rand::distr::Alphanumeric;
 use rand::RngExt;
-use std::collections::{HashMap, HashSet};
-use url::Url;
+use std::collections::HashSet;
 /* ---------------------------------------------------------------------------------------*/
 
 #[derive(Deserialize, Debug)]
@@ -27,6 +30,8 @@ pub struct LoginReq {
 pub struct GrantScopesResponse {
     pub op: String,
     pub scopes: Vec<String>,
+    /// the individually requested claims that the scopes do not release
+    pub claims: Vec<String>,
 }
 
 pub async fn login((form, state, req): (Json<LoginReq>, Data<AppState>, HttpRequest)) -> Result<HttpResponse> {
@@ -38,7 +43,7 @@ pub async fn login((form, state, req): (Json<LoginReq>, Data<AppState>, HttpRequ
         .get(auth_session_cookie_name)
         .ok_or_else(|| AppError::bad_auth_session("Auth Session not found or invalid"))?;
     let auth_ses: AuthSessionCookie =
-        serde_json::from_str(&json_auth_ses.value()).map_err(|_| AppError::bad_auth_session("failed to parse auth-session"))?;
+        serde_json::from_str(json_auth_ses.value()).map_err(|_| AppError::bad_auth_session("failed to parse auth-session"))?;
 
     cookie_jar.remove(Cookie::build(auth_session_cookie_name.to_owned(), "").path("/").finish());
 
@@ -54,34 +59,61 @@ pub async fn login((form, state, req): (Json<LoginReq>, Data<AppState>, HttpRequ
 
     let requested_scopes: HashSet<&str> = auth_ses.scopes.split_whitespace().collect();
 
-    let granted_scopes: HashSet<String> = state.user_db.fetch_granted_scopes(&auth_ses.client_id, &user.id)?;
+    // prompt=consent asks again for all the scopes
+    let force_consent = auth_ses.prompt.as_deref().is_some_and(|p| p.split_whitespace().any(|v| v == "consent"));
+    let granted_scopes: HashSet<String> = if force_consent {
+        HashSet::new()
+    } else {
+        state.user_db.fetch_granted_scopes(&auth_ses.client_id, &user.id)?
+    };
     let mut new_scopes = Vec::new();
-    for scope in requested_scopes {
-        if !granted_scopes.contains(scope) {
-            new_scopes.push(scope.to_owned());
+    for scope in &requested_scopes {
+        if !granted_scopes.contains(*scope) {
+            new_scopes.push(scope.to_string());
         }
     }
 
-    if new_scopes.is_empty() {
-        let sso = SSOCookie {
-            client_id: auth_ses.client_id.clone(),
-            subject: user.id.clone(),
-            auth_time: Utc::now().naive_utc().and_utc().timestamp(),
-        };
+    // the claims requested individually are shown with the scopes on the consent screen
+    let new_claims = match auth_ses.claims.as_deref() {
+        Some(claims) => {
+            let client = state
+                .oauth_db
+                .fetch_client_config(&auth_ses.client_id)
+                .map_err(|_| InternalError::query_fail("failed to load the client config").to_user())?;
+            let granted_claims: HashSet<String> = if force_consent {
+                HashSet::new()
+            } else {
+                state.user_db.fetch_granted_claims(&auth_ses.client_id, &user.id)?
+            };
+            let scopes = requested_scopes.iter().copied().chain(granted_scopes.iter().map(String::as_str));
+            claims::claims_to_consent(&state.config.oauth.claims, &client.allowed_scopes, Some(claims), scopes, &granted_claims)
+        }
+        None => Vec::new(),
+    };
+
+    let sso = SSOCookie {
+        client_id: auth_ses.client_id.clone(),
+        subject: user.id.clone(),
+        auth_time: Utc::now().naive_utc().and_utc().timestamp(),
+        amr: vec!["pwd".into()], // the password is the only login method for now
+        sid: Some(uuid::Uuid::new_v4().to_string()),
+    };
 
+    if new_scopes.is_empty() && new_claims.is_empty() {
         let json_sso = serde_json::to_string(&sso)?;
         cookie_jar
             .private_mut(&state.cookie_jar_key)
             .add(Cookie::build("sso", json_sso).path("/").secure(true).http_only(true).finish());
 
-        let callback_url = generate_callback(&state, &auth_ses, &sso)?;
-        let mut resp = HttpResponse::Found().append_header((CONTENT_LOCATION, callback_url)).finish();
+        let mut resp = generate_callback(&state, &auth_ses, &sso)?.send(&state, CONTENT_LOCATION)?;
         set_cookies_from_jar(&cookie_jar, &mut resp);
 
         Ok(resp)
     } else {
+        // the login is kept for the consent step, its auth_time and amr end up in the id_token
         let auth_ses_with_subject = AuthSessionCookie {
             subject: Some(user.id.clone()),
+            login: Some(sso),
             ..auth_ses
         };
         let json_auth_ses = serde_json::to_string(&auth_ses_with_subject)?;
@@ -94,6 +126,7 @@ pub async fn login((form, state, req): (Json<LoginReq>, Data<AppState>, HttpRequ
             GrantScopesResponse {
                 op: "GRANT".into(),
                 scopes: new_scopes,
+                claims: new_claims,
             },
         )?;
         set_cookies_from_jar(&cookie_jar, &mut resp);
@@ -101,13 +134,31 @@ pub async fn login((form, state, req): (Json<LoginReq>, Data<AppState>, HttpRequ
     }
 }
 
-pub fn generate_callback(state: &AppState, auth_ses: &AuthSessionCookie, sso: &SSOCookie) -> Result<String, InternalError> {
+/// builds the success response of the authorization endpoint for the requested `response_type`
+///
+/// by default only the code is returned in the query, tokens are returned in the fragment
+/// (https://openid.net/specs/oauth-v2-multiple-response-types-1_0.html)
+pub fn generate_callback(state: &AppState, auth_ses: &AuthSessionCookie, sso: &SSOCookie) -> Result<AuthResponse, AppError> {
     debug!("generating success callback_uri");
 
     let client = state
         .oauth_db
         .fetch_client_config(auth_ses.client_id.as_ref())
-        .map_err(|_| InternalError::query_fail("failed to load the client config "))?;
+        .map_err(|_| InternalError::query_fail("failed to load the client config ").to_user())?;
+
+    // https://openid.net/specs/openid-connect-core-1_0.html#acrSemantics
+    let acr_classes = &state.config.oauth.acr;
+    let acr_request = AcrRequest::parse(auth_ses.acr_values.as_deref(), auth_ses.claims.as_deref()).unwrap_or_default();
+    if acr_request.as_ref().is_some_and(|r| r.is_unmet(acr_classes, &sso.amr)) {
+        return Ok(generate_callback_err(
+            auth_ses,
+            "unmet_authentication_requirements",
+            "the requested authentication context class was not reached",
+        ));
+    }
+    let acr = acr::select(acr_classes, &sso.amr, acr_request.as_ref());
+
+    let response_type: HashSet<&str> = auth_ses.response_type.split_whitespace().collect();
 
     let auth_code: String = rand::rng().sample_iter(&Alphanumeric).take(10).map(char::from).collect::<String>();
 
@@ -117,25 +168,66 @@ pub fn generate_callback(state: &AppState, auth_ses: &AuthSessionCookie, sso: &S
         .unwrap();
     let auth_time = DateTime::from_timestamp(sso.auth_time, 0).unwrap().naive_utc();
 
-    // save the code into db
-    state.oauth_db.save_oauth_session(OauthSession {
-        auth_code: auth_code.clone(),
-        client_id: client.id,
+    let session = OauthSession {
+        auth_code,
+        client_id: client.id.clone(),
         scopes: auth_ses.scopes.to_string(),
         nonce: auth_ses.nonce.clone(),
         subject: sso.subject.clone(),
         expiration: auth_code_exp,
         auth_time: Some(auth_time),
-    })?;
+        code_challenge: auth_ses.code_challenge.clone(),
+        code_challenge_method: auth_ses.code_challenge_method.clone(),
+        acr: acr.map(String::from),
+        amr: (!sso.amr.is_empty()).then(|| sso.amr.join(" ")),
+
+        claims: auth_ses.claims.clone(),
+        sid: sso.sid.clone(),
+        consumed: false,
+        family: Some(uuid::Uuid::new_v4().to_string()),
+    };
+
+    let mut callback = AuthResponse::new(&auth_ses.redirect_uri, &auth_ses.client_id, response_mode(auth_ses));
 
-    // add the code to the callback URL and return it
-    let mut params: HashMap<&str, &str> = HashMap::new();
-    params.insert("code", &auth_code);
-    if auth_ses.state.is_some() {
-        params.insert("state", auth_ses.state.as_ref().unwrap());
+    // save the code into db
+    let code = if response_type.contains("code") {
+        state.oauth_db.save_oauth_session(session.clone()).map_err(|e| e.to_user())?;
+        callback.add("code", &session.auth_code);
+        Some(session.auth_code.as_str())
+    } else {
+        None
+    };
+
+    // no refresh token for tokens returned by the authorization endpoint,
+    // the access token of a hybrid response joins the family of the code, to be revoked on a code replay
+    let access_token = if response_type.contains("token") {
+        let family = code.and(session.family.as_deref());
+        let token = token::issue_access_token(
+            state,
+            &client,
+            Some(&session.subject),
+            &session.scopes,
+            session.claims.as_deref(),
+            family,
+            None,
+        )?;
+        callback.add("access_token", &token);
+        callback.add("token_type", "Bearer");
+        callback.add("expires_in", &state.config.oauth.token_exp.to_string());
+        Some(token)
+    } else {
+        None
+    };
+
+    if response_type.contains("id_token") {
+        let id_token = token::build_id_token(state, &client, &session, access_token.as_deref(), code)?;
+        callback.add("id_token", &id_token);
+    }
+
+    if let Some(s) = auth_ses.state.as_ref() {
+        callback.add("state", s);
     }
-    let callback_url = Url::parse_with_params(&auth_ses.redirect_uri, params).unwrap();
-    Ok(callback_url.to_string())
+    Ok(callback)
 }
 
 pub async fn consent((scopes, state, req): (Json<Vec<String>>, Data<AppState>, HttpRequest)) -> Result<HttpResponse> {
@@ -148,7 +240,7 @@ pub async fn consent((scopes, state, req): (Json<Vec<String>>, Data<AppState>, H
         .get(auth_session_cookie_name)
         .ok_or_else(|| AppError::bad_auth_session("Auth Session not found or invalid"))?;
     let auth_ses: AuthSessionCookie =
-        serde_json::from_str(&json_auth_ses.value()).map_err(|_| AppError::bad_auth_session("failed to parse auth-session"))?;
+        serde_json::from_str(json_auth_ses.value()).map_err(|_| AppError::bad_auth_session("failed to parse auth-session"))?;
 
     let uid = auth_ses
         .subject
@@ -160,19 +252,35 @@ pub async fn consent((scopes, state, req): (Json<Vec<String>>, Data<AppState>, H
     if !scopes.is_empty() {
         state.user_db.save_granted_scopes(&uid, &auth_ses.client_id, &scopes)?;
     }
+    // the individually requested claims were shown with the scopes, they are consented together
+    if let Some(requested) = auth_ses.claims.as_deref() {
+        let client = state
+            .oauth_db
+            .fetch_client_config(&auth_ses.client_id)
+            .map_err(|_| InternalError::query_fail("failed to load the client config").to_user())?;
+        let claims_cfg = &state.config.oauth.claims;
+        let consented = claims::claims_to_consent(claims_cfg, &client.allowed_scopes, Some(requested), [], &HashSet::new());
+        if !consented.is_empty() {
+            state.user_db.save_granted_claims(&uid, &auth_ses.client_id, &consented)?;
+        }
+    }
 
-    let sso = SSOCookie {
-        client_id: auth_ses.client_id.clone(),
-        subject: uid,
-        auth_time: Utc::now().naive_utc().and_utc().timestamp(),
+    // the fresh login, or for a user who only consented (SSO session) the authentication of the SSO session
+    let sso = match auth_ses.login.clone() {
+        Some(login) => login,
+        None => cookie_jar
+            .private_mut(&state.cookie_jar_key)
+            .get("sso")
+            .and_then(|c| serde_json::from_str::<SSOCookie>(c.value()).ok())
+            .filter(|sso| sso.subject == uid)
+            .ok_or_else(|| AppError::bad_auth_session("the SSO session is no longer valid"))?,
     };
     let json_sso = serde_json::to_string(&sso)?;
     cookie_jar
         .private_mut(&state.cookie_jar_key)
         .add(Cookie::build("sso", json_sso).path("/").secure(true).http_only(true).finish());
 
-    let callback_url = generate_callback(&state, &auth_ses, &sso)?;
-    let mut resp = HttpResponse::Found().append_header((CONTENT_LOCATION, callback_url)).finish();
+    let mut resp = generate_callback(&state, &auth_ses, &sso)?.send(&state, CONTENT_LOCATION)?;
     set_cookies_from_jar(&cookie_jar, &mut resp);
     Ok(resp)
 }
@@ -188,25 +296,27 @@ pub async fn cancel_login((state, req): (Data<AppState>, HttpRequest)) -> Result
         .get(auth_session_cookie_name)
         .ok_or_else(|| AppError::bad_auth_session("Auth Session not found or invalid"))?;
     let auth_ses: AuthSessionCookie =
-        serde_json::from_str(&json_auth_ses.value()).map_err(|_| AppError::bad_auth_session("failed to parse auth-session"))?;
+        serde_json::from_str(json_auth_ses.value()).map_err(|_| AppError::bad_auth_session("failed to parse auth-session"))?;
 
     cookie_jar.remove(Cookie::build(auth_session_cookie_name.to_owned(), "").path("/").finish());
 
-    let callback_url = generate_callback_err(&auth_ses.redirect_uri, "access_denied", "User denied access", auth_ses.state.as_deref())?;
-    let mut resp = HttpResponse::Found().append_header((CONTENT_LOCATION, callback_url)).finish();
+    let mut resp = generate_callback_err(&auth_ses, "access_denied", "User denied access").send(&state, CONTENT_LOCATION)?;
     set_cookies_from_jar(&cookie_jar, &mut resp);
     Ok(resp)
 }
 
-fn generate_callback_err(redirect_uri: &str, error: &str, description: &str, state: Option<&str>) -> Result<String, AppError> {
+fn generate_callback_err(auth_ses: &AuthSessionCookie, error: &str, description: &str) -> AuthResponse {
     debug!("generating error callback_uri: [{}] {}", error, description);
 
-    let mut params: HashMap<&str, &str> = HashMap::new();
-    params.insert("error", error);
-    params.insert("error_description", description);
-    if let Some(s) = state {
-        params.insert("state", s);
+    let mut callback = AuthResponse::new(&auth_ses.redirect_uri, &auth_ses.client_id, response_mode(auth_ses));
+    callback.add("error", error);
+    callback.add("error_description", description);
+    if let Some(s) = auth_ses.state.as_deref() {
+        callback.add("state", s);
     }
-    let callback_url = Url::parse_with_params(redirect_uri, params).unwrap();
-    Ok(callback_url.to_string())
+    callback
+}
+
+fn response_mode(auth_ses: &AuthSessionCookie) -> &'static str {
+    authorize::response_mode(&auth_ses.response_type, auth_ses.response_mode.as_deref())
 }
@@ -60,6 +60,8 @@ async fn main() -> std::io::Result<()> {
                     .route("/oauth2/authorize", web::post().to(oidc::authorize::auth_post))
                     .route("/oauth2/token", web::post().to(oidc::token::token_endpoint))
                     .route("/oauth2/token_info", web::post().to(oidc::introspection::introspect))
+                    .route("/oauth2/revoke", web::post().to(oidc::revocation::revoke))
+                    .route("/oauth2/par", web::post().to(oidc::par::push_request))
                     .route("/oauth2/user_info", web::get().to(oidc::userinfo::userinfo_endpoint))
                     .route("/oauth2/user_info", web::post().to(oidc::userinfo::userinfo_endpoint))
                     // identity provider (should be customizable)
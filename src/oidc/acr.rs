@@ -0,0 +1,101 @@
+use super::claims::ClaimsRequest;
+use super::OauthError;
+use crate::core::config::AcrConfig;
+use serde_json::Value;
+
+/// the `acr` requested by the client, https://openid.net/specs/openid-connect-core-1_0.html#acrSemantics
+///
+/// `acr_values` is a voluntary request, `claims.id_token.acr` is essential if marked so
+#[derive(Debug, Default)]
+pub(crate) struct AcrRequest {
+    /// in the order of preference
+    pub values: Vec<String>,
+    pub essential: bool,
+}
+
+impl AcrRequest {
+    /// the `acr` requested with `claims` (json) takes precedence over `acr_values`
+    pub(crate) fn parse(acr_values: Option<&str>, claims: Option<&str>) -> Result<Option<AcrRequest>, OauthError> {
+        // https://openid.net/specs/openid-connect-core-1_0.html#IndividualClaimsRequests
+        if let Some(acr) = ClaimsRequest::parse(claims)?.id_token.remove("acr") {
+            let acr = acr.unwrap_or_default();
+            let values = match (acr.value, acr.values) {
+                (Some(Value::String(v)), _) => vec![v],
+                (_, Some(vs)) => vs.iter().filter_map(Value::as_str).map(String::from).collect(),
+                _ => Vec::new(),
+            };
+            return Ok(Some(AcrRequest {
+                values,
+                essential: acr.essential,
+            }));
+        }
+
+        Ok(acr_values.map(|v| AcrRequest {
+            values: v.split_whitespace().map(String::from).collect(),
+            essential: false,
+        }))
+    }
+
+    /// an essential `acr` that the authentication methods do not reach
+    pub(crate) fn is_unmet(&self, classes: &[AcrConfig], amr: &[String]) -> bool {
+        self.essential && !self.values.is_empty() && !self.values.iter().any(|v| find_reached(classes, amr, v).is_some())
+    }
+}
+
+/// the `acr` of an authentication: the first requested class reached with the methods used at login,
+/// or else the strongest class reached (the classes are configured from the weakest to the strongest)
+pub(crate) fn select<'a>(classes: &'a [AcrConfig], amr: &[String], request: Option<&AcrRequest>) -> Option<&'a str> {
+    request
+        .into_iter()
+        .flat_map(|r| r.values.iter())
+        .find_map(|v| find_reached(classes, amr, v))
+        .or_else(|| classes.iter().rev().find(|c| is_reached(c, amr)))
+        .map(|c| c.value.as_str())
+}
+
+fn find_reached<'a>(classes: &'a [AcrConfig], amr: &[String], value: &str) -> Option<&'a AcrConfig> {
+    classes.iter().find(|c| c.value == value && is_reached(c, amr))
+}
+
+fn is_reached(class: &AcrConfig, amr: &[String]) -> bool {
+    class.amr.iter().all(|m| amr.contains(m))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn classes() -> Vec<AcrConfig> {
+        vec![
+            AcrConfig {
+                value: "pwd".into(),
+                amr: vec!["pwd".into()],
+            },
+            AcrConfig {
+                value: "mfa".into(),
+                amr: vec!["pwd".into(), "otp".into()],
+            },
+        ]
+    }
+
+    #[test]
+    fn test_select() {
+        let pwd = vec!["pwd".to_string()];
+        let mfa = vec!["pwd".to_string(), "otp".to_string()];
+        let voluntary = AcrRequest::parse(Some("mfa pwd"), None).unwrap().unwrap();
+
+        assert_eq!(select(&classes(), &pwd, None), Some("pwd"));
+        assert_eq!(select(&classes(), &mfa, None), Some("mfa"));
+        assert_eq!(select(&classes(), &pwd, Some(&voluntary)), Some("pwd"));
+        assert_eq!(select(&classes(), &[], Some(&voluntary)), None);
+        assert!(!voluntary.is_unmet(&classes(), &pwd));
+
+        let essential = AcrRequest::parse(Some("pwd"), Some(r#"{"id_token":{"acr":{"essential":true,"value":"mfa"}}}"#))
+            .unwrap()
+            .unwrap();
+        assert!(essential.is_unmet(&classes(), &pwd));
+        assert!(!essential.is_unmet(&classes(), &mfa));
+
+        assert!(AcrRequest::parse(None, Some("not json")).is_err());
+    }
+}
@@ -1,31 +1,35 @@
-use super::OauthError;
+use super::acr::AcrRequest;
+use super::claims::{self, ClaimsRequest};
+use super::{jwe, pkce, request_object, signing, subject, OauthError};
 use crate::core::cookies::set_cookies_from_jar;
+use crate::core::models::OauthClient;
 use crate::core::{
     cookies::{fill_cookie_jar, AuthSessionCookie, SSOCookie},
     error::AppError,
-    AppState,
+    web_util, AppState,
 };
 use actix_web::cookie::time::Duration;
 use actix_web::cookie::{Cookie, CookieJar};
-use actix_web::http::header::LOCATION;
+use actix_web::http::header::{HeaderName, HeaderValue, CACHE_CONTROL, LOCATION};
 use actix_web::http::StatusCode;
 use actix_web::web::{Data, Form, Query};
 use actix_web::{Error, HttpRequest};
 use actix_web::{HttpResponse, Responder, Result};
+use chrono::Utc;
 use std::collections::HashSet;
 
 /// GET /authorize
 pub async fn auth_get((data, state, req): (Query<AuthParams>, Data<AppState>, HttpRequest)) -> impl Responder {
-    handle_auth(&data, &state, req)
+    handle_auth(&data, &state, req).await
 }
 
 /// POST /authorize
 pub async fn auth_post((data, state, req): (Form<AuthParams>, Data<AppState>, HttpRequest)) -> impl Responder {
-    handle_auth(&data, &state, req)
+    handle_auth(&data, &state, req).await
 }
 
 // @see https://openid.net/specs/openid-connect-core-1_0.html#AuthRequest
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AuthParams {
     pub scope: Option<String>,
     pub response_type: Option<String>,
@@ -41,30 +45,86 @@ pub struct AuthParams {
     pub id_token_hint: Option<String>,
     pub login_hint: Option<String>,
     pub acr_values: Option<String>,
+    pub claims: Option<String>,
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: Option<String>,
+    pub request: Option<String>,
+    pub request_uri: Option<String>,
 }
 
 // common ground
-fn handle_auth(data: &AuthParams, state: &Data<AppState>, req: HttpRequest) -> Result<HttpResponse> {
+async fn handle_auth(data: &AuthParams, state: &Data<AppState>, req: HttpRequest) -> Result<HttpResponse> {
     info!("auth({:?})", data);
 
-    match validate_auth(data, state)? {
-        Some(e) => {
+    let data = &match request_object::resolve(data, state).await {
+        Ok(resolved) => resolved,
+        Err(e) if is_registered_redirect(data, state) => return callback_error(data, e).send(state, LOCATION),
+        Err(e) => return Err(AppError::bad_req(e.error_description.unwrap_or(e.error)).into()),
+    };
+    // pushed requests keep their request_uri
+    if state.config.oauth.require_pushed_authorization_requests && data.request_uri.is_none() {
+        return Err(AppError::bad_req("the authorization request must be pushed to /oauth2/par first").into());
+    }
+
+    match validate_auth(data, state).await? {
+        Err(e) => {
             info!("Validation ERROR {:?}", &e);
-            Ok(HttpResponse::Found().append_header((LOCATION, callback_error(data, e)?)).finish())
+            callback_error(data, e).send(state, LOCATION)
         }
-        None => {
-            if let Some(callback_url) = try_sso(data, state, req)? {
-                return Ok(HttpResponse::Found().append_header((LOCATION, callback_url)).finish());
+        Ok(client) => {
+            // https://openid.net/specs/openid-connect-core-1_0.html#AuthRequest (prompt)
+            let prompt: HashSet<&str> = data.prompt.as_deref().unwrap_or_default().split_whitespace().collect();
+            let interaction_forced = prompt.contains("login") || prompt.contains("select_account");
+            if !interaction_forced {
+                // prompt=consent keeps the authentication, only the consent step is shown
+                match try_sso(data, &client, state, req, prompt.contains("consent"))? {
+                    Sso::Reused(callback) => return callback.send(state, LOCATION),
+                    sso if prompt.contains("none") => return callback_error(data, sso.error()).send(state, LOCATION),
+                    Sso::ConsentRequired { subject, scopes, claims } => {
+                        let mut model = tera::Context::new();
+                        model.insert("step", &2);
+                        model.insert("scopes", &scopes);
+                        model.insert("claims", &claims);
+                        let mut resp = state.send_page(StatusCode::OK, "login.html", model)?;
+                        create_auth_session(state, data, Some(subject), &mut resp)?;
+                        return Ok(resp);
+                    }
+                    _ => {}
+                }
             }
 
             let mut resp = state.send_page(StatusCode::OK, "login.html", tera::Context::new())?;
-            create_auth_session(&state, data, &mut resp)?;
+            create_auth_session(state, data, None, &mut resp)?;
             Ok(resp)
         }
     }
 }
 
-fn try_sso(data: &AuthParams, state: &Data<AppState>, req: HttpRequest) -> Result<Option<String>, AppError> {
+/// the outcome of reusing the SSO session
+enum Sso {
+    Reused(AuthResponse),
+    LoginRequired,
+    /// the user is authenticated, but has to consent to the scopes and claims
+    ConsentRequired {
+        subject: String,
+        scopes: Vec<String>,
+        claims: Vec<String>,
+    },
+    InteractionRequired,
+}
+
+impl Sso {
+    /// the error returned for `prompt=none` if the SSO session can not be reused
+    fn error(&self) -> OauthError {
+        match self {
+            Sso::LoginRequired => OauthError::new("login_required", "the user is not logged in"),
+            Sso::ConsentRequired { .. } => OauthError::new("consent_required", "the requested scopes were not granted"),
+            _ => OauthError::new("interaction_required", "the user must interact with the provider"),
+        }
+    }
+}
+
+fn try_sso(data: &AuthParams, client: &OauthClient, state: &Data<AppState>, req: HttpRequest, force_consent: bool) -> Result<Sso, AppError> {
     let mut cookie_jar = fill_cookie_jar(req);
     let sso_cookie = cookie_jar.private_mut(&state.cookie_jar_key).get("sso");
 
@@ -75,41 +135,94 @@ fn try_sso(data: &AuthParams, state: &Data<AppState>, req: HttpRequest) -> Resul
 
     let sso = match sso {
         Some(s) => s,
-        None => return Ok(None),
+        None => return Ok(Sso::LoginRequired),
     };
 
+    // the authentication must not be older than max_age (already validated)
+    let max_age = data.max_age.as_deref().and_then(|m| m.parse::<i64>().ok()).or(client.default_max_age);
+    if let Some(max_age) = max_age {
+        let age = Utc::now().timestamp() - sso.auth_time;
+        if age >= max_age {
+            info!("SSO: authentication of {} is too old ({}s >= max_age {}s)", sso.subject, age, max_age);
+            return Ok(Sso::LoginRequired);
+        }
+    }
+
+    // an essential acr needs a (stronger) login (acr request already validated)
+    let acr_request = AcrRequest::parse(data.acr_values.as_deref(), data.claims.as_deref()).ok().flatten();
+    if acr_request.is_some_and(|r| r.is_unmet(&state.config.oauth.acr, &sso.amr)) {
+        info!("SSO: the authentication of {} does not reach the essential acr", sso.subject);
+        return Ok(Sso::LoginRequired);
+    }
+
     let client_id = data.client_id.as_ref().unwrap(); // already validated
     let scopes_str = match data.scope.as_ref() {
         Some(s) => s,
-        None => return Ok(None),
+        None => return Ok(Sso::InteractionRequired),
     };
 
+    // prompt=consent asks again for all the scopes
     let requested_scopes: HashSet<&str> = scopes_str.split_whitespace().collect();
-    let granted_scopes: HashSet<String> = state.user_db.fetch_granted_scopes(client_id, &sso.subject).map_err(|e| e.to_user())?;
-
-    let all_granted = requested_scopes.iter().all(|s| granted_scopes.contains(*s));
-    if !all_granted {
-        return Ok(None);
+    let granted_scopes: HashSet<String> = if force_consent {
+        HashSet::new()
+    } else {
+        state.user_db.fetch_granted_scopes(client_id, &sso.subject).map_err(|e| e.to_user())?
+    };
+    let new_scopes: Vec<String> = requested_scopes
+        .iter()
+        .filter(|s| !granted_scopes.contains(**s))
+        .map(|s| s.to_string())
+        .collect();
+    // individually requested claims beyond the scopes, that were not consented before
+    let granted_claims: HashSet<String> = if force_consent || data.claims.is_none() {
+        HashSet::new()
+    } else {
+        state.user_db.fetch_granted_claims(client_id, &sso.subject).map_err(|e| e.to_user())?
+    };
+    let scopes = requested_scopes.iter().copied().chain(granted_scopes.iter().map(String::as_str));
+    let new_claims = claims::claims_to_consent(
+        &state.config.oauth.claims,
+        &client.allowed_scopes,
+        data.claims.as_deref(),
+        scopes,
+        &granted_claims,
+    );
+    if !new_scopes.is_empty() || !new_claims.is_empty() {
+        return Ok(Sso::ConsentRequired {
+            subject: sso.subject,
+            scopes: new_scopes,
+            claims: new_claims,
+        });
     }
 
     let auth_ses = AuthSessionCookie {
         client_id: client_id.clone(),
+        response_mode: data.response_mode.clone(),
+        response_type: data.response_type.clone().unwrap(),
         scopes: scopes_str.clone(),
         redirect_uri: data.redirect_uri.clone().unwrap(),
         nonce: data.nonce.clone(),
         state: data.state.clone(),
+        prompt: data.prompt.clone(),
+        acr_values: data.acr_values.clone(),
+        claims: data.claims.clone(),
         subject: None,
+        code_challenge: data.code_challenge.clone(),
+        code_challenge_method: data.code_challenge_method.clone(),
+        login: None,
     };
 
-    let callback_url = crate::idp::generate_callback(state, &auth_ses, &sso).map_err(|e| e.to_user())?;
+    let callback = crate::idp::generate_callback(state, &auth_ses, &sso)?;
 
     info!("SSO: reusing session for subject={}", sso.subject);
-    Ok(Some(callback_url))
+    Ok(Sso::Reused(callback))
 }
 
 /// validates, extracts the info & puts it on the session
 /// https://openid.net/specs/openid-connect-core-1_0.html#AuthError
-fn validate_auth(data: &AuthParams, state: &AppState) -> Result<Option<OauthError>, AppError> {
+///
+/// returns the client config, or the error to send back to the client
+pub(crate) async fn validate_auth(data: &AuthParams, state: &AppState) -> Result<Result<OauthClient, OauthError>, AppError> {
     if data.redirect_uri.is_none() {
         return Err(AppError::bad_req("'redirect_uri' is required"));
     }
@@ -118,21 +231,8 @@ fn validate_auth(data: &AuthParams, state: &AppState) -> Result<Option<OauthErro
         return Err(AppError::bad_req("'redirect_uri' is required"));
     }
 
-    if data.response_type.is_none() {
-        return Ok(Some(OauthError::new("invalid_request", "response_type is required.")));
-    }
-    let response_type = data.response_type.as_ref().unwrap();
-    if !contains(&RESPONSE_TYPES, response_type.as_ref()) {
-        //if !RESPONSE_TYPES.iter().any(|x| x == &data.response_type) {
-        return Ok(Some(OauthError::new("invalid_request", "invalid 'response_type'")));
-    }
-    if response_type != "code" {
-        return Ok(Some(OauthError::of("unsupported_response_type"))); // TODO
-    }
-    // TODO is responsy_tpe allowed for client?
-
     if data.client_id.is_none() {
-        return Ok(Some(OauthError::new("invalid_request", "'client_id' is required")));
+        return Ok(Err(OauthError::new("invalid_request", "'client_id' is required")));
     }
     let client_id = data.client_id.as_ref().unwrap();
     let client = state
@@ -144,38 +244,163 @@ fn validate_auth(data: &AuthParams, state: &AppState) -> Result<Option<OauthErro
         return Err(AppError::bad_req("'redirect_uri' is invalid"));
     }
 
-    if data.scope.is_some() {
-        let scope_param = data.scope.as_ref().unwrap();
+    let response_type = match data.response_type.as_deref().map(normalize_response_type) {
+        None => return Ok(Err(OauthError::new("invalid_request", "response_type is required."))),
+        Some(None) => return Ok(Err(OauthError::new("invalid_request", "invalid 'response_type'"))),
+        Some(Some(rt)) => rt,
+    };
+
+    // an id_token is only issued for OpenID Connect requests
+    let is_oidc = data.scope.as_deref().is_some_and(|s| s.split_whitespace().any(|s| s == "openid"));
+    if response_type.split_whitespace().any(|rt| rt == "id_token") && !is_oidc {
+        return Ok(Err(OauthError::new("invalid_scope", "the 'openid' scope is required for an id_token")));
+    }
+
+    // https://openid.net/specs/openid-connect-core-1_0.html#ImplicitAuthRequest
+    if response_type != "code" && response_type != "none" && response_type != "token" && data.nonce.is_none() {
+        return Ok(Err(OauthError::new("invalid_request", "'nonce' is required")));
+    }
+
+    // https://openid.net/specs/oauth-v2-multiple-response-types-1_0.html#Combinations
+    match data.response_mode.as_deref() {
+        Some(mode) if !contains(&RESPONSE_MODES, mode) => {
+            return Ok(Err(OauthError::new("invalid_request", "invalid 'response_mode'")));
+        }
+        Some("query" | "query.jwt") if response_mode(response_type, None) != "query" => {
+            return Ok(Err(OauthError::new("invalid_request", "tokens must not be returned in the query")));
+        }
+        _ => {}
+    }
+
+    if client.require_signed_request_object && data.request.is_none() {
+        return Ok(Err(OauthError::new("invalid_request", "signed request object required")));
+    }
+
+    if !client.response_types.iter().any(|rt| normalize_response_type(rt) == Some(response_type)) {
+        return Ok(Err(OauthError::new("unauthorized_client", "response_type not allowed for the client")));
+    }
+
+    // the registered id_token alg must be one we have a key for
+    let id_token_alg = client.id_token_signing_alg(&state.config.oauth.id_token);
+    if !signing::can_sign(state, id_token_alg, &state.config.oauth.id_token.available_signing) {
+        error!("client {}: no key available to sign id_tokens with {:?}", client.id, id_token_alg);
+        return Ok(Err(OauthError::new("unauthorized_client", "id_token_signed_response_alg not supported")));
+    }
+    // the JARM and userinfo responses are only signed after the login, a client without a usable key must fail before it
+    if let Some(alg) = client.authorization_signed_response_alg {
+        if !signing::can_sign(state, alg, &state.config.oauth.id_token.available_signing) {
+            error!("client {}: no key available to sign authorization responses with {:?}", client.id, alg);
+            return Ok(Err(OauthError::new(
+                "unauthorized_client",
+                "authorization_signed_response_alg not supported",
+            )));
+        }
+    }
+    if let Some(alg) = client.userinfo_signed_response_alg {
+        if !signing::can_sign(state, alg, &state.config.oauth.id_token.available_signing) {
+            error!("client {}: no key available to sign userinfo responses with {:?}", client.id, alg);
+            return Ok(Err(OauthError::new("unauthorized_client", "userinfo_signed_response_alg not supported")));
+        }
+    }
+    // the id_token and userinfo responses are encrypted after the login too, the registered algs and key must be usable before it
+    if let Some(alg) = client.id_token_encrypted_response_alg.as_deref() {
+        if !jwe::can_encrypt(&client, alg, client.id_token_encrypted_response_enc.as_deref()) {
+            error!("client {}: id_tokens can not be encrypted with {}", client.id, alg);
+            return Ok(Err(OauthError::new(
+                "unauthorized_client",
+                "id_token_encrypted_response_alg not supported",
+            )));
+        }
+    }
+    if let Some(alg) = client.userinfo_encrypted_response_alg.as_deref() {
+        if !jwe::can_encrypt(&client, alg, client.userinfo_encrypted_response_enc.as_deref()) {
+            error!("client {}: userinfo responses can not be encrypted with {}", client.id, alg);
+            return Ok(Err(OauthError::new(
+                "unauthorized_client",
+                "userinfo_encrypted_response_alg not supported",
+            )));
+        }
+    }
+    if let Err(e) = subject::validate(&state.config.oauth, &client).await {
+        error!("{}", e);
+        return Ok(Err(OauthError::new("unauthorized_client", "the pairwise subject can not be determined")));
+    }
+
+    if let Some(scope_param) = data.scope.as_ref() {
         let scopes: HashSet<&str> = scope_param.split_whitespace().collect();
 
         // only client configured scopes are allowed
         let client_scopes: HashSet<&str> = client.allowed_scopes.split_whitespace().collect();
         if !(&scopes - &client_scopes).is_empty() {
-            return Ok(Some(OauthError::new("invalid_scope", "scope not allowed")));
+            return Ok(Err(OauthError::new("invalid_scope", "scope not allowed")));
         }
     }
 
-    if data.acr_values.is_some() {
-        return Ok(Some(OauthError::new("invalid_request", "invalid acr_value")));
+    // https://www.rfc-editor.org/rfc/rfc7636#section-4.4.1
+    match data.code_challenge.as_deref() {
+        Some(challenge) => {
+            let method = data.code_challenge_method.as_deref().unwrap_or("plain");
+            if !contains(&pkce::CODE_CHALLENGE_METHODS, method) {
+                return Ok(Err(OauthError::new("invalid_request", "transform algorithm not supported")));
+            }
+            if !pkce::is_valid_format(challenge) {
+                return Ok(Err(OauthError::new("invalid_request", "invalid 'code_challenge'")));
+            }
+        }
+        None if (client.require_pkce || client.is_public()) && response_type.contains("code") => {
+            return Ok(Err(OauthError::new("invalid_request", "code challenge required")));
+        }
+        None => {}
+    }
+
+    if let Some(prompt) = data.prompt.as_deref() {
+        let values: HashSet<&str> = prompt.split_whitespace().collect();
+        if !values.iter().all(|p| contains(&PROMPT_VALUES, *p)) {
+            return Ok(Err(OauthError::new("invalid_request", "invalid 'prompt'")));
+        }
+        if values.contains("none") && values.len() > 1 {
+            return Ok(Err(OauthError::new(
+                "invalid_request",
+                "'prompt=none' must not be combined with other values",
+            )));
+        }
     }
 
-    // TODO! support: "prompt" "display" "ui_locales claims_locales" "auth_time" "max_age" "acr_values"
+    if data.max_age.as_deref().is_some_and(|m| m.parse::<u32>().is_err()) {
+        return Ok(Err(OauthError::new("invalid_request", "invalid 'max_age'")));
+    }
+
+    // https://openid.net/specs/openid-connect-core-1_0.html#ClaimsParameter
+    if let Err(e) = ClaimsRequest::parse(data.claims.as_deref()) {
+        return Ok(Err(e));
+    }
+
+    // TODO! support: "display" "ui_locales claims_locales"
     // TODO validate
 
     debug!("no error found");
-    Ok(None)
+    Ok(Ok(client))
 }
 
-fn create_auth_session<'a>(state: &'a AppState, data: &'a AuthParams, resp: &mut HttpResponse) -> Result<(), Error> {
+/// `subject` is set when the user is already authenticated and only has to consent
+fn create_auth_session<'a>(state: &'a AppState, data: &'a AuthParams, subject: Option<String>, resp: &mut HttpResponse) -> Result<(), Error> {
     debug!("creating auth-session for {:?}", &data.client_id);
 
     let auth_ses = AuthSessionCookie {
         client_id: data.client_id.clone().unwrap(),
+        response_mode: data.response_mode.clone(),
+        response_type: data.response_type.clone().unwrap(),
         scopes: data.scope.clone().unwrap(),
         redirect_uri: data.redirect_uri.clone().unwrap(),
         nonce: data.nonce.clone(),
         state: data.state.clone(),
-        subject: None,
+        prompt: data.prompt.clone(),
+        acr_values: data.acr_values.clone(),
+        claims: data.claims.clone(),
+        subject,
+        code_challenge: data.code_challenge.clone(),
+        code_challenge_method: data.code_challenge_method.clone(),
+        login: None,
     };
 
     let json_auth_ses = serde_json::to_string(&auth_ses)?;
@@ -198,7 +423,7 @@ fn create_auth_session<'a>(state: &'a AppState, data: &'a AuthParams, resp: &mut
     Ok(())
 }
 
-static RESPONSE_TYPES: [&str; 8] = [
+pub static RESPONSE_TYPES: [&str; 8] = [
     "code",
     "token",
     "id_token",
@@ -209,24 +434,132 @@ static RESPONSE_TYPES: [&str; 8] = [
     "none",
 ];
 
+/// the entry of `RESPONSE_TYPES` matching `response_type`, the order of the values is not relevant
+fn normalize_response_type(response_type: &str) -> Option<&'static str> {
+    let requested: HashSet<&str> = response_type.split_whitespace().collect();
+    RESPONSE_TYPES
+        .iter()
+        .find(|rt| rt.split_whitespace().collect::<HashSet<&str>>() == requested)
+        .copied()
+}
+
+/// the requested `response_mode`, or the default one of the `response_type`: `fragment` as soon as a token
+/// is returned from the authorization endpoint (https://openid.net/specs/oauth-v2-multiple-response-types-1_0.html#Combinations)
+///
+/// `jwt` is the JARM variant of the default mode (https://openid.net/specs/oauth-v2-jarm.html#section-2.3.4)
+pub fn response_mode(response_type: &str, requested: Option<&str>) -> &'static str {
+    let returns_token = response_type.split_whitespace().any(|rt| rt == "token" || rt == "id_token");
+    match requested.and_then(|m| RESPONSE_MODES.iter().find(|x| **x == m)) {
+        Some(&"jwt") if returns_token => "fragment.jwt",
+        Some(&"jwt") => "query.jwt",
+        Some(mode) => mode,
+        None if returns_token => "fragment",
+        None => "query",
+    }
+}
+
+pub static PROMPT_VALUES: [&str; 4] = ["none", "login", "consent", "select_account"];
+
+pub static RESPONSE_MODES: [&str; 7] = ["query", "fragment", "form_post", "query.jwt", "fragment.jwt", "form_post.jwt", "jwt"];
+
 pub fn contains<T: PartialEq + AsRef<str>>(col: &[T], item: T) -> bool {
     col.iter().any(|x| &item == x)
 }
 
-fn callback_error(data: &AuthParams, err: OauthError) -> Result<String> {
-    use std::collections::HashMap;
-    use url::Url;
+/// errors are only redirected to a `redirect_uri` registered by the client, never to an unchecked one
+fn is_registered_redirect(data: &AuthParams, state: &AppState) -> bool {
+    match (data.client_id.as_deref(), data.redirect_uri.as_ref()) {
+        (Some(client_id), Some(redirect_uri)) => state
+            .oauth_db
+            .fetch_client_config(client_id)
+            .is_ok_and(|client| client.callback_url.contains(redirect_uri)),
+        _ => false,
+    }
+}
+
+fn callback_error(data: &AuthParams, err: OauthError) -> AuthResponse {
     info!("{:?}", err);
 
-    // add the code to the callback URL and return it
-    let mut params: HashMap<&str, &str> = HashMap::new();
-    params.insert("error", &err.error);
-    if err.error_description.is_some() {
-        params.insert("error_description", err.error_description.as_ref().unwrap());
+    let mode = response_mode(data.response_type.as_deref().unwrap_or("code"), data.response_mode.as_deref());
+    let client_id = data.client_id.as_deref().unwrap_or_default();
+    let mut callback = AuthResponse::new(data.redirect_uri.as_ref().unwrap(), client_id, mode);
+    callback.add("error", &err.error);
+    if let Some(descr) = err.error_description.as_ref() {
+        callback.add("error_description", descr);
+    }
+    if let Some(s) = data.state.as_ref() {
+        callback.add("state", s);
     }
-    if data.state.is_some() {
-        params.insert("state", data.state.as_ref().unwrap());
+    callback
+}
+
+/// the parameters of the authorization response (or error) and how they are sent to the client
+pub struct AuthResponse {
+    pub redirect_uri: String,
+    pub client_id: String,
+    /// one of `RESPONSE_MODES`, `jwt` already resolved
+    pub response_mode: &'static str,
+    pub params: Vec<(&'static str, String)>,
+}
+
+impl AuthResponse {
+    pub fn new(redirect_uri: &str, client_id: &str, response_mode: &'static str) -> Self {
+        AuthResponse {
+            redirect_uri: redirect_uri.to_string(),
+            client_id: client_id.to_string(),
+            response_mode,
+            params: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, name: &'static str, value: &str) {
+        self.params.push((name, value.to_string()));
+    }
+
+    /// redirects to the client (the URL is sent in the `location` header),
+    /// or renders an auto-submitting form for `form_post` (https://openid.net/specs/oauth-v2-form-post-response-mode-1_0.html)
+    ///
+    /// in the JARM modes the parameters are sent as a single signed `response` JWT
+    pub fn send(&self, state: &AppState, location: HeaderName) -> Result<HttpResponse> {
+        let (mode, params) = match self.response_mode.strip_suffix(".jwt") {
+            Some(mode) => (mode, vec![("response", self.sign(state)?)]),
+            None => (self.response_mode, self.params.clone()),
+        };
+
+        if mode == "form_post" {
+            let mut model = tera::Context::new();
+            model.insert("redirect_uri", &self.redirect_uri);
+            model.insert("params", &params);
+            let mut resp = state.send_page(StatusCode::OK, "form_post.html", model)?;
+            resp.headers_mut().insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+            return Ok(resp);
+        }
+
+        let params: Vec<(&str, &str)> = params.iter().map(|(k, v)| (*k, v.as_str())).collect();
+        let url = web_util::redirect_url(&self.redirect_uri, &params, mode == "fragment")?;
+        Ok(HttpResponse::Found().append_header((location, url)).finish())
+    }
+
+    /// https://openid.net/specs/oauth-v2-jarm.html#section-2.1
+    fn sign(&self, state: &AppState) -> Result<String, AppError> {
+        let client = state
+            .oauth_db
+            .fetch_client_config(&self.client_id)
+            .map_err(|_| AppError::bad_req("Unknown or invalid client_id "))?;
+
+        let exp = Utc::now() + chrono::Duration::minutes(state.config.oauth.auth_code_exp);
+        let mut claims = serde_json::Map::new();
+        claims.insert("iss".into(), state.config.oauth.issuer.clone().into());
+        claims.insert("aud".into(), client.id.clone().into());
+        claims.insert("exp".into(), exp.timestamp().into());
+        for (name, value) in self.params.iter() {
+            claims.insert(name.to_string(), value.clone().into());
+        }
+
+        let id_token_cfg = &state.config.oauth.id_token;
+        let alg = client
+            .authorization_signed_response_alg
+            .unwrap_or(client.id_token_signing_alg(id_token_cfg));
+        signing::sign(state, alg, &id_token_cfg.available_signing, None, &claims, "authorization response")
     }
-    let callback_url = Url::parse_with_params(data.redirect_uri.as_ref().unwrap(), params).unwrap();
-    Ok(callback_url.to_string())
 }
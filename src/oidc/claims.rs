@@ -0,0 +1,166 @@
+use super::OauthError;
+use crate::core::config::ClaimsConfig;
+use crate::core::models::User;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+/// the `claims` request parameter, https://openid.net/specs/openid-connect-core-1_0.html#ClaimsParameter
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct ClaimsRequest {
+    #[serde(default)]
+    pub userinfo: BTreeMap<String, Option<ClaimRequest>>,
+    #[serde(default)]
+    pub id_token: BTreeMap<String, Option<ClaimRequest>>,
+}
+
+/// an individual claim request, `null` in the request for the default behavior
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct ClaimRequest {
+    #[serde(default)]
+    pub essential: bool,
+    pub value: Option<Value>,
+    pub values: Option<Vec<Value>>,
+}
+
+impl ClaimsRequest {
+    pub(crate) fn parse(claims: Option<&str>) -> Result<ClaimsRequest, OauthError> {
+        let invalid = || OauthError::new("invalid_request", "invalid 'claims'");
+        match claims.map(serde_json::from_str::<Value>) {
+            None => Ok(ClaimsRequest::default()),
+            Some(Ok(c)) if c.is_object() => serde_json::from_value(c).map_err(|_| invalid()),
+            Some(_) => Err(invalid()),
+        }
+    }
+}
+
+/// the standard claims released by each scope, https://openid.net/specs/openid-connect-core-1_0.html#ScopeClaims
+///
+/// `preferred_username` is only released if the user has one, the login name (the user id) is never used for it,
+/// it would defeat pairwise subjects
+pub static SCOPE_CLAIMS: [(&str, &[&str]); 4] = [
+    (
+        "profile",
+        &[
+            "name",
+            "family_name",
+            "given_name",
+            "middle_name",
+            "nickname",
+            "preferred_username",
+            "profile",
+            "picture",
+            "website",
+            "gender",
+            "birthdate",
+            "zoneinfo",
+            "locale",
+            "updated_at",
+        ],
+    ),
+    ("email", &["email", "email_verified"]),
+    ("phone", &["phone_number", "phone_number_verified"]),
+    ("address", &["address"]),
+];
+
+/// the claims released by the granted scopes, the configured scopes replace the standard ones
+pub(crate) fn scope_claims<'a>(cfg: &ClaimsConfig, scopes: impl IntoIterator<Item = &'a str>) -> BTreeSet<String> {
+    let mut claims = BTreeSet::new();
+    for scope in scopes {
+        match cfg.scopes.get(scope) {
+            Some(c) => claims.extend(c.iter().cloned()),
+            None => claims.extend(standard_scope_claims(scope).iter().map(|c| c.to_string())),
+        }
+    }
+    claims
+}
+
+fn standard_scope_claims(scope: &str) -> &'static [&'static str] {
+    SCOPE_CLAIMS.iter().find(|(s, _)| *s == scope).map(|(_, c)| *c).unwrap_or_default()
+}
+
+/// the user claims supported by the provider: the claims of the standard and of the configured scopes
+pub fn supported_claims(cfg: &ClaimsConfig) -> BTreeSet<String> {
+    let standard = SCOPE_CLAIMS.iter().map(|(scope, _)| *scope);
+    let configured = cfg.scopes.keys().map(String::as_str);
+    let mut claims = scope_claims(cfg, standard.chain(configured));
+    claims.extend(cfg.mapping.keys().cloned());
+    claims
+}
+
+/// the requested user claims, unknown and unset claims are left out
+pub(crate) fn user_claims(cfg: &ClaimsConfig, user: &User, names: impl IntoIterator<Item = impl AsRef<str>>) -> Map<String, Value> {
+    let attributes: Map<String, Value> = user.attributes.as_deref().and_then(|a| serde_json::from_str(a).ok()).unwrap_or_default();
+
+    let mut claims = Map::new();
+    for name in names {
+        let name = name.as_ref();
+        let source = cfg.mapping.get(name).map(String::as_str).unwrap_or(name);
+        if let Some(v) = claim_value(user, &attributes, source) {
+            claims.insert(name.to_string(), v);
+        }
+    }
+    claims
+}
+
+/// the value of a `User` field (or of a standard claim derived from it), or of a user attribute
+fn claim_value(user: &User, attributes: &Map<String, Value>, source: &str) -> Option<Value> {
+    if let Some(attribute) = source.strip_prefix("attributes.") {
+        return attributes.get(attribute).cloned();
+    }
+    match source {
+        "id" => Some(user.id.clone().into()),
+        "name" => Some(
+            user.preferred_display_name
+                .clone()
+                .unwrap_or(format!("{} {}", user.given_name, user.family_name))
+                .into(),
+        ),
+        "given_name" => Some(user.given_name.clone().into()),
+        "family_name" => Some(user.family_name.clone().into()),
+        "middle_name" => user.middle_name.clone().map(Value::from),
+        "nickname" => user.nickname.clone().map(Value::from),
+        "preferred_username" => user.preferred_username.clone().map(Value::from),
+        "preferred_display_name" => user.preferred_display_name.clone().map(Value::from),
+        "profile" => user.profile.clone().map(Value::from),
+        "picture" => user.picture.clone().map(Value::from),
+        "website" => user.website.clone().map(Value::from),
+        "gender" => user.gender.clone().map(Value::from),
+        "zoneinfo" => user.zoneinfo.clone().map(Value::from),
+        "updated_at" => user.updated_at.map(|t| t.and_utc().timestamp().into()),
+        "locale" => user.locale.clone().map(Value::from),
+        "birthdate" => user.birthdate.clone().map(Value::from),
+        "email" => user.email.clone().map(Value::from),
+        "email_verified" => user.email.as_ref().map(|_| user.email_verified.into()),
+        "phone" | "phone_number" => user.phone.clone().map(Value::from),
+        "phone_number_verified" => user.phone.as_ref().map(|_| user.phone_number_verified.into()),
+        "address" => user.address.as_ref().and_then(|a| serde_json::to_value(a).ok()),
+        _ => attributes.get(source).cloned(),
+    }
+}
+
+/// the requested individual user claims that a scope in the client's `allowed_scopes` releases,
+/// other claims are left out (`sub`, `acr`, ... are handled by the caller)
+pub(crate) fn requested_user_claims(
+    cfg: &ClaimsConfig,
+    allowed_scopes: &str,
+    requested: &BTreeMap<String, Option<ClaimRequest>>,
+) -> BTreeSet<String> {
+    let allowed = scope_claims(cfg, allowed_scopes.split_whitespace());
+    requested.keys().filter(|c| allowed.contains(*c)).cloned().collect()
+}
+
+/// the individually requested user claims (userinfo and id_token) that are neither released by the `scopes`
+/// nor `granted` before, the user consents to them together with the scopes
+pub(crate) fn claims_to_consent<'a>(
+    cfg: &ClaimsConfig,
+    allowed_scopes: &str,
+    claims: Option<&str>,
+    scopes: impl IntoIterator<Item = &'a str>,
+    granted: &HashSet<String>,
+) -> Vec<String> {
+    let requested = ClaimsRequest::parse(claims).unwrap_or_default();
+    let released = scope_claims(cfg, scopes);
+    let mut names = requested_user_claims(cfg, allowed_scopes, &requested.userinfo);
+    names.extend(requested_user_claims(cfg, allowed_scopes, &requested.id_token));
+    names.into_iter().filter(|c| !released.contains(c) && !granted.contains(c)).collect()
+}
@@ -0,0 +1,272 @@
+//! Client Authentication (https://openid.net/specs/openid-connect-core-1_0.html#ClientAuthentication)
+//!
+//! every client registers one `token_endpoint_auth_method`, which is looked up in [`AUTH_METHODS`].
+use crate::core::models::{ClientAssertion, OauthClient};
+use crate::core::secrets::verify_password;
+use crate::core::web_util::parse_basic_auth;
+use crate::core::AppState;
+use crate::oidc::discovery;
+use actix_http::header::AUTHORIZATION;
+use actix_web::web::Data;
+use actix_web::HttpRequest;
+use chrono::DateTime;
+use jwt::dangerous::insecure_decode;
+use jwt::{decode, decode_header, Algorithm, AlgorithmFamily, DecodingKey, Header, Validation};
+
+/// https://www.rfc-editor.org/rfc/rfc7523#section-2.2
+const JWT_BEARER: &str = "urn:ietf:params:oauth:client-assertion-type:jwt-bearer";
+
+/// the supported client authentication methods
+pub static AUTH_METHODS: [&dyn ClientAuthMethod; 5] = [&ClientSecretBasic, &ClientSecretPost, &ClientSecretJwt, &PrivateKeyJwt, &NoAuth];
+
+/// algorithms accepted for `client_secret_jwt` and `private_key_jwt` assertions
+pub static ASSERTION_SIGNING_ALGS: [Algorithm; 12] = [
+    Algorithm::HS256,
+    Algorithm::HS384,
+    Algorithm::HS512,
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+/// client authentication parameters sent in the request body
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ClientAuthParams {
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    pub client_assertion_type: Option<String>,
+    pub client_assertion: Option<String>,
+}
+
+/// the credentials presented by the client
+pub enum Credentials {
+    Basic { client_id: String, secret: String },
+    Post { client_id: String, secret: String },
+    Assertion { client_id: String, assertion: String },
+    ClientId(String),
+}
+
+impl Credentials {
+    fn client_id(&self) -> &str {
+        match self {
+            Credentials::Basic { client_id, .. } => client_id,
+            Credentials::Post { client_id, .. } => client_id,
+            Credentials::Assertion { client_id, .. } => client_id,
+            Credentials::ClientId(client_id) => client_id,
+        }
+    }
+}
+
+pub trait ClientAuthMethod: Sync {
+    /// the method name, as used in the client registration and discovery
+    fn name(&self) -> &'static str;
+    /// verifies the credentials. `audience` holds the values accepted as `aud` of a client assertion
+    fn authenticate(&self, client: &OauthClient, credentials: &Credentials, audience: &[String]) -> Result<(), String>;
+}
+
+struct ClientSecretBasic;
+struct ClientSecretPost;
+struct ClientSecretJwt;
+struct PrivateKeyJwt;
+struct NoAuth;
+
+impl ClientAuthMethod for ClientSecretBasic {
+    fn name(&self) -> &'static str {
+        "client_secret_basic"
+    }
+    fn authenticate(&self, client: &OauthClient, credentials: &Credentials, _: &[String]) -> Result<(), String> {
+        match credentials {
+            Credentials::Basic { secret, .. } => verify_password(&client.secret, secret),
+            _ => Err(format!("client {} must use {}", client.id, self.name())),
+        }
+    }
+}
+
+impl ClientAuthMethod for ClientSecretPost {
+    fn name(&self) -> &'static str {
+        "client_secret_post"
+    }
+    fn authenticate(&self, client: &OauthClient, credentials: &Credentials, _: &[String]) -> Result<(), String> {
+        match credentials {
+            Credentials::Post { secret, .. } => verify_password(&client.secret, secret),
+            _ => Err(format!("client {} must use {}", client.id, self.name())),
+        }
+    }
+}
+
+impl ClientAuthMethod for ClientSecretJwt {
+    fn name(&self) -> &'static str {
+        "client_secret_jwt"
+    }
+    fn authenticate(&self, client: &OauthClient, credentials: &Credentials, audience: &[String]) -> Result<(), String> {
+        match credentials {
+            Credentials::Assertion { assertion, .. } => verify_assertion(client, assertion, audience, true),
+            _ => Err(format!("client {} must use {}", client.id, self.name())),
+        }
+    }
+}
+
+impl ClientAuthMethod for PrivateKeyJwt {
+    fn name(&self) -> &'static str {
+        "private_key_jwt"
+    }
+    fn authenticate(&self, client: &OauthClient, credentials: &Credentials, audience: &[String]) -> Result<(), String> {
+        match credentials {
+            Credentials::Assertion { assertion, .. } => verify_assertion(client, assertion, audience, false),
+            _ => Err(format!("client {} must use {}", client.id, self.name())),
+        }
+    }
+}
+
+impl ClientAuthMethod for NoAuth {
+    fn name(&self) -> &'static str {
+        "none"
+    }
+    fn authenticate(&self, client: &OauthClient, credentials: &Credentials, _: &[String]) -> Result<(), String> {
+        match credentials {
+            Credentials::ClientId(_) if client.is_public() => Ok(()),
+            _ => Err(format!("client {} must authenticate", client.id)),
+        }
+    }
+}
+
+/// authenticates the client with its registered `token_endpoint_auth_method` and returns its configuration
+pub fn validate_client_credentials(req: &HttpRequest, state: &Data<AppState>, params: &ClientAuthParams) -> actix_web::Result<OauthClient, String> {
+    let credentials = extract_credentials(req, params)?;
+    let client_id = credentials.client_id();
+
+    let client = state
+        .oauth_db
+        .fetch_client_config(client_id)
+        .map_err(|e| format!("error loading client {}: {}", client_id, e))?;
+    debug!("client {} loaded", client_id);
+
+    let method = AUTH_METHODS
+        .iter()
+        .find(|m| m.name() == client.token_endpoint_auth_method)
+        .ok_or_else(|| format!("unsupported auth method {} for client {}", client.token_endpoint_auth_method, client_id))?;
+
+    let base_url = discovery::base_url(&state.config);
+    let audience = vec![
+        state.config.oauth.issuer.clone(),
+        base_url.clone() + "/oauth2/token",
+        base_url + req.path(),
+    ];
+    method.authenticate(&client, &credentials, &audience)?;
+
+    // a client assertion is accepted only once (https://www.rfc-editor.org/rfc/rfc7523#section-3)
+    if let Credentials::Assertion { assertion, .. } = &credentials {
+        let used = used_assertion(&client, assertion)?;
+        let first_use = state
+            .oauth_db
+            .save_client_assertion(&used)
+            .map_err(|e| format!("error saving client assertion: {}", e))?;
+        if !first_use {
+            return Err(format!("client assertion {} of client {} already used", used.jti, client.id));
+        }
+    }
+
+    Ok(client)
+}
+
+/// a client must not use more than one authentication method per request
+/// (https://www.rfc-editor.org/rfc/rfc6749#section-2.3)
+fn extract_credentials(req: &HttpRequest, params: &ClientAuthParams) -> Result<Credentials, String> {
+    let credentials = match (req.headers().get(AUTHORIZATION), &params.client_secret, &params.client_assertion) {
+        (Some(header), None, None) => {
+            let raw_basic_auth_header = header.to_str().map_err(|_| "header convert error")?;
+            let (client_id, secret) = parse_basic_auth(raw_basic_auth_header).ok_or("error parsing basic auth header")?;
+            Credentials::Basic { client_id, secret }
+        }
+        (None, Some(secret), None) => Credentials::Post {
+            client_id: params.client_id.clone().ok_or("'client_id' is required")?,
+            secret: secret.clone(),
+        },
+        (None, None, Some(assertion)) => {
+            if params.client_assertion_type.as_deref() != Some(JWT_BEARER) {
+                return Err("invalid 'client_assertion_type'".into());
+            }
+            let claims = insecure_decode::<serde_json::Value>(assertion).map_err(|e| format!("invalid client assertion: {}", e))?;
+            let client_id = claims.claims["sub"].as_str().ok_or("no 'sub' in client assertion")?;
+            Credentials::Assertion {
+                client_id: client_id.to_string(),
+                assertion: assertion.clone(),
+            }
+        }
+        (None, None, None) => Credentials::ClientId(params.client_id.clone().ok_or("no client authentication")?),
+        _ => return Err("more than one client authentication method used".into()),
+    };
+
+    match params.client_id.as_deref() {
+        Some(id) if id != credentials.client_id() => Err("'client_id' does not match the client credentials".into()),
+        _ => Ok(credentials),
+    }
+}
+
+fn verify_assertion(client: &OauthClient, assertion: &str, audience: &[String], symmetric: bool) -> Result<(), String> {
+    let header = decode_header(assertion).map_err(|e| format!("invalid client assertion: {}", e))?;
+    if (header.alg.family() == AlgorithmFamily::Hmac) != symmetric || !ASSERTION_SIGNING_ALGS.contains(&header.alg) {
+        return Err(format!("alg {:?} not allowed for {}", header.alg, client.token_endpoint_auth_method));
+    }
+
+    let key = client_decoding_key(client, &header)?;
+    let mut validation = Validation::new(header.alg);
+    validation.set_required_spec_claims(&["exp", "iss", "sub", "aud"]);
+    validation.set_issuer(&[&client.id]);
+    validation.sub = Some(client.id.clone());
+    validation.set_audience(audience);
+
+    let claims = decode::<serde_json::Value>(assertion, &key, &validation).map_err(|e| format!("invalid client assertion: {}", e))?;
+    if !claims.claims["jti"].is_string() {
+        return Err("no 'jti' in client assertion".into());
+    }
+    Ok(())
+}
+
+/// the `jti` and `exp` of a verified client assertion
+#[derive(Deserialize)]
+struct AssertionId {
+    jti: String,
+    exp: i64,
+}
+
+fn used_assertion(client: &OauthClient, assertion: &str) -> Result<ClientAssertion, String> {
+    let id = insecure_decode::<AssertionId>(assertion)
+        .map_err(|e| format!("invalid client assertion: {}", e))?
+        .claims;
+    let expiration = DateTime::from_timestamp(id.exp, 0).ok_or("invalid 'exp' in client assertion")?;
+    Ok(ClientAssertion {
+        client_id: client.id.clone(),
+        jti: id.jti,
+        expiration: expiration.naive_utc(),
+    })
+}
+
+/// selects the key to verify a JWT signed by the client: the client secret for HMAC algs, a key of its registered JWKS otherwise
+pub(crate) fn client_decoding_key(client: &OauthClient, header: &Header) -> Result<DecodingKey, String> {
+    if header.alg.family() == AlgorithmFamily::Hmac {
+        if client.secret.is_empty() || client.secret.starts_with("{BCRYPT}") {
+            return Err(format!("client {} has no plain secret for {:?}", client.id, header.alg));
+        }
+        return Ok(DecodingKey::from_secret(client.secret.as_bytes()));
+    }
+
+    let jwks = client
+        .jwks
+        .as_ref()
+        .ok_or_else(|| format!("client {} has no registered jwks", client.id))?;
+    let jwk = match header.kid.as_deref() {
+        Some(kid) => jwks.find(kid),
+        None if jwks.keys.len() == 1 => jwks.keys.first(),
+        None => None,
+    }
+    .ok_or_else(|| format!("no matching key in the jwks of client {}", client.id))?;
+
+    DecodingKey::from_jwk(jwk).map_err(|e| format!("invalid jwk of client {}: {}", client.id, e))
+}
@@ -1,5 +1,7 @@
 use crate::core;
-use crate::core::AppState;
+use crate::core::config::ClaimsConfig;
+use crate::core::{AppState, Config};
+use crate::oidc::{authorize, claims, client_auth, jwe, pkce};
 use actix_web::http::StatusCode;
 use actix_web::web::Data;
 use actix_web::{HttpRequest, HttpResponse, Result};
@@ -11,27 +13,56 @@ use jwt::Algorithm;
  * Discovery End-Point: https://openid.net/specs/openid-connect-discovery-1_0.html
  */
 pub async fn openid_config((_req, state): (HttpRequest, Data<AppState>)) -> Result<HttpResponse> {
-    let base_url = format!(
-        "{}://{}:{}",
-        &state.config.server.protocol,
-        state.config.server.domain.as_deref().unwrap_or(&state.config.server.address),
-        &state.config.server.port
-    );
+    let base_url = base_url(&state.config);
 
     let prov_config = OIDCProviderConfig {
         issuer: base_url.clone(),
         authorization_endpoint: base_url.clone() + "/oauth2/authorize",
         token_endpoint: base_url.clone() + "/oauth2/token",
         introspection_endpoint: Some(base_url.clone() + "/oauth2/token_info"),
+        revocation_endpoint: Some(base_url.clone() + "/oauth2/revoke"),
+        revocation_endpoint_auth_methods_supported: Some(client_auth::AUTH_METHODS.iter().map(|m| m.name().to_string()).collect()),
         userinfo_endpoint: Some(base_url.clone() + "/oauth2/user_info"),
         jwks_uri: base_url.clone() + "/.well-known/jwks.json",
-        scopes_supported: Some(supported_scopes(&state.config.oauth.scopes)),
-        response_types_supported: vec!["code".into()],                  // TODO token?
-        grant_types_supported: Some(vec!["authorization_code".into()]), // TODO impl. more
-        subject_types_supported: vec!["public".into()],                 // TODO add pairwise too?
+        scopes_supported: Some(supported_scopes(&state.config.oauth.scopes, &state.config.oauth.claims)),
+        response_types_supported: authorize::RESPONSE_TYPES.iter().map(|s| s.to_string()).collect(),
+        response_modes_supported: Some(authorize::RESPONSE_MODES.iter().map(|s| s.to_string()).collect()),
+        prompt_values_supported: Some(authorize::PROMPT_VALUES.iter().map(|s| s.to_string()).collect()),
+        pushed_authorization_request_endpoint: Some(base_url.clone() + "/oauth2/par"),
+        require_pushed_authorization_requests: Some(state.config.oauth.require_pushed_authorization_requests),
+        request_parameter_supported: Some(true),
+        request_uri_parameter_supported: Some(true),
+        require_request_uri_registration: Some(true),
+        request_object_signing_alg_values_supported: Some(client_auth::ASSERTION_SIGNING_ALGS.to_vec()),
+        userinfo_signing_alg_values_supported: Some(state.config.oauth.id_token.available_signing.keys().cloned().collect()),
+        userinfo_encryption_alg_values_supported: Some(jwe::ENCRYPTION_ALGS.iter().map(|s| s.to_string()).collect()),
+        userinfo_encryption_enc_values_supported: Some(jwe::ENCRYPTION_ENCS.iter().map(|s| s.to_string()).collect()),
+        authorization_signing_alg_values_supported: Some(state.config.oauth.id_token.available_signing.keys().cloned().collect()),
+        grant_types_supported: Some(vec![
+            "authorization_code".into(),
+            "implicit".into(),
+            "refresh_token".into(),
+            "client_credentials".into(),
+        ]), // TODO impl. more
+        subject_types_supported: match state.config.oauth.pairwise_salt {
+            Some(_) => vec!["public".into(), "pairwise".into()],
+            None => vec!["public".into()],
+        },
         id_token_signing_alg_values_supported: state.config.oauth.id_token.available_signing.keys().cloned().collect(),
-        claims_supported: Some(vec!["sub".into()]),
-        acr_values_supported: Some(SUPPORTED_ACR_VALUES.to_vec()),
+        id_token_encryption_alg_values_supported: Some(jwe::ENCRYPTION_ALGS.iter().map(|s| s.to_string()).collect()),
+        id_token_encryption_enc_values_supported: Some(jwe::ENCRYPTION_ENCS.iter().map(|s| s.to_string()).collect()),
+        claims_supported: Some(
+            ["sub", "auth_time", "acr", "amr"]
+                .into_iter()
+                .map(String::from)
+                .chain(claims::supported_claims(&state.config.oauth.claims))
+                .collect(),
+        ),
+        claims_parameter_supported: Some(true),
+        acr_values_supported: Some(state.config.oauth.acr.iter().map(|c| c.value.clone()).collect()),
+        code_challenge_methods_supported: Some(pkce::CODE_CHALLENGE_METHODS.iter().map(|m| m.to_string()).collect()),
+        token_endpoint_auth_methods_supported: Some(client_auth::AUTH_METHODS.iter().map(|m| m.name().to_string()).collect()),
+        token_endpoint_auth_signing_alg_values_supported: Some(client_auth::ASSERTION_SIGNING_ALGS.to_vec()),
         ..Default::default()
     };
 
@@ -52,6 +83,10 @@ pub struct OIDCProviderConfig {
     userinfo_endpoint: Option<String>, // RECOMENDED
     #[serde(skip_serializing_if = "Option::is_none")]
     introspection_endpoint: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    revocation_endpoint: Option<String>, // RFC 8414
+    #[serde(skip_serializing_if = "Option::is_none")]
+    revocation_endpoint_auth_methods_supported: Option<Vec<String>>, // RFC 8414
     jwks_uri: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     registration_endpoint: Option<String>, // RECOMENDED
@@ -66,14 +101,59 @@ pub struct OIDCProviderConfig {
     acr_values_supported: Option<Vec<String>>, // OPTIONAL
     subject_types_supported: Vec<String>,
     id_token_signing_alg_values_supported: Vec<Algorithm>, // RS256 must be included
-    // ...
-    claims_supported: Option<Vec<String>>, // RECOMENDED, default: ["authorization_code", "implicit"]
-                                           // ...
-                                           // TODO add all fields
+    #[serde(skip_serializing_if = "Option::is_none")]
+    userinfo_signing_alg_values_supported: Option<Vec<Algorithm>>, // OPTIONAL
+    #[serde(skip_serializing_if = "Option::is_none")]
+    userinfo_encryption_alg_values_supported: Option<Vec<String>>, // OPTIONAL
+    #[serde(skip_serializing_if = "Option::is_none")]
+    userinfo_encryption_enc_values_supported: Option<Vec<String>>, // OPTIONAL
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id_token_encryption_alg_values_supported: Option<Vec<String>>, // OPTIONAL
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id_token_encryption_enc_values_supported: Option<Vec<String>>, // OPTIONAL
+    // ... TODO add all fields
+    claims_supported: Option<Vec<String>>, // RECOMENDED
+    #[serde(skip_serializing_if = "Option::is_none")]
+    claims_parameter_supported: Option<bool>, // OPTIONAL, default: false
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token_endpoint_auth_methods_supported: Option<Vec<String>>, // OPTIONAL, default: ["client_secret_basic"]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token_endpoint_auth_signing_alg_values_supported: Option<Vec<Algorithm>>, // OPTIONAL
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code_challenge_methods_supported: Option<Vec<String>>, // RFC 8414
+    #[serde(skip_serializing_if = "Option::is_none")]
+    authorization_signing_alg_values_supported: Option<Vec<Algorithm>>, // JARM
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prompt_values_supported: Option<Vec<String>>, // OpenID Connect Prompt Create
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pushed_authorization_request_endpoint: Option<String>, // RFC 9126
+    #[serde(skip_serializing_if = "Option::is_none")]
+    require_pushed_authorization_requests: Option<bool>, // RFC 9126, default: false
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_parameter_supported: Option<bool>, // OPTIONAL, default: false
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_uri_parameter_supported: Option<bool>, // OPTIONAL, default: true
+    #[serde(skip_serializing_if = "Option::is_none")]
+    require_request_uri_registration: Option<bool>, // OPTIONAL, default: false
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_object_signing_alg_values_supported: Option<Vec<Algorithm>>, // OPTIONAL
 }
 
-pub fn supported_scopes(scopes: &str) -> Vec<String> {
-    scopes.split_whitespace().map(String::from).collect::<Vec<String>>()
+/// the public base URL of the provider
+pub fn base_url(config: &Config) -> String {
+    format!(
+        "{}://{}:{}",
+        &config.server.protocol,
+        config.server.domain.as_deref().unwrap_or(&config.server.address),
+        &config.server.port
+    )
 }
 
-pub static SUPPORTED_ACR_VALUES: [String; 0] = [];
+/// the configured scopes, with the custom scopes of the claims mapping
+pub fn supported_scopes(scopes: &str, claims: &ClaimsConfig) -> Vec<String> {
+    let mut scopes = scopes.split_whitespace().map(String::from).collect::<Vec<String>>();
+    let mut custom: Vec<&String> = claims.scopes.keys().filter(|s| !scopes.contains(s)).collect();
+    custom.sort();
+    scopes.extend(custom.into_iter().cloned());
+    scopes
+}
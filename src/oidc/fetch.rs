@@ -0,0 +1,142 @@
+//! fetches the documents a client registered by URL (request objects, sector identifiers)
+//!
+//! the fetch is blocking, it runs on the blocking thread pool so the actix worker only waits for it.
+use actix_web::web;
+use openssl::ssl::{SslConnector, SslMethod};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+use url::{Position, Url};
+
+/// the whole fetch (DNS, connect, TLS, response) must be done within this time
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+/// the maximal size of the response
+const MAX_RESPONSE_SIZE: usize = 64 * 1024;
+
+/// GETs the body of `url` (https only), `accept` is the expected media type
+pub(crate) async fn https_get(url: &Url, accept: &'static str) -> Result<String, String> {
+    if url.scheme() != "https" {
+        return Err("only https URLs are fetched".into());
+    }
+    let url = url.clone();
+    let deadline = Instant::now() + FETCH_TIMEOUT;
+    // the DNS lookup can not be interrupted, the worker stops waiting at the deadline anyway
+    match actix_rt::time::timeout(FETCH_TIMEOUT, web::block(move || blocking_get(&url, accept, deadline))).await {
+        Ok(Ok(result)) => result,
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err("timeout".into()),
+    }
+}
+
+/// a plain HTTP/1.0 GET, so the response is neither chunked nor kept alive
+fn blocking_get(url: &Url, accept: &str, deadline: Instant) -> Result<String, String> {
+    let remaining = || {
+        deadline
+            .checked_duration_since(Instant::now())
+            .filter(|d| !d.is_zero())
+            .ok_or_else(|| "timeout".to_string())
+    };
+
+    let host = url.host_str().ok_or("no host")?;
+    let addrs = url.socket_addrs(|| Some(443)).map_err(|e| e.to_string())?;
+
+    // the first address that accepts the connection is used
+    let mut last_error = String::from("host not found");
+    let mut stream = None;
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, remaining()?) {
+            Ok(s) => {
+                stream = Some(s);
+                break;
+            }
+            Err(e) => last_error = format!("{}: {}", addr, e),
+        }
+    }
+    let stream = stream.ok_or(last_error)?;
+    stream.set_read_timeout(Some(remaining()?)).map_err(|e| e.to_string())?;
+    stream.set_write_timeout(Some(remaining()?)).map_err(|e| e.to_string())?;
+    let connector = SslConnector::builder(SslMethod::tls_client()).map_err(|e| e.to_string())?.build();
+    let mut stream = connector.connect(host, stream).map_err(|e| e.to_string())?;
+
+    let request = request(url, accept);
+    stream.get_ref().set_write_timeout(Some(remaining()?)).map_err(|e| e.to_string())?;
+    stream.write_all(request.as_bytes()).map_err(|e| e.to_string())?;
+
+    let mut response = Vec::new();
+    let mut buf = [0u8; 4096];
+    while response.len() < MAX_RESPONSE_SIZE {
+        // the read timeout is the time left, a slow server can not extend the fetch
+        stream.get_ref().set_read_timeout(Some(remaining()?)).map_err(|e| e.to_string())?;
+        match stream.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => response.extend_from_slice(&buf[..n]),
+            // some servers close the connection without a TLS close_notify, a truncated document fails the parsing
+            Err(_) if !response.is_empty() => break,
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+    response_body(&response)
+}
+
+/// the `Host` header includes the port if the URL has one, for virtual hosts on other ports than 443
+fn request(url: &Url, accept: &str) -> String {
+    format!(
+        "GET {} HTTP/1.0\r\nHost: {}\r\nAccept: {}\r\nConnection: close\r\n\r\n",
+        &url[Position::BeforePath..Position::AfterQuery],
+        &url[Position::BeforeHost..Position::AfterPort],
+        accept
+    )
+}
+
+/// the body of a `200 OK` response
+fn response_body(response: &[u8]) -> Result<String, String> {
+    let response = std::str::from_utf8(response).map_err(|_| "invalid response encoding")?;
+    let (head, body) = response.split_once("\r\n\r\n").ok_or("invalid HTTP response")?;
+    match head.split_whitespace().nth(1) {
+        Some("200") => Ok(body.trim().to_string()),
+        Some(status) => Err(format!("HTTP status {}", status)),
+        None => Err("invalid HTTP response".into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_response_body() {
+        let ok = b"HTTP/1.1 200 OK\r\nContent-Type: application/oauth-authz-req+jwt\r\n\r\neyJhbGciOiJSUzI1NiJ9.e30.sig\r\n";
+        assert_eq!(response_body(ok).unwrap(), "eyJhbGciOiJSUzI1NiJ9.e30.sig");
+
+        assert_eq!(response_body(b"HTTP/1.0 404 Not Found\r\n\r\nnot found").unwrap_err(), "HTTP status 404");
+        assert!(response_body(b"HTTP/1.0 200 OK").is_err());
+    }
+
+    #[test]
+    fn test_request() {
+        let url = Url::parse("https://client.example.org:8443/request.jwt?v=1").unwrap();
+        assert_eq!(
+            request(&url, "application/json"),
+            "GET /request.jwt?v=1 HTTP/1.0\r\nHost: client.example.org:8443\r\nAccept: application/json\r\nConnection: close\r\n\r\n"
+        );
+        let url = Url::parse("https://client.example.org:443/request.jwt").unwrap();
+        assert!(request(&url, "application/json").contains("\r\nHost: client.example.org\r\n"));
+    }
+
+    #[actix_rt::test]
+    async fn test_https_get_total_deadline() {
+        // the server accepts the connection, but never answers the TLS handshake
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("https://127.0.0.1:{}/request.jwt", listener.local_addr().unwrap().port())).unwrap();
+
+        let start = Instant::now();
+        assert!(https_get(&url, "application/json").await.is_err());
+        assert!(start.elapsed() < FETCH_TIMEOUT + Duration::from_secs(1));
+    }
+
+    #[actix_rt::test]
+    async fn test_https_get_plain_http() {
+        let url = Url::parse("http://client.example.org/request.jwt").unwrap();
+        assert!(https_get(&url, "application/json").await.is_err());
+    }
+}
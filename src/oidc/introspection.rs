@@ -1,13 +1,17 @@
-use crate::core::{error::AppError, json_ok, AppState};
-use crate::oidc::common::validate_client_credentials;
+use crate::core::error::{AppError, InternalError};
+use crate::core::{json_ok, AppState};
+use crate::oidc::client_auth::{validate_client_credentials, ClientAuthParams};
+use crate::oidc::subject;
 use actix_web::web::{Data, Form};
 use actix_web::{HttpRequest, HttpResponse, Result};
-use chrono::{Duration, Utc};
+use chrono::Duration;
 
 #[derive(Deserialize, Debug)]
 pub struct IntrospectParams {
     pub token: String,
     pub token_type_hint: Option<String>,
+    #[serde(flatten)]
+    pub client_auth: ClientAuthParams,
 }
 
 #[derive(Serialize, Default)]
@@ -33,31 +37,34 @@ struct IntrospectResponse {
 pub async fn introspect((params, state, req): (Form<IntrospectParams>, Data<AppState>, HttpRequest)) -> Result<HttpResponse> {
     debug!("introspect(hint: {:?})", params.token_type_hint);
 
-    if params.token_type_hint != None && params.token_type_hint != Some("access_token".to_owned()) {
+    if params.token_type_hint.is_some() && params.token_type_hint != Some("access_token".to_owned()) {
         info!("introspect: invalid token_type_hint"); // todo add support for refresh_token
         return json_ok(IntrospectResponse::default());
     }
 
-    match validate_client_credentials(&req, &state) {
-        Ok(_) => debug!("introspect: valid credentials"),
+    let caller = match validate_client_credentials(&req, &state, &params.client_auth) {
+        // a `client_id` alone is no authentication, public clients can not introspect tokens
+        Ok(client) if client.token_endpoint_auth_method == "none" => {
+            error!("introspect: client {} is not authenticated", client.id);
+            Err(AppError::Unauthorized)?
+        }
+        Ok(client) => {
+            debug!("introspect: valid credentials");
+            client
+        }
         Err(e) => {
             error!("introspect: invalid client credentials: {}", e);
             Err(AppError::Unauthorized)?
         }
-    }
+    };
 
     let token_data = match state.oauth_db.load_token_data(&params.token) {
         Ok(t) => t,
         Err(_) => return json_ok(IntrospectResponse::default()),
     };
 
-    let is_active = token_data.expiration.map_or(true, |secs| {
-        let exp_time = token_data.created + Duration::seconds(secs);
-        exp_time > Utc::now().naive_utc()
-    });
-
-    if !is_active {
-        debug!("introspect: token expired");
+    if !token_data.is_active() {
+        debug!("introspect: token expired or revoked");
         return json_ok(IntrospectResponse::default());
     }
 
@@ -65,11 +72,27 @@ pub async fn introspect((params, state, req): (Form<IntrospectParams>, Data<AppS
         .expiration
         .map(|secs| (token_data.created + Duration::seconds(secs)).and_utc().timestamp());
 
+    // the `sub` as released to the client of the token
+    let sub = match token_data.subject.as_deref() {
+        Some(subject) => {
+            let client = if token_data.client_id == caller.id {
+                caller
+            } else {
+                state
+                    .oauth_db
+                    .fetch_client_config(&token_data.client_id)
+                    .map_err(|_| InternalError::query_fail("failed to load the client config").to_user())?
+            };
+            Some(subject::for_client(&state.config.oauth, &client, subject)?)
+        }
+        None => None,
+    };
+
     json_ok(IntrospectResponse {
         active: true,
         scope: token_data.scopes,
         client_id: Some(token_data.client_id),
-        sub: token_data.subject,
+        sub,
         exp,
         iat: Some(token_data.created.and_utc().timestamp()),
         token_type: Some(token_data.token_type),
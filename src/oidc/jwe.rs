@@ -0,0 +1,425 @@
+use crate::core::error::AppError;
+use crate::core::models::OauthClient;
+use base64::prelude::*;
+use jwt::jwk::EllipticCurve;
+use jwt::jwk::{AlgorithmParameters, PublicKeyUse};
+use openssl::bn::{BigNum, BigNumContext};
+use openssl::derive::Deriver;
+use openssl::ec::{EcGroup, EcKey};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Public};
+use openssl::pkey_ctx::PkeyCtx;
+use openssl::rsa::{Padding, Rsa};
+use openssl::sha::Sha256;
+use openssl::sign::Signer;
+use openssl::symm::{self, Cipher};
+
+/// the supported key management algorithms (`alg`) of encrypted responses
+pub static ENCRYPTION_ALGS: [&str; 3] = ["RSA-OAEP", "RSA-OAEP-256", "ECDH-ES"];
+
+/// the supported content encryption algorithms (`enc`), `A128CBC-HS256` is the default
+pub static ENCRYPTION_ENCS: [&str; 4] = ["A128CBC-HS256", "A256CBC-HS512", "A128GCM", "A256GCM"];
+
+/// whether responses can be encrypted for the client: `alg` and `enc` are supported and the client's JWKS has a fitting key
+pub(crate) fn can_encrypt(client: &OauthClient, alg: &str, enc: Option<&str>) -> bool {
+    let enc = enc.unwrap_or(ENCRYPTION_ENCS[0]);
+    ENCRYPTION_ALGS.contains(&alg) && ENCRYPTION_ENCS.contains(&enc) && encryption_key(client, alg).is_ok()
+}
+
+/// the client key an encrypted response is encrypted to
+enum EncryptionKey {
+    Rsa(Rsa<Public>),
+    Ec(EcKey<Public>),
+}
+
+/// encrypts `payload` to the (RSA or EC) encryption key registered by the client, in the compact serialization
+/// (https://www.rfc-editor.org/rfc/rfc7516)
+///
+/// `cty` is `JWT` for nested (signed, then encrypted) tokens
+pub(crate) fn encrypt(client: &OauthClient, alg: &str, enc: Option<&str>, cty: Option<&str>, payload: &[u8]) -> Result<String, AppError> {
+    let enc = enc.unwrap_or(ENCRYPTION_ENCS[0]);
+    if !ENCRYPTION_ALGS.contains(&alg) || !ENCRYPTION_ENCS.contains(&enc) {
+        return Err(AppError::bad_config(format!(
+            "unsupported encryption {} / {} for client {}",
+            alg, enc, client.id
+        )));
+    }
+    let (key, kid) = encryption_key(client, alg)?;
+
+    let mut header = serde_json::Map::new();
+    header.insert("alg".into(), alg.into());
+    header.insert("enc".into(), enc.into());
+    if let Some(kid) = kid {
+        header.insert("kid".into(), kid.into());
+    }
+    if let Some(cty) = cty {
+        header.insert("cty".into(), cty.into());
+    }
+
+    // the content encryption key, for the CBC-HS algs the MAC key followed by the AES key
+    let (cek, encrypted_key) = match key {
+        EncryptionKey::Rsa(key) => {
+            let mut cek = vec![0u8; cek_len(enc)];
+            openssl::rand::rand_bytes(&mut cek).map_err(internal)?;
+            let encrypted_key = wrap_key(&key, alg, &cek).map_err(internal)?;
+            (cek, encrypted_key)
+        }
+        // direct key agreement, the encrypted key is empty
+        EncryptionKey::Ec(key) => {
+            let (cek, epk) = agree_key(&key, enc).map_err(internal)?;
+            header.insert("epk".into(), epk);
+            (cek, Vec::new())
+        }
+    };
+
+    let header = BASE64_URL_SAFE_NO_PAD.encode(serde_json::Value::Object(header).to_string());
+    let aad = header.as_bytes();
+
+    let mut iv = vec![0u8; if enc.ends_with("GCM") { 12 } else { 16 }];
+    openssl::rand::rand_bytes(&mut iv).map_err(internal)?;
+
+    let (ciphertext, tag) = encrypt_content(enc, &cek, &iv, aad, payload).map_err(internal)?;
+
+    let b64 = |v: &[u8]| BASE64_URL_SAFE_NO_PAD.encode(v);
+    Ok(format!(
+        "{}.{}.{}.{}.{}",
+        header,
+        b64(&encrypted_key),
+        b64(&iv),
+        b64(&ciphertext),
+        b64(&tag)
+    ))
+}
+
+/// encrypts `payload` with the content encryption algorithm `enc`, returns the ciphertext and the authentication tag
+fn encrypt_content(enc: &str, cek: &[u8], iv: &[u8], aad: &[u8], payload: &[u8]) -> Result<(Vec<u8>, Vec<u8>), openssl::error::ErrorStack> {
+    match enc {
+        "A128GCM" | "A256GCM" => {
+            let cipher = if enc == "A128GCM" {
+                Cipher::aes_128_gcm()
+            } else {
+                Cipher::aes_256_gcm()
+            };
+            let mut tag = vec![0u8; 16];
+            let ciphertext = symm::encrypt_aead(cipher, cek, Some(iv), aad, payload, &mut tag)?;
+            Ok((ciphertext, tag))
+        }
+        _ => {
+            // https://www.rfc-editor.org/rfc/rfc7518#section-5.2.2.1
+            let (mac_key, enc_key) = cek.split_at(cek.len() / 2);
+            let (cipher, digest) = if enc == "A128CBC-HS256" {
+                (Cipher::aes_128_cbc(), MessageDigest::sha256())
+            } else {
+                (Cipher::aes_256_cbc(), MessageDigest::sha512())
+            };
+            let ciphertext = symm::encrypt(cipher, enc_key, Some(iv), payload)?;
+            let al = ((aad.len() as u64) * 8).to_be_bytes();
+            let mac = hmac(digest, mac_key, &[aad, iv, &ciphertext, &al])?;
+            Ok((ciphertext, mac[..mac_key.len()].to_vec()))
+        }
+    }
+}
+
+/// the first key of the client's JWKS that is not restricted to signatures and fits `alg`: RSA for `RSA-OAEP*`, EC for `ECDH-ES`
+fn encryption_key(client: &OauthClient, alg: &str) -> Result<(EncryptionKey, Option<String>), AppError> {
+    let no_key = || AppError::bad_config(format!("client {} has no key for {} encryption in its jwks", client.id, alg));
+    let ecdh = alg.starts_with("ECDH-ES");
+    let jwk = client
+        .jwks
+        .as_ref()
+        .ok_or_else(no_key)?
+        .keys
+        .iter()
+        .filter(|k| k.common.public_key_use != Some(PublicKeyUse::Signature))
+        .find(|k| match &k.algorithm {
+            AlgorithmParameters::RSA(_) => !ecdh,
+            AlgorithmParameters::EllipticCurve(_) => ecdh,
+            _ => false,
+        })
+        .ok_or_else(no_key)?;
+
+    let component = |v: &str| {
+        let bytes = BASE64_URL_SAFE_NO_PAD.decode(v).map_err(|_| no_key())?;
+        BigNum::from_slice(&bytes).map_err(internal)
+    };
+    let key = match &jwk.algorithm {
+        AlgorithmParameters::RSA(params) => {
+            EncryptionKey::Rsa(Rsa::from_public_components(component(&params.n)?, component(&params.e)?).map_err(internal)?)
+        }
+        AlgorithmParameters::EllipticCurve(params) => {
+            let nid = match params.curve {
+                EllipticCurve::P256 => Nid::X9_62_PRIME256V1,
+                EllipticCurve::P384 => Nid::SECP384R1,
+                EllipticCurve::P521 => Nid::SECP521R1,
+                EllipticCurve::Ed25519 => return Err(no_key()),
+            };
+            let group = EcGroup::from_curve_name(nid).map_err(internal)?;
+            let (x, y) = (component(&params.x)?, component(&params.y)?);
+            let key = EcKey::from_public_key_affine_coordinates(&group, &x, &y).map_err(|_| no_key())?;
+            EncryptionKey::Ec(key)
+        }
+        _ => return Err(no_key()),
+    };
+    Ok((key, jwk.common.key_id.clone()))
+}
+
+/// ECDH-ES key agreement with an ephemeral key on the curve of the client key
+/// (https://www.rfc-editor.org/rfc/rfc7518#section-4.6), returns the content encryption key and the `epk` header
+fn agree_key(key: &EcKey<Public>, enc: &str) -> Result<(Vec<u8>, serde_json::Value), openssl::error::ErrorStack> {
+    let group = key.group();
+    let ephemeral = EcKey::generate(group)?;
+    let (private, peer) = (PKey::from_ec_key(ephemeral.clone())?, PKey::from_ec_key(key.clone())?);
+    let mut deriver = Deriver::new(&private)?;
+    deriver.set_peer(&peer)?;
+    let z = deriver.derive_to_vec()?;
+
+    let mut ctx = BigNumContext::new()?;
+    let (mut x, mut y) = (BigNum::new()?, BigNum::new()?);
+    ephemeral.public_key().affine_coordinates(group, &mut x, &mut y, &mut ctx)?;
+    let len = group.degree().div_ceil(8) as i32;
+    let crv = match group.curve_name() {
+        Some(Nid::SECP384R1) => "P-384",
+        Some(Nid::SECP521R1) => "P-521",
+        _ => "P-256",
+    };
+    let epk = serde_json::json!({
+        "kty": "EC",
+        "crv": crv,
+        "x": BASE64_URL_SAFE_NO_PAD.encode(x.to_vec_padded(len)?),
+        "y": BASE64_URL_SAFE_NO_PAD.encode(y.to_vec_padded(len)?),
+    });
+    Ok((concat_kdf(&z, enc, b"", b"", cek_len(enc)), epk))
+}
+
+/// the Concat KDF with SHA-256 (NIST SP 800-56A), for direct key agreement the algorithm id is `enc`,
+/// the responses of this server send no `apu`/`apv`
+fn concat_kdf(z: &[u8], alg_id: &str, apu: &[u8], apv: &[u8], len: usize) -> Vec<u8> {
+    let mut other_info = Vec::new();
+    for part in [alg_id.as_bytes(), apu, apv] {
+        other_info.extend((part.len() as u32).to_be_bytes());
+        other_info.extend(part);
+    }
+    other_info.extend(((len * 8) as u32).to_be_bytes());
+
+    let mut out = Vec::with_capacity(len + 32);
+    let mut counter = 1u32;
+    while out.len() < len {
+        let mut hasher = Sha256::new();
+        hasher.update(&counter.to_be_bytes());
+        hasher.update(z);
+        hasher.update(&other_info);
+        out.extend(hasher.finish());
+        counter += 1;
+    }
+    out.truncate(len);
+    out
+}
+
+fn cek_len(enc: &str) -> usize {
+    match enc {
+        "A128GCM" => 16,
+        "A256GCM" | "A128CBC-HS256" => 32,
+        _ => 64, // A256CBC-HS512
+    }
+}
+
+fn wrap_key(key: &Rsa<Public>, alg: &str, cek: &[u8]) -> Result<Vec<u8>, openssl::error::ErrorStack> {
+    let pkey = PKey::from_rsa(key.clone())?;
+    let mut ctx = PkeyCtx::new(&pkey)?;
+    ctx.encrypt_init()?;
+    ctx.set_rsa_padding(Padding::PKCS1_OAEP)?;
+    if alg == "RSA-OAEP-256" {
+        ctx.set_rsa_oaep_md(openssl::md::Md::sha256())?;
+        ctx.set_rsa_mgf1_md(openssl::md::Md::sha256())?;
+    }
+    let mut out = Vec::new();
+    ctx.encrypt_to_vec(cek, &mut out)?;
+    Ok(out)
+}
+
+fn hmac(digest: MessageDigest, key: &[u8], parts: &[&[u8]]) -> Result<Vec<u8>, openssl::error::ErrorStack> {
+    let key = PKey::hmac(key)?;
+    let mut signer = Signer::new(digest, &key)?;
+    for part in parts {
+        signer.update(part)?;
+    }
+    signer.sign_to_vec()
+}
+
+fn internal(e: openssl::error::ErrorStack) -> AppError {
+    log::error!("JWE encryption error: {}", e);
+    AppError::InternalError
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::pkey::Private;
+
+    fn client_with_key(key: &Rsa<Private>) -> OauthClient {
+        let b64 = |n: &openssl::bn::BigNumRef| BASE64_URL_SAFE_NO_PAD.encode(n.to_vec());
+        let jwks = serde_json::json!({"keys": [
+            {"kty": "RSA", "use": "sig", "kid": "sig-1", "n": b64(key.n()), "e": b64(key.e())},
+            {"kty": "RSA", "use": "enc", "kid": "enc-1", "n": b64(key.n()), "e": b64(key.e())},
+        ]});
+        OauthClient {
+            id: "test1".into(),
+            secret: String::new(),
+            name: "Test".into(),
+            allowed_scopes: "openid".into(),
+            token_endpoint_auth_method: "private_key_jwt".into(),
+            jwks: Some(serde_json::from_value(jwks).unwrap()),
+            ..Default::default()
+        }
+    }
+
+    /// decrypts with the private key, verifying the authentication tag
+    fn decrypt(key: &Rsa<Private>, jwe: &str) -> (serde_json::Value, Vec<u8>) {
+        let parts: Vec<Vec<u8>> = jwe.split('.').map(|p| BASE64_URL_SAFE_NO_PAD.decode(p).unwrap()).collect();
+        let header: serde_json::Value = serde_json::from_slice(&parts[0]).unwrap();
+        let aad = jwe.split('.').next().unwrap().as_bytes();
+
+        let pkey = PKey::from_rsa(key.clone()).unwrap();
+        let mut ctx = PkeyCtx::new(&pkey).unwrap();
+        ctx.decrypt_init().unwrap();
+        ctx.set_rsa_padding(Padding::PKCS1_OAEP).unwrap();
+        if header["alg"] == "RSA-OAEP-256" {
+            ctx.set_rsa_oaep_md(openssl::md::Md::sha256()).unwrap();
+            ctx.set_rsa_mgf1_md(openssl::md::Md::sha256()).unwrap();
+        }
+        let mut cek = Vec::new();
+        ctx.decrypt_to_vec(&parts[1], &mut cek).unwrap();
+
+        let (iv, ciphertext, tag) = (&parts[2], &parts[3], &parts[4]);
+        let plaintext = match header["enc"].as_str().unwrap() {
+            "A256GCM" => symm::decrypt_aead(Cipher::aes_256_gcm(), &cek, Some(iv), aad, ciphertext, tag).unwrap(),
+            "A128CBC-HS256" => {
+                let (mac_key, enc_key) = cek.split_at(16);
+                let al = ((aad.len() as u64) * 8).to_be_bytes();
+                let mac = hmac(MessageDigest::sha256(), mac_key, &[aad, iv, ciphertext, &al]).unwrap();
+                assert_eq!(&mac[..16], &tag[..]);
+                symm::decrypt(Cipher::aes_128_cbc(), enc_key, Some(iv), ciphertext).unwrap()
+            }
+            enc => panic!("unexpected enc {}", enc),
+        };
+        (header, plaintext)
+    }
+
+    #[test]
+    fn test_encrypt() {
+        let key = Rsa::generate(2048).unwrap();
+        let client = client_with_key(&key);
+
+        let jwe = encrypt(&client, "RSA-OAEP-256", None, Some("JWT"), b"a.b.c").unwrap();
+        let (header, plaintext) = decrypt(&key, &jwe);
+        assert_eq!(
+            header,
+            serde_json::json!({"alg": "RSA-OAEP-256", "enc": "A128CBC-HS256", "kid": "enc-1", "cty": "JWT"})
+        );
+        assert_eq!(plaintext, b"a.b.c");
+
+        let jwe = encrypt(&client, "RSA-OAEP", Some("A256GCM"), None, br#"{"sub":"max"}"#).unwrap();
+        let (header, plaintext) = decrypt(&key, &jwe);
+        assert_eq!(header["enc"], "A256GCM");
+        assert_eq!(plaintext, br#"{"sub":"max"}"#);
+
+        assert!(encrypt(&client, "RSA1_5", None, None, b"x").is_err());
+    }
+
+    #[test]
+    fn test_can_encrypt() {
+        let client = client_with_key(&Rsa::generate(2048).unwrap());
+        assert!(can_encrypt(&client, "RSA-OAEP-256", None));
+        assert!(can_encrypt(&client, "RSA-OAEP", Some("A256GCM")));
+        assert!(!can_encrypt(&client, "RSA1_5", None));
+        assert!(!can_encrypt(&client, "RSA-OAEP", Some("A192GCM")));
+        // the client has no EC key
+        assert!(!can_encrypt(&client, "ECDH-ES", None));
+        assert!(!can_encrypt(&OauthClient { jwks: None, ..client }, "RSA-OAEP", None));
+    }
+
+    #[test]
+    fn test_encrypt_ecdh_es() {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = EcKey::generate(&group).unwrap();
+        let mut ctx = BigNumContext::new().unwrap();
+        let (mut x, mut y) = (BigNum::new().unwrap(), BigNum::new().unwrap());
+        key.public_key().affine_coordinates(&group, &mut x, &mut y, &mut ctx).unwrap();
+        let b64 = |n: &BigNum| BASE64_URL_SAFE_NO_PAD.encode(n.to_vec_padded(32).unwrap());
+        let jwks = serde_json::json!({"keys": [{"kty": "EC", "crv": "P-256", "kid": "ec-1", "x": b64(&x), "y": b64(&y)}]});
+        let client = OauthClient {
+            jwks: Some(serde_json::from_value(jwks).unwrap()),
+            ..client_with_key(&Rsa::generate(2048).unwrap())
+        };
+
+        // the JWKS holds only the EC key, which has no `use`
+        let jwe = encrypt(&client, "ECDH-ES", Some("A256GCM"), None, b"a.b.c").unwrap();
+        let parts: Vec<Vec<u8>> = jwe.split('.').map(|p| BASE64_URL_SAFE_NO_PAD.decode(p).unwrap()).collect();
+        let header: serde_json::Value = serde_json::from_slice(&parts[0]).unwrap();
+        assert_eq!(header["kid"], "ec-1");
+        assert_eq!(header["epk"]["crv"], "P-256");
+        assert!(parts[1].is_empty());
+
+        let component = |v: &serde_json::Value| BigNum::from_slice(&BASE64_URL_SAFE_NO_PAD.decode(v.as_str().unwrap()).unwrap()).unwrap();
+        let epk = EcKey::from_public_key_affine_coordinates(&group, &component(&header["epk"]["x"]), &component(&header["epk"]["y"])).unwrap();
+        let (private, peer) = (PKey::from_ec_key(key).unwrap(), PKey::from_ec_key(epk).unwrap());
+        let mut deriver = Deriver::new(&private).unwrap();
+        deriver.set_peer(&peer).unwrap();
+        let cek = concat_kdf(&deriver.derive_to_vec().unwrap(), "A256GCM", b"", b"", 32);
+
+        let aad = jwe.split('.').next().unwrap().as_bytes();
+        let plaintext = symm::decrypt_aead(Cipher::aes_256_gcm(), &cek, Some(&parts[2]), aad, &parts[3], &parts[4]).unwrap();
+        assert_eq!(plaintext, b"a.b.c");
+    }
+
+    /// https://www.rfc-editor.org/rfc/rfc7518#appendix-C
+    #[test]
+    fn test_concat_kdf_known_answer() {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let component = |v: &str| BigNum::from_slice(&BASE64_URL_SAFE_NO_PAD.decode(v).unwrap()).unwrap();
+        let alice = EcKey::from_public_key_affine_coordinates(
+            &group,
+            &component("gI0GAILBdu7T53akrFmMyGcsF3n5dO7MmwNBHKW5SV0"),
+            &component("SLW_xSffzlPWrHEVI30DHM_4egVwt3NQqeUD7nMFpps"),
+        )
+        .unwrap();
+        let bob = EcKey::from_public_key_affine_coordinates(
+            &group,
+            &component("weNJy2HscCSM6AEDTDg04biOvhFhyyWvOHQfeF_PxMQ"),
+            &component("e8lnCO-AlStT-NJVX-crhB7QRYhiix03illJOVAOyck"),
+        )
+        .unwrap();
+        let bob = EcKey::from_private_components(&group, &component("VEmDZpDXXK8p8N0Cndsxs924q6nS1RXFASRl6BfUqdw"), bob.public_key()).unwrap();
+
+        let (private, peer) = (PKey::from_ec_key(bob).unwrap(), PKey::from_ec_key(alice).unwrap());
+        let mut deriver = Deriver::new(&private).unwrap();
+        deriver.set_peer(&peer).unwrap();
+        let z = deriver.derive_to_vec().unwrap();
+        assert_eq!(
+            z,
+            [
+                158, 86, 217, 29, 129, 113, 53, 211, 114, 131, 66, 131, 191, 132, 38, 156, 251, 49, 110, 163, 218, 128, 106, 72, 246, 218, 167, 121,
+                140, 254, 144, 196
+            ]
+        );
+
+        let cek = concat_kdf(&z, "A128GCM", b"Alice", b"Bob", 16);
+        assert_eq!(BASE64_URL_SAFE_NO_PAD.encode(cek), "VqqN6vgjbSBcIijNcacQGg");
+    }
+
+    /// https://www.rfc-editor.org/rfc/rfc7516#appendix-B
+    #[test]
+    fn test_encrypt_content_known_answer() {
+        let cek = [
+            4, 211, 31, 197, 84, 157, 252, 254, 11, 100, 157, 250, 63, 170, 106, 206, 107, 124, 212, 45, 111, 107, 9, 219, 200, 177, 0, 240, 143,
+            156, 44, 207,
+        ];
+        let iv = [3, 22, 60, 12, 43, 67, 104, 105, 108, 108, 105, 99, 111, 116, 104, 101];
+        let aad = b"eyJhbGciOiJBMTI4S1ciLCJlbmMiOiJBMTI4Q0JDLUhTMjU2In0";
+
+        let (ciphertext, tag) = encrypt_content("A128CBC-HS256", &cek, &iv, aad, b"Live long and prosper.").unwrap();
+        assert_eq!(BASE64_URL_SAFE_NO_PAD.encode(ciphertext), "KDlTtXchhZTGufMYmOYGS4HffxPSUrfmqCHXaI9wOGY");
+        assert_eq!(BASE64_URL_SAFE_NO_PAD.encode(tag), "U0m_YmjN04DJvceFICbCVQ");
+    }
+}
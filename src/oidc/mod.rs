@@ -1,9 +1,19 @@
+pub mod acr;
 pub mod authorize;
+pub mod claims;
 pub mod discovery;
 //pub mod dynamic_registration; TODO
-mod common;
+mod client_auth;
+mod fetch;
 pub mod introspection;
+mod jwe;
 pub mod jwks;
+pub mod par;
+mod pkce;
+mod request_object;
+pub mod revocation;
+mod signing;
+mod subject;
 pub mod token;
 pub mod userinfo;
 
@@ -0,0 +1,102 @@
+//! Pushed Authorization Requests (https://www.rfc-editor.org/rfc/rfc9126)
+//!
+//! the client pushes the authorization request parameters directly, and references them later with `request_uri`.
+use crate::core;
+use crate::core::models::PushedRequest;
+use crate::core::{AppState, OauthError};
+use crate::oidc::authorize::{self, AuthParams};
+use crate::oidc::client_auth::{validate_client_credentials, ClientAuthParams};
+use crate::oidc::request_object;
+use actix_web::http::StatusCode;
+use actix_web::web::{Data, Form};
+use actix_web::{HttpRequest, HttpResponse, Result};
+use chrono::{offset::Utc, Duration};
+
+/// https://www.rfc-editor.org/rfc/rfc9126#section-2.2
+pub const REQUEST_URI_PREFIX: &str = "urn:ietf:params:oauth:request_uri:";
+
+#[derive(Deserialize, Debug)]
+pub struct ParParams {
+    #[serde(flatten)]
+    pub auth: AuthParams,
+    pub client_secret: Option<String>,
+    pub client_assertion_type: Option<String>,
+    pub client_assertion: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ParResponse {
+    request_uri: String,
+    expires_in: i64,
+}
+
+/// POST /par
+pub async fn push_request((data, state, req): (Form<ParParams>, Data<AppState>, HttpRequest)) -> Result<HttpResponse> {
+    let client_auth = ClientAuthParams {
+        client_id: data.auth.client_id.clone(),
+        client_secret: data.client_secret.clone(),
+        client_assertion_type: data.client_assertion_type.clone(),
+        client_assertion: data.client_assertion.clone(),
+    };
+    let client = match validate_client_credentials(&req, &state, &client_auth) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("par: invalid client credentials: {}", e);
+            return core::send_json(StatusCode::UNAUTHORIZED, OauthError::of("invalid_client"));
+        }
+    };
+
+    if data.auth.request_uri.is_some() {
+        return par_error(OauthError::new("invalid_request", "'request_uri' must not be pushed"));
+    }
+
+    // the request is validated as on the authorization endpoint (https://www.rfc-editor.org/rfc/rfc9126#section-2.1)
+    let mut params = data.auth.clone();
+    params.client_id = Some(client.id.clone());
+    let params = match request_object::resolve(&params, &state).await {
+        Ok(p) => p,
+        Err(e) => return par_error(e),
+    };
+    if let Err(e) = authorize::validate_auth(&params, &state).await? {
+        return par_error(e);
+    }
+
+    let request_uri = format!("{}{}", REQUEST_URI_PREFIX, uuid::Uuid::new_v4());
+    let expires_in = state.config.oauth.par_exp;
+    state
+        .oauth_db
+        .save_pushed_request(&PushedRequest {
+            request_uri: request_uri.clone(),
+            client_id: client.id.clone(),
+            params: serde_json::to_string(&params)?,
+            expiration: Utc::now().naive_utc() + Duration::seconds(expires_in),
+        })
+        .map_err(|e| e.to_user())?;
+    debug!("par: request of client {} saved as {}", client.id, request_uri);
+
+    core::send_json(StatusCode::CREATED, ParResponse { request_uri, expires_in })
+}
+
+/// loads the parameters pushed by the client, `request_uri` stays set to mark them as pushed
+pub(crate) fn load(request_uri: &str, client_id: Option<&str>, state: &AppState) -> Result<AuthParams, OauthError> {
+    let invalid = || OauthError::new("invalid_request_uri", "invalid or expired 'request_uri'");
+
+    // a request of another client is not found, and stays usable for its owner
+    let client_id = client_id.ok_or_else(invalid)?;
+    let pushed = state.oauth_db.consume_pushed_request(request_uri, client_id).map_err(|e| {
+        info!("par: request {} not loaded: {}", request_uri, e);
+        invalid()
+    })?;
+    if pushed.expiration < Utc::now().naive_utc() {
+        return Err(invalid());
+    }
+
+    let mut params: AuthParams = serde_json::from_str(&pushed.params).map_err(|_| invalid())?;
+    params.request_uri = Some(request_uri.to_string());
+    Ok(params)
+}
+
+fn par_error(err: OauthError) -> Result<HttpResponse> {
+    info!("par error: {:?}", err);
+    core::send_json(StatusCode::BAD_REQUEST, err)
+}
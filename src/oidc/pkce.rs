@@ -0,0 +1,53 @@
+//! Proof Key for Code Exchange (https://www.rfc-editor.org/rfc/rfc7636)
+use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+use base64::Engine;
+use openssl::sha::sha256;
+
+pub static CODE_CHALLENGE_METHODS: [&str; 2] = ["S256", "plain"];
+
+/// checks the syntax of a code_challenge or code_verifier (https://www.rfc-editor.org/rfc/rfc7636#section-4.1)
+pub fn is_valid_format(value: &str) -> bool {
+    (43..=128).contains(&value.len())
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.' || c == '_' || c == '~')
+}
+
+/// verifies the code_verifier against the challenge stored with the auth code
+pub fn verify(challenge: &str, method: &str, verifier: &str) -> bool {
+    if !is_valid_format(verifier) {
+        return false;
+    }
+    match method {
+        "S256" => BASE64_URL_SAFE_NO_PAD.encode(sha256(verifier.as_bytes())) == challenge,
+        "plain" => verifier == challenge,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VERIFIER: &str = "dBjftJeZ4CVP-mJ92K3fE5vOcNBK7z8aRcRAHJiakWk";
+    const CHALLENGE: &str = "BGRdnTHjMfKkm_fK-RIa_dCS3vi4GjsEkjaKJgwLoEo";
+
+    #[test]
+    fn test_verify_s256() {
+        assert!(verify(CHALLENGE, "S256", VERIFIER));
+        assert!(!verify(CHALLENGE, "S256", "dBjftJeZ4CVP-mJ92K3fE5vOcNBK7z8aRcRAHJiakWl"));
+    }
+
+    #[test]
+    fn test_verify_plain() {
+        assert!(verify(VERIFIER, "plain", VERIFIER));
+        assert!(!verify(VERIFIER, "unknown", VERIFIER));
+    }
+
+    #[test]
+    fn test_verifier_format() {
+        assert!(!is_valid_format("too-short"));
+        assert!(!is_valid_format(&"a".repeat(129)));
+        assert!(!is_valid_format(&format!("{}+", "a".repeat(43))));
+    }
+}
@@ -0,0 +1,102 @@
+//! Request Objects (https://www.rfc-editor.org/rfc/rfc9101)
+//!
+//! the authorization request parameters are sent in a JWT signed by the client, so they can not be tampered with.
+//! the JWT is passed by value (`request`), or by reference (`request_uri`) to one of the client's registered `request_uris`.
+use crate::core::models::OauthClient;
+use crate::core::{AppState, OauthError};
+use crate::oidc::authorize::AuthParams;
+use crate::oidc::client_auth::{client_decoding_key, ASSERTION_SIGNING_ALGS};
+use crate::oidc::{fetch, par};
+use jwt::{decode, decode_header, Validation};
+use serde_json::Value;
+use url::Url;
+
+/// replaces the plain parameters with the ones of the verified `request` object, or of the pushed request
+///
+/// only the parameters of the request object are used (https://www.rfc-editor.org/rfc/rfc9101#section-6.3)
+pub(crate) async fn resolve(data: &AuthParams, state: &AppState) -> Result<AuthParams, OauthError> {
+    let request_uri = match (data.request.as_deref(), data.request_uri.as_deref()) {
+        (None, None) => return Ok(data.clone()),
+        (Some(_), Some(_)) => return Err(invalid("'request' and 'request_uri' must not be used together")),
+        (None, Some(uri)) if uri.starts_with(par::REQUEST_URI_PREFIX) => return par::load(uri, data.client_id.as_deref(), state),
+        (None, Some(uri)) => Some(uri),
+        (Some(_), None) => None,
+    };
+
+    let client_id = data.client_id.as_deref().ok_or_else(|| invalid("'client_id' is required"))?;
+    let client = state
+        .oauth_db
+        .fetch_client_config(client_id)
+        .map_err(|_| invalid("Unknown or invalid client_id"))?;
+
+    let request = match request_uri {
+        Some(uri) => fetch(&client, uri).await?,
+        None => data.request.clone().unwrap_or_default(),
+    };
+    let claims = verify(&client, &request, &state.config.oauth.issuer)?;
+    if claims.get("client_id").and_then(Value::as_str).is_some_and(|id| id != client_id) {
+        return Err(invalid("'client_id' does not match the request object"));
+    }
+
+    // the parameters are strings, except the JSON ones (e.g. max_age)
+    let params: serde_json::Map<String, Value> = claims
+        .into_iter()
+        .map(|(k, v)| match v {
+            Value::String(_) => (k, v),
+            other => (k, Value::String(other.to_string())),
+        })
+        .collect();
+    let mut resolved: AuthParams = serde_json::from_value(Value::Object(params)).map_err(|e| invalid(&e.to_string()))?;
+    resolved.client_id = Some(client_id.to_string());
+    resolved.request = Some(request);
+    resolved.request_uri = None;
+    debug!("request object of client {} verified", client_id);
+    Ok(resolved)
+}
+
+fn verify(client: &OauthClient, request: &str, issuer: &str) -> Result<serde_json::Map<String, Value>, OauthError> {
+    let header = decode_header(request).map_err(|e| invalid(&e.to_string()))?;
+    if !ASSERTION_SIGNING_ALGS.contains(&header.alg) {
+        return Err(invalid(&format!("alg {:?} not allowed", header.alg)));
+    }
+    let key = client_decoding_key(client, &header).map_err(|e| invalid(&e))?;
+
+    // iss & aud are not required, but must be valid if present (https://www.rfc-editor.org/rfc/rfc9101#section-4)
+    let mut validation = Validation::new(header.alg);
+    validation.set_required_spec_claims::<&str>(&[]);
+    validation.set_issuer(&[&client.id]);
+    validation.set_audience(&[issuer]);
+
+    decode::<serde_json::Map<String, Value>>(request, &key, &validation)
+        .map(|data| data.claims)
+        .map_err(|e| {
+            info!("invalid request object of client {}: {}", client.id, e);
+            invalid("invalid request object")
+        })
+}
+
+/// fetches the request object passed by reference (https://www.rfc-editor.org/rfc/rfc9101#section-5.2.3)
+///
+/// only the client's registered `request_uris` are fetched, the provider must not be used to reach any other host
+async fn fetch(client: &OauthClient, request_uri: &str) -> Result<String, OauthError> {
+    let invalid_uri = |description: &str| OauthError::new("invalid_request_uri", description);
+
+    // the fragment is not sent, it is not part of the registered URI
+    let uri = request_uri.split('#').next().unwrap_or_default();
+    if !client.request_uris.iter().any(|registered| registered == uri) {
+        return Err(invalid_uri("'request_uri' is not registered for the client"));
+    }
+    let url = Url::parse(uri).map_err(|_| invalid_uri("invalid 'request_uri'"))?;
+    if url.scheme() != "https" {
+        return Err(invalid_uri("'request_uri' must use https"));
+    }
+
+    fetch::https_get(&url, "application/oauth-authz-req+jwt").await.map_err(|e| {
+        info!("failed to fetch the request object of client {} from {}: {}", client.id, uri, e);
+        invalid_uri("the request object could not be fetched")
+    })
+}
+
+fn invalid(description: &str) -> OauthError {
+    OauthError::new("invalid_request_object", description)
+}
@@ -0,0 +1,60 @@
+use crate::core::error::InternalError;
+use crate::core::{error::AppError, send_json, AppState, OauthError};
+use crate::oidc::client_auth::{validate_client_credentials, ClientAuthParams};
+use actix_web::http::StatusCode;
+use actix_web::web::{Data, Form};
+use actix_web::{HttpRequest, HttpResponse, Result};
+
+#[derive(Deserialize, Debug)]
+pub struct RevokeParams {
+    pub token: String,
+    pub token_type_hint: Option<String>,
+    #[serde(flatten)]
+    pub client_auth: ClientAuthParams,
+}
+
+/// POST /oauth2/revoke
+///
+/// https://www.rfc-editor.org/rfc/rfc7009
+pub async fn revoke((params, state, req): (Form<RevokeParams>, Data<AppState>, HttpRequest)) -> Result<HttpResponse> {
+    debug!("revoke(hint: {:?})", params.token_type_hint);
+
+    let client = match validate_client_credentials(&req, &state, &params.client_auth) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("revoke: invalid client credentials: {}", e);
+            Err(AppError::Unauthorized)?
+        }
+    };
+
+    // the hint (even an unknown one) is only an optimization for the lookup, the token is found by its value anyway (rfc7009#section-2.1)
+    let token_data = match state.oauth_db.load_token_data(&params.token) {
+        Ok(t) => t,
+        Err(InternalError::NotFound) => {
+            debug!("revoke: unknown token");
+            return Ok(HttpResponse::Ok().finish()); // invalid tokens do not cause an error (rfc7009#section-2.2)
+        }
+        Err(e) => Err(e.to_user())?,
+    };
+
+    if token_data.client_id != client.id {
+        warn!("revoke: client {} tried to revoke a token of client {}", client.id, token_data.client_id);
+        return send_json(
+            StatusCode::BAD_REQUEST,
+            OauthError::new("unauthorized_client", "token was issued to another client"),
+        );
+    }
+
+    match (token_data.token_type.as_str(), token_data.family.as_deref()) {
+        // revoking a refresh token invalidates all tokens issued from the same grant
+        ("refresh", Some(family)) => {
+            state.oauth_db.revoke_token_family(family).map_err(|e| e.to_user())?;
+        }
+        _ => {
+            state.oauth_db.revoke_token(&token_data.token).map_err(|e| e.to_user())?;
+        }
+    }
+    info!("revoke: {} token of client {} revoked", token_data.token_type, client.id);
+
+    Ok(HttpResponse::Ok().insert_header(("Cache-Control", "no-store")).finish())
+}
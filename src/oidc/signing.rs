@@ -0,0 +1,86 @@
+use crate::core::error::AppError;
+use crate::core::AppState;
+use base64::prelude::*;
+use jwt::{encode, Algorithm, AlgorithmFamily, Header};
+use openssl::sha::{sha256, sha384, sha512};
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// signs the claims with the first key configured for `alg` in `available_signing` that fits the alg
+///
+/// `purpose` (e.g. "id_token") is only used in the error messages
+pub(crate) fn sign<T: Serialize>(
+    state: &AppState,
+    alg: Algorithm,
+    available_signing: &HashMap<Algorithm, Vec<String>>,
+    typ: Option<&str>,
+    claims: &T,
+    purpose: &str,
+) -> Result<String, AppError> {
+    let key_names = available_signing
+        .get(&alg)
+        .ok_or_else(|| AppError::bad_config(format!("alg '{:?}' not configured as available for signing {}", alg, purpose)))?;
+    let (key_name, secret) = key_names
+        .iter()
+        .filter_map(|name| state.secrets.get(name).map(|s| (name, s)))
+        .find(|(_, secret)| fits(&secret.kind, alg))
+        .ok_or_else(|| {
+            log::error!("no secret of {:?} found for signing {} with alg '{:?}'", key_names, purpose, alg);
+            AppError::bad_config(format!("no secret configured for signing {} with alg '{:?}'", purpose, alg))
+        })?;
+
+    let mut header = Header::new(alg);
+    header.kid = Some(key_name.to_string());
+    if let Some(t) = typ {
+        header.typ = Some(t.to_string());
+    }
+    encode(&header, claims, &secret.key).map_err(|e| {
+        log::error!("JWT encoding error. (alg: {:?}) (cause: {})", alg, e);
+        AppError::InternalError
+    })
+}
+
+/// a key configured in `available_signing` can sign with `alg`
+pub(crate) fn can_sign(state: &AppState, alg: Algorithm, available_signing: &HashMap<Algorithm, Vec<String>>) -> bool {
+    available_signing
+        .get(&alg)
+        .into_iter()
+        .flatten()
+        .filter_map(|name| state.secrets.get(name))
+        .any(|secret| fits(&secret.kind, alg))
+}
+
+/// the secret kind (`SECRET`, `RSA`, `EC`, `ED`) needed by `alg`
+fn fits(kind: &str, alg: Algorithm) -> bool {
+    kind == match alg.family() {
+        AlgorithmFamily::Hmac => "SECRET",
+        AlgorithmFamily::Rsa => "RSA",
+        AlgorithmFamily::Ec => "EC",
+        AlgorithmFamily::Ed => "ED",
+    }
+}
+
+/// the base64url encoded left-most half of the hash of `value`, as used by `at_hash` and `c_hash`
+///
+/// the hash function is the one used by `alg` (https://openid.net/specs/openid-connect-core-1_0.html#CodeIDToken)
+pub(crate) fn half_hash(alg: Algorithm, value: &str) -> String {
+    use Algorithm::*;
+    let digest: Vec<u8> = match alg {
+        HS256 | RS256 | PS256 | ES256 => sha256(value.as_bytes()).to_vec(),
+        HS384 | RS384 | PS384 | ES384 => sha384(value.as_bytes()).to_vec(),
+        HS512 | RS512 | PS512 | EdDSA => sha512(value.as_bytes()).to_vec(), // Ed25519 uses SHA-512
+    };
+    BASE64_URL_SAFE_NO_PAD.encode(&digest[..digest.len() / 2])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_half_hash() {
+        assert_eq!(half_hash(Algorithm::RS256, "jHkWEdUXMU1BwAsC4vtUsZwnNCVzKtkr"), "Bp8mTJ6xeTJw18ZZ0lMjvA");
+        assert_eq!(half_hash(Algorithm::ES384, "abc").len(), 32); // 24 bytes
+        assert_eq!(half_hash(Algorithm::EdDSA, "abc").len(), 43); // 32 bytes
+    }
+}
@@ -0,0 +1,213 @@
+//! the subject identifiers released to the clients, https://openid.net/specs/openid-connect-core-1_0.html#SubjectIDTypes
+//!
+//! the tokens and sessions always keep the internal `users.id`, the pairwise value is only computed when a `sub` is
+//! released, so every stored `subject` still resolves to the user.
+use crate::core::config::OauthConfig;
+use crate::core::error::AppError;
+use crate::core::models::OauthClient;
+use crate::oidc::fetch;
+use base64::prelude::*;
+use openssl::sha::Sha256;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// how long the check of a `sector_identifier_uri` is kept, a failed check is retried sooner
+const SECTOR_IDENTIFIER_TTL: Duration = Duration::from_secs(24 * 3600);
+const SECTOR_IDENTIFIER_RETRY: Duration = Duration::from_secs(60);
+
+/// when the check of a `sector_identifier_uri` expires and its result
+type SectorIdentifierCheck = (Instant, Result<(), String>);
+
+/// the checked `sector_identifier_uri`s by client registration
+static SECTOR_IDENTIFIERS: LazyLock<Mutex<HashMap<String, SectorIdentifierCheck>>> = LazyLock::new(Default::default);
+
+/// the `sub` of `user_id` for the client: the user id for `public` clients,
+/// a hash of the sector identifier, the user id and the configured salt for `pairwise` clients
+pub(crate) fn for_client(cfg: &OauthConfig, client: &OauthClient, user_id: &str) -> Result<String, AppError> {
+    if !client.is_pairwise() {
+        return Ok(user_id.to_string());
+    }
+    let salt = cfg
+        .pairwise_salt
+        .as_deref()
+        .ok_or_else(|| AppError::bad_config("'pairwise_salt' is required for pairwise clients"))?;
+    let sector = sector_identifier(client).map_err(AppError::bad_config)?;
+    Ok(pairwise(&sector, user_id, salt))
+}
+
+/// the pairwise subject can be computed for the client
+///
+/// the `sector_identifier_uri` must list all redirect URIs of the client, so a client can not join the sector
+/// (and get the `sub` values) of another one (https://openid.net/specs/openid-connect-core-1_0.html#PairwiseAlg)
+pub(crate) async fn validate(cfg: &OauthConfig, client: &OauthClient) -> Result<(), String> {
+    if !client.is_pairwise() {
+        return Ok(());
+    }
+    if cfg.pairwise_salt.is_none() {
+        return Err("pairwise subjects are not supported".into());
+    }
+    sector_identifier(client)?;
+
+    match client.sector_identifier_uri.as_deref() {
+        Some(uri) => verify_sector_identifier_uri(client, uri).await,
+        None => Ok(()),
+    }
+}
+
+/// fetches the `sector_identifier_uri` once per client registration, not on every authorization request
+async fn verify_sector_identifier_uri(client: &OauthClient, uri: &str) -> Result<(), String> {
+    // a changed registration is checked again
+    let key = format!("{} {} {}", client.id, uri, client.callback_url.join(" "));
+    let now = Instant::now();
+    if let Some((_, result)) = SECTOR_IDENTIFIERS.lock().unwrap().get(&key).filter(|(expires, _)| *expires > now) {
+        return result.clone();
+    }
+
+    let result = match Url::parse(uri) {
+        Ok(url) => match fetch::https_get(&url, "application/json").await {
+            Ok(document) => check_redirect_uris(client, &document),
+            Err(e) => Err(format!("client {}: failed to fetch {}: {}", client.id, uri, e)),
+        },
+        Err(_) => Err(format!("client {}: invalid 'sector_identifier_uri'", client.id)),
+    };
+    let ttl = if result.is_ok() {
+        SECTOR_IDENTIFIER_TTL
+    } else {
+        SECTOR_IDENTIFIER_RETRY
+    };
+    let mut cache = SECTOR_IDENTIFIERS.lock().unwrap();
+    cache.retain(|_, (expires, _)| *expires > now);
+    cache.insert(key, (now + ttl, result.clone()));
+    result
+}
+
+/// the sector identifier document is a JSON array of redirect URIs
+fn check_redirect_uris(client: &OauthClient, document: &str) -> Result<(), String> {
+    let listed: Vec<String> =
+        serde_json::from_str(document).map_err(|_| format!("client {}: the 'sector_identifier_uri' is not a JSON array of URIs", client.id))?;
+    match client.callback_url.iter().find(|uri| !listed.contains(uri)) {
+        Some(uri) => Err(format!("client {}: '{}' is not listed by the 'sector_identifier_uri'", client.id, uri)),
+        None => Ok(()),
+    }
+}
+
+/// the host of the `sector_identifier_uri`, or else the single host of the redirect URIs
+///
+/// https://openid.net/specs/openid-connect-core-1_0.html#PairwiseAlg
+fn sector_identifier(client: &OauthClient) -> Result<String, String> {
+    let uris: Vec<&str> = match client.sector_identifier_uri.as_deref() {
+        Some(uri) => vec![uri],
+        None => client.callback_url.iter().map(String::as_str).collect(),
+    };
+    let hosts = uris
+        .iter()
+        .map(|uri| Url::parse(uri).ok().and_then(|u| u.host_str().map(String::from)).ok_or(*uri))
+        .collect::<Result<BTreeSet<String>, &str>>()
+        .map_err(|uri| format!("client {}: no host in '{}'", client.id, uri))?;
+
+    match hosts.len() {
+        1 => Ok(hosts.into_iter().next().unwrap_or_default()),
+        0 => Err(format!("client {} has no redirect URIs", client.id)),
+        _ => Err(format!(
+            "client {}: the redirect URIs have different hosts, 'sector_identifier_uri' is required",
+            client.id
+        )),
+    }
+}
+
+fn pairwise(sector: &str, user_id: &str, salt: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(sector.as_bytes());
+    hasher.update(user_id.as_bytes());
+    hasher.update(salt.as_bytes());
+    BASE64_URL_SAFE_NO_PAD.encode(hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(id: &str, callback_url: &[&str], sector_identifier_uri: Option<&str>) -> OauthClient {
+        OauthClient {
+            id: id.into(),
+            secret: String::new(),
+            name: id.into(),
+            callback_url: callback_url.iter().map(|u| u.to_string()).collect(),
+            allowed_scopes: "openid".into(),
+            subject_type: "pairwise".into(),
+            sector_identifier_uri: sector_identifier_uri.map(String::from),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_sector_identifier() {
+        let web = client("web", &["https://app.example.com/cb", "https://app.example.com/other"], None);
+        assert_eq!(sector_identifier(&web).unwrap(), "app.example.com");
+
+        let multi = client("multi", &["https://a.example.com/cb", "https://b.example.com/cb"], None);
+        assert!(sector_identifier(&multi).is_err());
+        let multi = client(
+            "multi",
+            &multi.callback_url.iter().map(String::as_str).collect::<Vec<_>>(),
+            Some("https://example.com/sector.json"),
+        );
+        assert_eq!(sector_identifier(&multi).unwrap(), "example.com");
+    }
+
+    #[test]
+    fn test_check_redirect_uris() {
+        let multi = client(
+            "multi",
+            &["https://a.example.com/cb", "https://b.example.com/cb"],
+            Some("https://example.com/sector.json"),
+        );
+        let listed = r#"["https://a.example.com/cb", "https://b.example.com/cb", "https://c.example.com/cb"]"#;
+        assert!(check_redirect_uris(&multi, listed).is_ok());
+
+        // the sector of another client
+        assert!(check_redirect_uris(&multi, r#"["https://a.example.com/cb"]"#).is_err());
+        assert!(check_redirect_uris(&multi, r#"{"redirect_uris": []}"#).is_err());
+    }
+
+    #[actix_rt::test]
+    async fn test_verify_sector_identifier_uri_cached() {
+        // the server closes every connection, the fetch fails
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let uri = format!("https://127.0.0.1:{}/sector.json", listener.local_addr().unwrap().port());
+        let connections = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = connections.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                drop(stream);
+            }
+        });
+
+        let multi = client("cached", &["https://a.example.com/cb", "https://b.example.com/cb"], Some(&uri));
+        assert!(verify_sector_identifier_uri(&multi, &uri).await.is_err());
+        assert!(verify_sector_identifier_uri(&multi, &uri).await.is_err());
+        assert_eq!(connections.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        // another registration is fetched again
+        let other = client("cached", &["https://a.example.com/cb"], Some(&uri));
+        assert!(verify_sector_identifier_uri(&other, &uri).await.is_err());
+        assert_eq!(connections.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_pairwise() {
+        let app = client("app", &["https://app.example.com/cb"], None);
+        let app2 = client("app2", &["https://app.example.com/cb2"], None);
+        let partner = client("partner", &["https://partner.example.org/cb"], None);
+
+        // the same sector gets the same sub, other sectors a different one
+        assert_eq!(pairwise("app.example.com", "max", "salt"), pairwise("app.example.com", "max", "salt"));
+        assert_ne!(pairwise("app.example.com", "max", "salt"), pairwise("app.example.com", "max", "pepper"));
+        assert_eq!(sector_identifier(&app), sector_identifier(&app2));
+        assert_ne!(sector_identifier(&app), sector_identifier(&partner));
+        assert_eq!(pairwise("app.example.com", "max", "salt").len(), 43);
+    }
+}
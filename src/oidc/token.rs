@@ -1,19 +1,26 @@
 use crate::core;
-use crate::core::error::AppError::InternalError;
-use crate::core::{error::AppError, models::OauthSession, models::OauthToken, AppState};
-use crate::oidc::common::validate_client_credentials;
+use crate::core::models::{OauthClient, OauthSession, OauthToken};
+use crate::core::{error::AppError, AppState, OauthError};
+use crate::oidc::claims::{self, ClaimsRequest};
+use crate::oidc::client_auth::{validate_client_credentials, ClientAuthParams};
+use crate::oidc::{jwe, pkce, signing, subject};
 use actix_web::web::{Data, Form};
 use actix_web::{http::StatusCode, HttpRequest, HttpResponse, Result};
 use chrono::{offset::Utc, Duration};
-use jwt::{encode, Header};
 use rand::distr::Alphanumeric;
 use rand::RngExt;
+use std::collections::HashSet;
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct TokenParams {
     pub grant_type: String,
-    pub code: String,
-    pub redirect_uri: String,
+    pub code: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub refresh_token: Option<String>,
+    pub scope: Option<String>,
+    pub code_verifier: Option<String>,
+    #[serde(flatten)]
+    pub client_auth: ClientAuthParams,
 }
 
 /// POST /token
@@ -22,113 +29,395 @@ pub struct TokenParams {
 pub async fn token_endpoint((data, state, req): (Form<TokenParams>, Data<AppState>, HttpRequest)) -> Result<HttpResponse> {
     debug!("form: [{:?}]", data);
 
-    match validate_client_credentials(&req, &state) {
-        Ok(_) => debug!("introspect: valid credentials"),
+    let client = match validate_client_credentials(&req, &state, &data.client_auth) {
+        Ok(c) => {
+            debug!("token: valid credentials");
+            c
+        }
         Err(e) => {
-            error!("introspect: invalid client credentials: {}", e);
+            error!("token: invalid client credentials: {}", e);
             Err(AppError::Unauthorized)?
         }
-    }
+    };
 
     match data.grant_type.to_lowercase().as_ref() {
-        "authorization_code" => {
-            let session = state.oauth_db.consume_oauth_session_by_code(&data.code)?;
+        "authorization_code" => exchange_auth_code(&data, &state, &client),
+        "refresh_token" => refresh(&data, &state, &client),
+        "client_credentials" => client_credentials(&data, &state, &client),
+        _ => {
+            error!("token({}) = nok", data.grant_type);
+            Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("'grant_type' not supported"))
+        }
+    }
+}
 
-            core::validate(session.expiration > Utc::now().naive_utc(), "Expired code")?;
+fn exchange_auth_code(data: &TokenParams, state: &AppState, client: &OauthClient) -> Result<HttpResponse> {
+    let code = data.code.as_deref().ok_or_else(|| AppError::bad_req("'code' is required"))?;
+    let redirect_uri = data
+        .redirect_uri
+        .as_ref()
+        .ok_or_else(|| AppError::bad_req("'redirect_uri' is required"))?;
 
-            let client = state
-                .oauth_db
-                .fetch_client_config(&session.client_id)
-                .map_err(|_| AppError::bad_req("failed to load the client config"))?;
+    let session = match state.oauth_db.consume_oauth_session_by_code(code) {
+        Ok(s) => s,
+        Err(core::InternalError::NotFound) => return token_error("invalid_grant", "invalid authorization code"),
+        Err(e) => Err(e.to_user())?,
+    };
 
-            if !client.callback_url.contains(&data.redirect_uri) {
-                return Err(AppError::bad_req("redirect_uri mismatch"))?;
-            }
+    // https://www.rfc-editor.org/rfc/rfc6749#section-4.1.2: the tokens issued from a replayed code are revoked
+    if session.consumed {
+        warn!(
+            "security event: authorization code replay (client: {}, code issued to: {}, subject: {})",
+            client.id, session.client_id, session.subject
+        );
+        if let Some(family) = session.family.as_deref() {
+            let revoked = state.oauth_db.revoke_token_family(family).map_err(|e| e.to_user())?;
+            warn!("revoked {} tokens of family {} issued from the replayed code", revoked, family);
+        }
+        return token_error("invalid_grant", "authorization code already used");
+    }
 
-            debug!("exchange_auth_code({},{}) = ok", data.grant_type, data.code);
-
-            // todo add JWT support for access_token
-            let access_token: String = rand::rng().sample_iter(&Alphanumeric).take(30).map(char::from).collect::<String>();
-
-            // todo fix scope check
-            let id_token = if session.scopes.contains("openid") {
-                Some(build_id_token(&state, &session)?)
-            } else {
-                None
-            };
-
-            // todo refresh token on "offline_access" scope
-
-            // save access_token to db
-            state
-                .oauth_db
-                .save_oauth_token(&OauthToken {
-                    token: access_token.clone(),
-                    token_type: "access".to_string(),
-                    client_id: session.client_id,
-                    scopes: Some(session.scopes),
-                    subject: Some(session.subject), // set on login
-                    expiration: Some(state.config.oauth.token_exp),
-                    created: Utc::now().naive_utc(),
-                })
-                .map_err(|e| e.to_user())?;
-
-            core::json_ok(TokenResponse {
-                access_token,
-                refresh_token: Option::None,
-                token_type: "Bearer".into(),
-                expires_in: state.config.oauth.token_exp,
-                id_token: id_token.into(),
-            })
+    core::validate(session.expiration > Utc::now().naive_utc(), "Expired code")?;
+    if session.client_id != client.id {
+        return token_error("invalid_grant", "code was issued to another client");
+    }
+    if client.is_public() && session.code_challenge.is_none() {
+        return token_error("invalid_grant", "public clients must use PKCE");
+    }
+
+    // https://www.rfc-editor.org/rfc/rfc7636#section-4.6
+    match (session.code_challenge.as_deref(), data.code_verifier.as_deref()) {
+        (Some(challenge), Some(verifier)) => {
+            let method = session.code_challenge_method.as_deref().unwrap_or("plain");
+            if !pkce::verify(challenge, method, verifier) {
+                return token_error("invalid_grant", "code_verifier mismatch");
+            }
         }
-        // TODO add refresh_token support?
-        _ => {
-            error!("exchange_auth_code({},{}) = nok", data.grant_type, data.code);
-            Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("'grant_type' not supported"))
+        (Some(_), None) => return token_error("invalid_grant", "'code_verifier' is required"),
+        (None, Some(_)) => return token_error("invalid_grant", "no code_challenge for this code"),
+        (None, None) => {}
+    }
+
+    let client = state
+        .oauth_db
+        .fetch_client_config(&session.client_id)
+        .map_err(|_| AppError::bad_req("failed to load the client config"))?;
+
+    if !client.callback_url.contains(redirect_uri) {
+        return Err(AppError::bad_req("redirect_uri mismatch").into());
+    }
+
+    debug!("exchange_auth_code({},{}) = ok", data.grant_type, code);
+
+    // a new token family is started for each grant, refresh tokens are rotated inside it
+    let offline = session.scopes.split_whitespace().any(|s| s == "offline_access");
+    let family = session.family.clone().unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+
+    let access_token = issue_access_token(
+        state,
+        &client,
+        Some(&session.subject),
+        &session.scopes,
+        session.claims.as_deref(),
+        Some(&family),
+        None,
+    )?;
+
+    // todo fix scope check
+    let id_token = if session.scopes.contains("openid") {
+        Some(build_id_token(state, &client, &session, Some(&access_token), None)?)
+    } else {
+        None
+    };
+
+    let refresh_token = if offline {
+        Some(issue_refresh_token(
+            state,
+            &session.client_id,
+            &session.scopes,
+            Some(&session.subject),
+            session.claims.as_deref(),
+            &family,
+            None,
+        )?)
+    } else {
+        None
+    };
+
+    core::json_ok(TokenResponse {
+        access_token,
+        refresh_token,
+        token_type: "Bearer".into(),
+        expires_in: state.config.oauth.token_exp,
+        id_token,
+    })
+}
+
+/// https://www.rfc-editor.org/rfc/rfc6749#section-6
+///
+/// the refresh token is rotated on every use. Presenting an already rotated token revokes the whole family
+/// (https://datatracker.ietf.org/doc/html/draft-ietf-oauth-security-topics#section-4.14.2)
+fn refresh(data: &TokenParams, state: &AppState, client: &OauthClient) -> Result<HttpResponse> {
+    let refresh_token = match data.refresh_token.as_deref() {
+        Some(t) if !t.is_empty() => t,
+        _ => return token_error("invalid_request", "'refresh_token' is required"),
+    };
+
+    let old = match state.oauth_db.load_token_data(refresh_token) {
+        Ok(t) if t.token_type == "refresh" && t.client_id == client.id => t,
+        Ok(_) | Err(core::InternalError::NotFound) => return token_error("invalid_grant", "invalid refresh token"),
+        Err(e) => Err(e.to_user())?,
+    };
+
+    let family = old.family.clone().unwrap_or_else(|| old.token.clone());
+
+    if old.revoked {
+        warn!("refresh token reuse detected: revoking token family {} (client: {})", family, client.id);
+        state.oauth_db.revoke_token_family(&family).map_err(|e| e.to_user())?;
+        return token_error("invalid_grant", "invalid refresh token");
+    }
+    if !old.is_active() {
+        return token_error("invalid_grant", "refresh token expired");
+    }
+
+    // the requested scope must not exceed the originally granted one, it only narrows the access token
+    let granted_scopes = old.scopes.clone().unwrap_or_default();
+    let scopes = match data.scope.as_ref() {
+        Some(requested) => {
+            let granted: HashSet<&str> = granted_scopes.split_whitespace().collect();
+            if !requested.split_whitespace().all(|s| granted.contains(s)) {
+                return token_error("invalid_scope", "scope exceeds the granted scope");
+            }
+            requested.clone()
         }
+        None => granted_scopes.clone(),
+    };
+
+    // the token was used concurrently by another request
+    if !state.oauth_db.revoke_token(&old.token).map_err(|e| e.to_user())? {
+        warn!("refresh token reuse detected: revoking token family {} (client: {})", family, client.id);
+        state.oauth_db.revoke_token_family(&family).map_err(|e| e.to_user())?;
+        return token_error("invalid_grant", "invalid refresh token");
     }
+    debug!("refresh token rotated (family: {})", family);
+
+    let (subject, claims) = (old.subject.as_deref(), old.claims.as_deref());
+    let access_token = issue_access_token(state, client, subject, &scopes, claims, Some(&family), Some(&old.token))?;
+
+    // the new refresh token has the scope of the presented one (https://www.rfc-editor.org/rfc/rfc6749#section-6)
+    let refresh_token = issue_refresh_token(state, &client.id, &granted_scopes, subject, claims, &family, Some(&old.token))?;
+
+    // id_token is optional on refresh (https://openid.net/specs/openid-connect-core-1_0.html#RefreshTokenResponse)
+    core::json_ok(TokenResponse {
+        access_token,
+        refresh_token: Some(refresh_token),
+        token_type: "Bearer".into(),
+        expires_in: state.config.oauth.token_exp,
+        id_token: None,
+    })
 }
 
-fn build_id_token(state: &AppState, session: &OauthSession) -> Result<String, AppError> {
+/// https://www.rfc-editor.org/rfc/rfc6749#section-4.4
+///
+/// machine to machine tokens: no subject, no id_token and no refresh token
+fn client_credentials(data: &TokenParams, state: &AppState, client: &OauthClient) -> Result<HttpResponse> {
+    if client.is_public() {
+        return token_error("unauthorized_client", "public clients can not use client_credentials");
+    }
+
+    let scopes = data.scope.clone().unwrap_or_default();
+    let allowed: HashSet<&str> = client.allowed_scopes.split_whitespace().collect();
+    if !scopes.split_whitespace().all(|s| allowed.contains(s)) {
+        return token_error("invalid_scope", "scope not allowed");
+    }
+
+    let access_token = issue_access_token(state, client, None, &scopes, None, None, None)?;
+    debug!("client_credentials({}) = ok", client.id);
+
+    core::json_ok(TokenResponse {
+        access_token,
+        refresh_token: None,
+        token_type: "Bearer".into(),
+        expires_in: state.config.oauth.token_exp,
+        id_token: None,
+    })
+}
+
+/// creates a new access token and saves it
+pub(crate) fn issue_access_token(
+    state: &AppState,
+    client: &OauthClient,
+    subject: Option<&str>,
+    scopes: &str,
+    claims: Option<&str>,
+    family: Option<&str>,
+    parent: Option<&str>,
+) -> Result<String, AppError> {
+    let access_token = new_access_token(state, client, subject, scopes)?;
+    state
+        .oauth_db
+        .save_oauth_token(&OauthToken {
+            token: access_token.clone(),
+            token_type: "access".to_string(),
+            client_id: client.id.clone(),
+            scopes: Some(scopes.to_string()),
+            subject: subject.map(String::from),
+            expiration: Some(state.config.oauth.token_exp),
+            created: Utc::now().naive_utc(),
+            family: family.map(String::from),
+            parent: parent.map(String::from),
+            revoked: false,
+            claims: claims.map(String::from),
+        })
+        .map_err(|e| e.to_user())?;
+    Ok(access_token)
+}
+
+fn issue_refresh_token(
+    state: &AppState,
+    client_id: &str,
+    scopes: &str,
+    subject: Option<&str>,
+    claims: Option<&str>,
+    family: &str,
+    parent: Option<&str>,
+) -> Result<String, AppError> {
+    let refresh_token = random_token();
+    state
+        .oauth_db
+        .save_oauth_token(&OauthToken {
+            token: refresh_token.clone(),
+            token_type: "refresh".to_string(),
+            client_id: client_id.to_string(),
+            scopes: Some(scopes.to_string()),
+            subject: subject.map(String::from),
+            expiration: Some(state.config.oauth.refresh_token_exp),
+            created: Utc::now().naive_utc(),
+            family: Some(family.to_string()),
+            parent: parent.map(String::from),
+            revoked: false,
+            claims: claims.map(String::from),
+        })
+        .map_err(|e| e.to_user())?;
+    Ok(refresh_token)
+}
+
+/// a random opaque reference token, or a signed JWT (RFC 9068) if configured
+fn new_access_token(state: &AppState, client: &OauthClient, subject: Option<&str>, scopes: &str) -> Result<String, AppError> {
+    let cfg = match state.config.oauth.access_token.as_ref() {
+        Some(cfg) if cfg.is_jwt() => cfg,
+        _ => return Ok(random_token()),
+    };
+
+    let now = Utc::now().naive_utc();
+    let sub = match subject {
+        Some(s) => subject::for_client(&state.config.oauth, client, s)?,
+        None => client.id.clone(), // client_credentials: the client itself
+    };
+    let claims = AccessTokenClaims {
+        iss: &state.config.oauth.issuer,
+        sub: &sub,
+        aud: cfg.audience.as_deref().unwrap_or(&state.config.oauth.issuer),
+        client_id: &client.id,
+        scope: scopes,
+        jti: uuid::Uuid::new_v4().to_string(),
+        exp: (now + Duration::seconds(state.config.oauth.token_exp)).and_utc().timestamp(),
+        iat: now.and_utc().timestamp(),
+    };
+
+    let signing_alg = cfg
+        .signing_alg
+        .ok_or_else(|| AppError::bad_config("'signing_alg' is required for JWT access tokens"))?;
+    let available_signing = cfg.available_signing.as_ref().unwrap_or(&state.config.oauth.id_token.available_signing);
+    signing::sign(state, signing_alg, available_signing, Some("at+jwt"), &claims, "access_token")
+}
+
+fn random_token() -> String {
+    rand::rng().sample_iter(&Alphanumeric).take(30).map(char::from).collect::<String>()
+}
+
+/// https://www.rfc-editor.org/rfc/rfc6749#section-5.2
+fn token_error(error: &str, description: &str) -> Result<HttpResponse> {
+    info!("token error: [{}] {}", error, description);
+    core::send_json(StatusCode::BAD_REQUEST, OauthError::new(error, description))
+}
+
+/// the signed id_token, encrypted when the client registered `id_token_encrypted_response_alg`
+///
+/// `access_token` and `code` are the ones issued together with the id_token, for `at_hash` and `c_hash`
+pub(crate) fn build_id_token(
+    state: &AppState,
+    client: &OauthClient,
+    session: &OauthSession,
+    access_token: Option<&str>,
+    code: Option<&str>,
+) -> Result<String, AppError> {
     let now = Utc::now().naive_utc();
     let exp = state.config.oauth.token_exp;
+    let id_token_cfg = &state.config.oauth.id_token;
+    let signing_alg = client.id_token_signing_alg(id_token_cfg);
+
+    // the individually requested user claims, https://openid.net/specs/openid-connect-core-1_0.html#ClaimsParameter
+    let requested = ClaimsRequest::parse(session.claims.as_deref()).map_err(|_| AppError::bad_req("invalid 'claims'"))?;
+    let claims_cfg = &state.config.oauth.claims;
+    let mut user_claims = claims::requested_user_claims(claims_cfg, &client.allowed_scopes, &requested.id_token);
+    // without an access token (response_type=id_token) the claims of the scopes can not be fetched from the userinfo endpoint
+    // https://openid.net/specs/openid-connect-core-1_0.html#ScopeClaims
+    if access_token.is_none() && code.is_none() {
+        user_claims.extend(claims::scope_claims(claims_cfg, session.scopes.split_whitespace()));
+    }
+    let user = if user_claims.is_empty() {
+        serde_json::Map::new()
+    } else {
+        let user = state.user_db.fetch_user_by_id(&session.subject).map_err(|e| e.to_user())?;
+        claims::user_claims(claims_cfg, &user, user_claims)
+    };
 
+    // https://openid.net/specs/openid-connect-core-1_0.html#IDToken
+    let aud = if id_token_cfg.audiences.is_empty() {
+        Audience::Single(session.client_id.as_str())
+    } else {
+        Audience::Multiple(
+            std::iter::once(&session.client_id)
+                .chain(&id_token_cfg.audiences)
+                .map(String::as_str)
+                .collect(),
+        )
+    };
+    let sub = subject::for_client(&state.config.oauth, client, &session.subject)?;
     let claims = IdTokenClaims {
-        iss: &state.config.oauth.issuer.clone(),
-        sub: &session.subject,
-        aud: &session.client_id,
-        nonce: session.nonce.as_ref(),
+        iss: state.config.oauth.issuer.as_str(),
+        sub: &sub,
+        aud,
+        azp: Some(&session.client_id),
+        nonce: session.nonce.as_deref(),
         exp: now.checked_add_signed(Duration::seconds(exp)).unwrap_or(now).and_utc().timestamp(),
         iat: now.and_utc().timestamp(),
-        auth_time: session.auth_time.map(|d| d.and_utc().timestamp()),
+        auth_time: session.auth_time.map(|d| d.and_utc().timestamp()), // always set, REQUIRED when max_age was requested
+        at_hash: access_token.map(|t| signing::half_hash(signing_alg, t)),
+        c_hash: code.map(|c| signing::half_hash(signing_alg, c)),
+        acr: session.acr.as_deref(),
+        amr: session.amr.as_deref().map(|a| a.split_whitespace().map(String::from).collect()),
+        sid: session.sid.as_deref(),
+        jti: uuid::Uuid::new_v4().to_string(),
+
+        user,
     };
     debug!("claims: {:?}", &claims);
 
-    let signing_alg = &state.config.oauth.id_token.signing_alg;
-    let key_name = &state
-        .config
-        .oauth
-        .id_token
-        .available_signing
-        .get(signing_alg)
-        .ok_or_else(|| AppError::bad_config(format!("alg '{:?}' not configured as available for signing id_token", signing_alg)))?
-        .iter()
-        .next()
-        .ok_or_else(|| AppError::bad_config(format!("no secret configured for signing id_token with alg '{:?}'", signing_alg)))?;
-    let secret = state.secrets.get(key_name).ok_or_else(|| {
-        log::error!("id_token signing key '{}' not found in secrets", key_name);
-        InternalError
-    })?;
-    let sign_alg = state.config.oauth.id_token.signing_alg;
-    let key = &secret.key;
-    let mut header = Header::new(sign_alg);
-    header.kid = Some(key_name.to_string());
-    let id_token = encode(&header, &claims, &key).map_err(|e| {
-        log::error!("JWT encoding error. (alg: {:?}) (cause: {})", sign_alg, e);
-        InternalError
-    })?;
-
-    Ok(id_token)
+    let id_token = signing::sign(state, signing_alg, &id_token_cfg.available_signing, None, &claims, "id_token")?;
+
+    // https://openid.net/specs/openid-connect-core-1_0.html#Encryption
+    match client.id_token_encrypted_response_alg.as_deref() {
+        Some(alg) => jwe::encrypt(
+            client,
+            alg,
+            client.id_token_encrypted_response_enc.as_deref(),
+            Some("JWT"),
+            id_token.as_bytes(),
+        ),
+        None => Ok(id_token),
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -141,21 +430,55 @@ pub struct TokenResponse {
     id_token: Option<String>, // only if 'openid' scope is requested
 }
 
+/// https://www.rfc-editor.org/rfc/rfc9068#section-2.2
+#[derive(Debug, Serialize)]
+struct AccessTokenClaims<'a> {
+    iss: &'a str,
+    sub: &'a str,
+    aud: &'a str,
+    client_id: &'a str,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    scope: &'a str,
+    jti: String,
+    exp: i64,
+    iat: i64,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct IdTokenClaims<STR: AsRef<str>> {
     iss: STR,
     sub: STR,
-    aud: STR,
+    aud: Audience<STR>,
+    /// the client the id_token was issued to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    azp: Option<STR>,
     exp: i64,
     iat: i64,
     #[serde(skip_serializing_if = "Option::is_none")]
     auth_time: Option<i64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     nonce: Option<STR>,
-    //#[serde(skip_serializing_if = "Option::is_none")]
-    //acr: Option<STR>,
-    //#[serde(skip_serializing_if = "Option::is_none")]
-    //amr: Option<String>,
-    //#[serde(skip_serializing_if = "Option::is_none")]
-    //azp: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    at_hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    c_hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    acr: Option<STR>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    amr: Option<Vec<String>>,
+    /// the SSO session, https://openid.net/specs/openid-connect-frontchannel-1_0.html#ClaimsContents
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sid: Option<STR>,
+    jti: String,
+    /// the requested user claims
+    #[serde(flatten)]
+    user: serde_json::Map<String, serde_json::Value>,
+}
+
+/// the `aud` of an id_token: the client, or the client and the configured audiences
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Audience<STR: AsRef<str>> {
+    Single(STR),
+    Multiple(Vec<STR>),
 }
@@ -1,8 +1,11 @@
-use crate::core::{send_json, AppState};
+use crate::core::{error::AppError, send_json, AppState};
+use crate::oidc::claims::{self, ClaimsRequest};
+use crate::oidc::{jwe, signing, subject};
 use actix_web::http::header::AUTHORIZATION;
 use actix_web::http::StatusCode;
 use actix_web::web::Data;
 use actix_web::{HttpRequest, HttpResponse, HttpResponseBuilder, Result};
+use std::collections::HashSet;
 
 ///GET /userinfo
 ///
@@ -22,46 +25,68 @@ pub async fn userinfo_endpoint((req, ctx): (HttpRequest, Data<AppState>)) -> Res
     if "access" != &data.token_type {
         return Ok(err_resp(StatusCode::FORBIDDEN, "invalid_token", "wrong type"));
     }
+    if !data.is_active() {
+        return Ok(err_resp(StatusCode::UNAUTHORIZED, "invalid_token", "token expired or revoked"));
+    }
 
-    let granted_scopes = match data.scopes {
-        Some(x) => x.split_whitespace().collect::<String>(), // split by space
+    let granted_scopes: HashSet<&str> = match data.scopes.as_deref() {
+        Some(x) => x.split_whitespace().collect(),
         None => return Ok(err_resp(StatusCode::FORBIDDEN, "forbidden", "Missing scope 'openid'")),
     };
-    debug!("granted_scopes: {}", granted_scopes);
+    debug!("granted_scopes: {:?}", granted_scopes);
 
     if !granted_scopes.contains("openid") {
         return Ok(err_resp(StatusCode::FORBIDDEN, "forbidden", "Missing scope 'openid'"));
     }
     // if user_id is not set - not an OIDC flow (maybe client credentials?)
-    if data.subject.is_none() {
-        return Ok(err_resp(StatusCode::FORBIDDEN, "not possible", ""));
-    }
-    let subject = data.subject.unwrap();
+    let subject = match data.subject.as_deref() {
+        Some(s) => s,
+        None => return Ok(err_resp(StatusCode::FORBIDDEN, "not possible", "")),
+    };
 
-    let user = ctx.user_db.fetch_user_by_id(&subject)?;
+    let user = ctx.user_db.fetch_user_by_id(subject)?;
+    let client = ctx.oauth_db.fetch_client_config(&data.client_id).map_err(|e| {
+        error!("userinfo: failed to load the client {}: {}", &data.client_id, e);
+        AppError::InternalError
+    })?;
 
-    let mut user_info = UserInfoClaims::new(&subject);
-    if granted_scopes.contains("email") && user.email.is_some() {
-        user_info.email = user.email;
-        user_info.email_verified = Some(false); // not suppoerted yet
-    }
-    if granted_scopes.contains("phone") && user.phone.is_some() {
-        user_info.phone_number = user.phone;
-        user_info.phone_number_verified = Some(false); // not suppoerted yet
-    }
-    if granted_scopes.contains("profile") {
-        user_info.given_name = Some(String::from(&user.given_name));
-        user_info.family_name = Some(String::from(&user.family_name));
-        user_info.name = Some(user.preferred_display_name.unwrap_or(format!("{} {}", user.given_name, user.family_name)));
-        user_info.locale = user.locale;
-        user_info.birthdate = user.birthdate;
-    }
-    if granted_scopes.contains("address") {
-        user_info.address = user.address;
+    // the claims of the granted scopes and the individually requested ones (claims request parameter)
+    let claims_cfg = &ctx.config.oauth.claims;
+    let requested = ClaimsRequest::parse(data.claims.as_deref()).unwrap_or_default();
+    let mut names = claims::scope_claims(claims_cfg, granted_scopes.iter().copied());
+    names.extend(claims::requested_user_claims(claims_cfg, &client.allowed_scopes, &requested.userinfo));
+
+    let sub = subject::for_client(&ctx.config.oauth, &client, subject)?;
+    let mut user_info = UserInfoClaims {
+        sub: &sub,
+        iss: None,
+        aud: None,
+        claims: claims::user_claims(claims_cfg, &user, names),
+    };
+
+    if client.userinfo_signed_response_alg.is_none() && client.userinfo_encrypted_response_alg.is_none() {
+        return send_json(StatusCode::OK, user_info);
     }
 
-    // TODO userinfo_encrypted_response_alg
-    send_json(StatusCode::OK, user_info)
+    // https://openid.net/specs/openid-connect-core-1_0.html#UserInfoResponse
+    let signed = client.userinfo_signed_response_alg.is_some();
+    let payload = match client.userinfo_signed_response_alg {
+        Some(alg) => {
+            user_info.iss = Some(&ctx.config.oauth.issuer);
+            user_info.aud = Some(&client.id);
+            signing::sign(&ctx, alg, &ctx.config.oauth.id_token.available_signing, None, &user_info, "userinfo")?
+        }
+        None => serde_json::to_string(&user_info)?,
+    };
+    // signed, then encrypted: a nested JWT
+    let jwt = match client.userinfo_encrypted_response_alg.as_deref() {
+        Some(alg) => {
+            let cty = if signed { Some("JWT") } else { None };
+            jwe::encrypt(&client, alg, client.userinfo_encrypted_response_enc.as_deref(), cty, payload.as_bytes())?
+        }
+        None => payload,
+    };
+    Ok(HttpResponse::Ok().content_type("application/jwt").body(jwt))
 }
 
 fn err_resp(status: StatusCode, error: &str, error_description: &str) -> HttpResponse {
@@ -91,34 +116,14 @@ fn extract_token(req: HttpRequest) -> std::result::Result<String, (String, Strin
     Ok(token)
 }
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[derive(Debug, Serialize)]
 struct UserInfoClaims<'a> {
     sub: &'a str,
-    // profile
+    /// only set in signed responses
     #[serde(skip_serializing_if = "Option::is_none")]
-    name: Option<String>,
+    iss: Option<&'a str>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    given_name: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    family_name: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    email: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    email_verified: Option<bool>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    phone_number: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    phone_number_verified: Option<bool>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    address: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    locale: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    birthdate: Option<String>,
-}
-
-impl<'a> UserInfoClaims<'a> {
-    fn new(sub: &str) -> UserInfoClaims<'_> {
-        UserInfoClaims { sub, ..Default::default() }
-    }
+    aud: Option<&'a str>,
+    #[serde(flatten)]
+    claims: serde_json::Map<String, serde_json::Value>,
 }
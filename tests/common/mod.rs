@@ -1,5 +1,7 @@
 use actix_web::cookie::Key;
-use flipid::core::config::{AuthConfig, Config, CoreConfig, CorsConfig, DatabaseConfig, IdTokenConfig, OauthConfig, SecretConfig, ServerConfig};
+use flipid::core::config::{
+    AcrConfig, AuthConfig, ClaimsConfig, Config, CoreConfig, CorsConfig, DatabaseConfig, IdTokenConfig, OauthConfig, SecretConfig, ServerConfig,
+};
 use jsonwebtoken::Algorithm;
 use std::collections::HashMap;
 
@@ -39,10 +41,30 @@ pub fn test_config() -> Config {
             scopes: "openid profile email phone address".into(),
             auth_code_exp: 60,
             token_exp: 3600,
+            refresh_token_exp: 86400,
+            par_exp: 60,
+            require_pushed_authorization_requests: false,
+            access_token: None,
             id_token: IdTokenConfig {
                 signing_alg: Algorithm::RS256,
                 available_signing: HashMap::from([(Algorithm::RS256, vec![TEST_SECRET_NAME.to_string()])]),
+                audiences: vec![],
             },
+            acr: vec![
+                AcrConfig {
+                    value: "urn:flipid:acr:pwd".into(),
+                    amr: vec!["pwd".into()],
+                },
+                AcrConfig {
+                    value: "urn:flipid:acr:mfa".into(),
+                    amr: vec!["pwd".into(), "otp".into()],
+                },
+            ],
+            claims: ClaimsConfig {
+                scopes: HashMap::from([("employee".to_string(), vec!["department".to_string(), "cost_center".to_string()])]),
+                mapping: HashMap::from([("cost_center".to_string(), "attributes.cc".to_string())]),
+            },
+            pairwise_salt: Some("test-pairwise-salt".into()),
         },
         secrets: vec![SecretConfig {
             name: TEST_SECRET_NAME.into(),
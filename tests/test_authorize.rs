@@ -1,12 +1,17 @@
 mod common;
 
+use actix_web::cookie::{Cookie, CookieJar};
 use actix_web::http::StatusCode;
 use actix_web::{test, web, web::Data, App};
-use flipid::core::models::OauthClient;
+use base64::prelude::*;
+use flipid::core::cookies::SSOCookie;
+use flipid::core::models::{OauthClient, OauthSession, User};
 use flipid::core::{self, AppState, Secrets};
-use flipid::oidc::authorize;
+use flipid::oidc::{authorize, token};
 use mockall::predicate::*;
+use std::collections::HashSet;
 use std::sync::Arc;
+use url::Url;
 
 #[actix_rt::test]
 async fn test_authorize_get_goto_login() {
@@ -18,7 +23,7 @@ async fn test_authorize_get_goto_login() {
         .times(1)
         .returning(|_| Ok(test_client1()));
 
-    let mut app = test::init_service(
+    let app = test::init_service(
         App::new()
             .app_data(Data::new(AppState::new(
                 common::test_key(),
@@ -33,17 +38,20 @@ async fn test_authorize_get_goto_login() {
     let req = test::TestRequest::get()
         .uri("/authorize?response_type=code&client_id=test1&scope=openid&redirect_uri=http://localhost:8080/callback")
         .to_request();
-    let resp = test::call_service(&mut app, req).await;
+    let resp = test::call_service(&app, req).await;
     assert_eq!(resp.status(), StatusCode::OK); // login page
 
     // authorize must set an encrypted flip_auth session cookie
     let set_cookie = resp.headers().get("set-cookie").expect("expected Set-Cookie header");
     assert!(set_cookie.to_str().unwrap().starts_with("flip_auth="));
+
+    let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    assert!(body.contains("step: 1,"));
 }
 
 #[actix_rt::test]
 async fn test_authorize_get_no_params() {
-    let mut app = test::init_service(
+    let app = test::init_service(
         App::new()
             .app_data(Data::new(mock_app_state()))
             .route("/authorize", web::get().to(authorize::auth_get)),
@@ -51,8 +59,1299 @@ async fn test_authorize_get_no_params() {
     .await;
 
     let req = test::TestRequest::with_uri("/authorize").to_request();
-    let resp = test::call_service(&mut app, req).await;
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_rt::test]
+async fn test_authorize_pkce_required() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().with(eq("test1")).times(1).returning(|_| {
+        Ok(OauthClient {
+            require_pkce: true,
+            ..test_client1()
+        })
+    });
+
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(AppState::new(
+                common::test_key(),
+                oauth_db,
+                Box::new(core::MockUserDatabase::new()),
+                Arc::new(Secrets::load(&common::test_config().secrets).expect("test secrets")),
+                common::test_config(),
+            )))
+            .route("/authorize", web::get().to(authorize::auth_get)),
+    )
+    .await;
+    let req = test::TestRequest::get()
+        .uri("/authorize?response_type=code&client_id=test1&scope=openid&redirect_uri=http://localhost:8080/callback")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+
+    let location = resp.headers().get("location").unwrap().to_str().unwrap();
+    assert!(location.contains("error=invalid_request"));
+}
+
+#[actix_rt::test]
+async fn test_authorize_implicit_sso() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    let mut user_db = Box::new(core::MockUserDatabase::new());
+    oauth_db.expect_fetch_client_config().times(2).returning(|_| Ok(implicit_client()));
+    oauth_db.expect_save_oauth_token().times(1).returning(|_| Ok(()));
+    oauth_db.expect_save_oauth_session().never();
+    user_db
+        .expect_fetch_granted_scopes()
+        .times(1)
+        .returning(|_, _| Ok(HashSet::from(["openid".to_string()])));
+
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(app_state(oauth_db, user_db)))
+            .route("/authorize", web::get().to(authorize::auth_get)),
+    )
+    .await;
+    // the order of the response_type values is not relevant
+    let req = test::TestRequest::get()
+        .uri("/authorize?response_type=token%20id_token&client_id=test1&scope=openid&nonce=n-0S6&state=af0ifjsldkj&redirect_uri=http://localhost:8080/callback")
+        .insert_header(("Cookie", sso_cookie_header()))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+
+    let location = Url::parse(resp.headers().get("location").unwrap().to_str().unwrap()).unwrap();
+    assert!(location.query().is_none(), "tokens must not be sent in the query");
+    let params: std::collections::HashMap<String, String> = url::form_urlencoded::parse(location.fragment().unwrap().as_bytes())
+        .into_owned()
+        .collect();
+    assert_eq!(params["token_type"], "Bearer");
+    assert_eq!(params["state"], "af0ifjsldkj");
+    assert!(!params.contains_key("code"));
+
+    let claims = decode_jwt_payload(&params["id_token"]);
+    assert_eq!(claims["nonce"], "n-0S6");
+    assert_eq!(claims["aud"], "test1");
+    assert_eq!(claims["sid"], SSO_SID);
+    let digest = openssl::sha::sha256(params["access_token"].as_bytes());
+    assert_eq!(claims["at_hash"], BASE64_URL_SAFE_NO_PAD.encode(&digest[..16]));
+    assert!(claims.get("c_hash").is_none());
+}
+
+#[actix_rt::test]
+async fn test_authorize_hybrid_code_replay_revokes_access_token() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    let mut user_db = Box::new(core::MockUserDatabase::new());
+    let hash = bcrypt::hash("test1", 4).unwrap();
+    let client = OauthClient {
+        secret: format!("{{BCRYPT}}{}", hash),
+        response_types: vec!["code token".into()],
+        ..test_client1()
+    };
+    // authorize (validate_auth + generate_callback) + token
+    oauth_db.expect_fetch_client_config().times(3).returning(move |_| Ok(client.clone()));
+    user_db
+        .expect_fetch_granted_scopes()
+        .times(1)
+        .returning(|_, _| Ok(HashSet::from(["openid".to_string()])));
+
+    let session = Arc::new(std::sync::Mutex::new(None::<OauthSession>));
+    let token_family = Arc::new(std::sync::Mutex::new(None::<String>));
+    let saved = session.clone();
+    oauth_db.expect_save_oauth_session().times(1).returning(move |s| {
+        *saved.lock().unwrap() = Some(s);
+        Ok(())
+    });
+    let saved = token_family.clone();
+    oauth_db.expect_save_oauth_token().times(1).returning(move |t| {
+        *saved.lock().unwrap() = t.family.clone();
+        Ok(())
+    });
+    // the code was already exchanged
+    let saved = session.clone();
+    oauth_db.expect_consume_oauth_session_by_code().times(1).returning(move |_| {
+        Ok(OauthSession {
+            consumed: true,
+            ..saved.lock().unwrap().clone().unwrap()
+        })
+    });
+    let saved = token_family.clone();
+    oauth_db
+        .expect_revoke_token_family()
+        .withf(move |f| saved.lock().unwrap().as_deref() == Some(f))
+        .times(1)
+        .returning(|_| Ok(2));
+
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(app_state(oauth_db, user_db)))
+            .route("/authorize", web::get().to(authorize::auth_get))
+            .route("/token", web::post().to(token::token_endpoint)),
+    )
+    .await;
+    let req = test::TestRequest::get()
+        .uri("/authorize?response_type=code%20token&client_id=test1&scope=openid&nonce=n&redirect_uri=http://localhost:8080/callback")
+        .insert_header(("Cookie", sso_cookie_header()))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+
+    let location = Url::parse(resp.headers().get("location").unwrap().to_str().unwrap()).unwrap();
+    let params: std::collections::HashMap<String, String> = url::form_urlencoded::parse(location.fragment().unwrap().as_bytes())
+        .into_owned()
+        .collect();
+    assert!(params.contains_key("access_token"));
+    // the access token belongs to the family of the code
+    let family = session.lock().unwrap().as_ref().unwrap().family.clone();
+    assert!(family.is_some());
+    assert_eq!(*token_family.lock().unwrap(), family);
+
+    let req = test::TestRequest::post()
+        .uri("/token")
+        .insert_header(("Authorization", core::basic_auth("test1", "test1")))
+        .insert_header(("Content-Type", "application/x-www-form-urlencoded"))
+        .set_payload(format!(
+            "grant_type=authorization_code&code={}&redirect_uri=http://localhost:8080/callback",
+            params["code"]
+        ))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_rt::test]
+async fn test_authorize_implicit_nonce_required() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    // the error is only redirected to the registered redirect_uri
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(implicit_client()));
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(app_state(oauth_db, Box::new(core::MockUserDatabase::new()))))
+            .route("/authorize", web::get().to(authorize::auth_get)),
+    )
+    .await;
+    let req = test::TestRequest::get()
+        .uri("/authorize?response_type=id_token%20token&client_id=test1&scope=openid&redirect_uri=http://localhost:8080/callback")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+
+    // errors are returned in the fragment too
+    let location = resp.headers().get("location").unwrap().to_str().unwrap();
+    assert!(
+        location.starts_with("http://localhost:8080/callback#error=invalid_request"),
+        "{}",
+        location
+    );
+}
+
+#[actix_rt::test]
+async fn test_authorize_id_token_requires_openid_scope() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(implicit_client()));
+    oauth_db.expect_save_oauth_token().never();
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(app_state(oauth_db, Box::new(core::MockUserDatabase::new()))))
+            .route("/authorize", web::get().to(authorize::auth_get)),
+    )
+    .await;
+    let req = test::TestRequest::get()
+        .uri("/authorize?response_type=id_token%20token&client_id=test1&scope=profile&nonce=n-0S6&redirect_uri=http://localhost:8080/callback")
+        .insert_header(("Cookie", sso_cookie_header()))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+
+    let location = resp.headers().get("location").unwrap().to_str().unwrap();
+    assert!(location.starts_with("http://localhost:8080/callback#error=invalid_scope"), "{}", location);
+    assert!(!location.contains("id_token="), "{}", location);
+}
+
+#[actix_rt::test]
+async fn test_authorize_response_type_not_allowed() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(test_client1()));
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(app_state(oauth_db, Box::new(core::MockUserDatabase::new()))))
+            .route("/authorize", web::get().to(authorize::auth_get)),
+    )
+    .await;
+    let req = test::TestRequest::get()
+        .uri("/authorize?response_type=code%20id_token&client_id=test1&scope=openid&nonce=n&redirect_uri=http://localhost:8080/callback")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+
+    let location = resp.headers().get("location").unwrap().to_str().unwrap();
+    assert!(location.contains("#error=unauthorized_client"), "{}", location);
+}
+
+#[actix_rt::test]
+async fn test_authorize_invalid_response_type() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(2).returning(|_| Ok(test_client1()));
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(app_state(oauth_db, Box::new(core::MockUserDatabase::new()))))
+            .route("/authorize", web::get().to(authorize::auth_get)),
+    )
+    .await;
+
+    // the redirect_uri is not registered for the client: no redirect
+    let req = test::TestRequest::get()
+        .uri("/authorize?response_type=unknown&client_id=test1&scope=openid&redirect_uri=https://evil.example.com")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
     assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    assert!(resp.headers().get("location").is_none());
+
+    let req = test::TestRequest::get()
+        .uri("/authorize?response_type=unknown&client_id=test1&scope=openid&redirect_uri=http://localhost:8080/callback")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+
+    let location = resp.headers().get("location").unwrap().to_str().unwrap();
+    assert!(
+        location.starts_with("http://localhost:8080/callback?error=invalid_request"),
+        "{}",
+        location
+    );
+}
+
+#[actix_rt::test]
+async fn test_authorize_id_token_alg_not_supported() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| {
+        Ok(OauthClient {
+            id_token_signed_response_alg: Some(jsonwebtoken::Algorithm::ES256),
+            ..test_client1()
+        })
+    });
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(app_state(oauth_db, Box::new(core::MockUserDatabase::new()))))
+            .route("/authorize", web::get().to(authorize::auth_get)),
+    )
+    .await;
+    let req = test::TestRequest::get()
+        .uri("/authorize?response_type=code&client_id=test1&scope=openid&redirect_uri=http://localhost:8080/callback")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+
+    let location = resp.headers().get("location").unwrap().to_str().unwrap();
+    assert!(location.contains("error=unauthorized_client"), "{}", location);
+}
+
+#[actix_rt::test]
+async fn test_authorize_encryption_not_supported() {
+    let clients = [
+        // no encryption key in the JWKS
+        OauthClient {
+            id_token_encrypted_response_alg: Some("RSA-OAEP".into()),
+            ..test_client1()
+        },
+        OauthClient {
+            userinfo_encrypted_response_alg: Some("RSA1_5".into()),
+            ..test_client1()
+        },
+    ];
+    for client in clients {
+        let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+        oauth_db.expect_fetch_client_config().times(1).returning(move |_| Ok(client.clone()));
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(app_state(oauth_db, Box::new(core::MockUserDatabase::new()))))
+                .route("/authorize", web::get().to(authorize::auth_get)),
+        )
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/authorize?response_type=code&client_id=test1&scope=openid&redirect_uri=http://localhost:8080/callback")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FOUND);
+
+        let location = resp.headers().get("location").unwrap().to_str().unwrap();
+        assert!(location.contains("error=unauthorized_client"), "{}", location);
+    }
+}
+
+#[actix_rt::test]
+async fn test_authorize_authorization_alg_not_supported() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| {
+        Ok(OauthClient {
+            authorization_signed_response_alg: Some(jsonwebtoken::Algorithm::ES256),
+            ..test_client1()
+        })
+    });
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(app_state(oauth_db, Box::new(core::MockUserDatabase::new()))))
+            .route("/authorize", web::get().to(authorize::auth_get)),
+    )
+    .await;
+    // rejected before the login page is shown, even when the client does not ask for a JARM response
+    let req = test::TestRequest::get()
+        .uri("/authorize?response_type=code&client_id=test1&scope=openid&redirect_uri=http://localhost:8080/callback")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+
+    let location = resp.headers().get("location").unwrap().to_str().unwrap();
+    assert!(location.contains("error=unauthorized_client"), "{}", location);
+}
+
+#[actix_rt::test]
+async fn test_authorize_userinfo_alg_not_supported() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| {
+        Ok(OauthClient {
+            userinfo_signed_response_alg: Some(jsonwebtoken::Algorithm::ES256),
+            ..test_client1()
+        })
+    });
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(app_state(oauth_db, Box::new(core::MockUserDatabase::new()))))
+            .route("/authorize", web::get().to(authorize::auth_get)),
+    )
+    .await;
+    let req = test::TestRequest::get()
+        .uri("/authorize?response_type=code&client_id=test1&scope=openid&redirect_uri=http://localhost:8080/callback")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+
+    let location = resp.headers().get("location").unwrap().to_str().unwrap();
+    assert!(location.contains("error=unauthorized_client"), "{}", location);
+}
+
+#[actix_rt::test]
+async fn test_authorize_sector_identifier_not_verified() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| {
+        Ok(OauthClient {
+            subject_type: "pairwise".into(),
+            // the document listing the redirect URIs can not be fetched
+            sector_identifier_uri: Some("http://partner.example.org/sector.json".into()),
+            ..test_client1()
+        })
+    });
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(app_state(oauth_db, Box::new(core::MockUserDatabase::new()))))
+            .route("/authorize", web::get().to(authorize::auth_get)),
+    )
+    .await;
+    let req = test::TestRequest::get()
+        .uri("/authorize?response_type=code&client_id=test1&scope=openid&redirect_uri=http://localhost:8080/callback")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+
+    let location = resp.headers().get("location").unwrap().to_str().unwrap();
+    assert!(location.contains("error=unauthorized_client"), "{}", location);
+}
+
+#[actix_rt::test]
+async fn test_authorize_client_id_token_alg() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    let mut user_db = Box::new(core::MockUserDatabase::new());
+    oauth_db.expect_fetch_client_config().times(2).returning(|_| {
+        Ok(OauthClient {
+            id_token_signed_response_alg: Some(jsonwebtoken::Algorithm::PS384),
+            ..implicit_client()
+        })
+    });
+    oauth_db.expect_save_oauth_token().times(1).returning(|_| Ok(()));
+    user_db
+        .expect_fetch_granted_scopes()
+        .times(1)
+        .returning(|_, _| Ok(HashSet::from(["openid".to_string()])));
+
+    // the global alg stays RS256
+    let mut config = common::test_config();
+    config
+        .oauth
+        .id_token
+        .available_signing
+        .insert(jsonwebtoken::Algorithm::PS384, vec![common::TEST_SECRET_NAME.to_string()]);
+    let state = AppState::new(
+        common::test_key(),
+        oauth_db,
+        user_db,
+        Arc::new(Secrets::load(&config.secrets).expect("test secrets")),
+        config,
+    );
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(state))
+            .route("/authorize", web::get().to(authorize::auth_get)),
+    )
+    .await;
+    let req = test::TestRequest::get()
+        .uri("/authorize?response_type=id_token%20token&client_id=test1&scope=openid&nonce=n-0S6&redirect_uri=http://localhost:8080/callback")
+        .insert_header(("Cookie", sso_cookie_header()))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+
+    let location = Url::parse(resp.headers().get("location").unwrap().to_str().unwrap()).unwrap();
+    let params: std::collections::HashMap<String, String> = url::form_urlencoded::parse(location.fragment().unwrap().as_bytes())
+        .into_owned()
+        .collect();
+    let header = jsonwebtoken::decode_header(&params["id_token"]).unwrap();
+    assert_eq!(header.alg, jsonwebtoken::Algorithm::PS384);
+
+    // at_hash uses the hash of the client's alg
+    let claims = decode_jwt_payload(&params["id_token"]);
+    let digest = openssl::sha::sha384(params["access_token"].as_bytes());
+    assert_eq!(claims["at_hash"], BASE64_URL_SAFE_NO_PAD.encode(&digest[..24]));
+}
+
+#[actix_rt::test]
+async fn test_authorize_form_post_sso() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    let mut user_db = Box::new(core::MockUserDatabase::new());
+    oauth_db.expect_fetch_client_config().times(2).returning(|_| Ok(test_client1()));
+    oauth_db.expect_save_oauth_session().times(1).returning(|_| Ok(()));
+    user_db
+        .expect_fetch_granted_scopes()
+        .times(1)
+        .returning(|_, _| Ok(HashSet::from(["openid".to_string()])));
+
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(app_state(oauth_db, user_db)))
+            .route("/authorize", web::get().to(authorize::auth_get)),
+    )
+    .await;
+    let req = test::TestRequest::get()
+        .uri("/authorize?response_type=code&response_mode=form_post&client_id=test1&scope=openid&state=a%22b&redirect_uri=http://localhost:8080/callback")
+        .insert_header(("Cookie", sso_cookie_header()))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(resp.headers().get("location").is_none());
+
+    let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    // tera escapes the '/' too
+    assert!(body.contains(r#"action="http:&#x2F;&#x2F;localhost:8080&#x2F;callback""#), "{}", body);
+    assert!(body.contains(r#"name="code""#));
+    assert!(body.contains(r#"name="state" value="a&quot;b""#), "values must be escaped: {}", body);
+}
+
+#[actix_rt::test]
+async fn test_authorize_query_mode_not_allowed_for_tokens() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(implicit_client()));
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(app_state(oauth_db, Box::new(core::MockUserDatabase::new()))))
+            .route("/authorize", web::get().to(authorize::auth_get)),
+    )
+    .await;
+    let req = test::TestRequest::get()
+        .uri("/authorize?response_type=token&response_mode=query&client_id=test1&scope=openid&redirect_uri=http://localhost:8080/callback")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+
+    let location = resp.headers().get("location").unwrap().to_str().unwrap();
+    assert!(
+        location.starts_with("http://localhost:8080/callback?error=invalid_request"),
+        "{}",
+        location
+    );
+}
+
+#[actix_rt::test]
+async fn test_authorize_jarm_sso() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    let mut user_db = Box::new(core::MockUserDatabase::new());
+    // validate_auth + generate_callback + signing the response
+    oauth_db.expect_fetch_client_config().times(3).returning(|_| Ok(test_client1()));
+    oauth_db.expect_save_oauth_session().times(1).returning(|_| Ok(()));
+    user_db
+        .expect_fetch_granted_scopes()
+        .times(1)
+        .returning(|_, _| Ok(HashSet::from(["openid".to_string()])));
+
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(app_state(oauth_db, user_db)))
+            .route("/authorize", web::get().to(authorize::auth_get)),
+    )
+    .await;
+    let req = test::TestRequest::get()
+        .uri("/authorize?response_type=code&response_mode=jwt&client_id=test1&scope=openid&state=xyz&redirect_uri=http://localhost:8080/callback")
+        .insert_header(("Cookie", sso_cookie_header()))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+
+    // 'jwt' defaults to 'query.jwt' for the code flow
+    let location = Url::parse(resp.headers().get("location").unwrap().to_str().unwrap()).unwrap();
+    let params: Vec<(String, String)> = location.query_pairs().into_owned().collect();
+    assert_eq!(params.len(), 1);
+    assert_eq!(params[0].0, "response");
+
+    let claims = decode_jwt_payload(&params[0].1);
+    assert_eq!(claims["iss"], common::test_config().oauth.issuer);
+    assert_eq!(claims["aud"], "test1");
+    assert_eq!(claims["state"], "xyz");
+    assert!(claims["code"].is_string());
+    assert!(claims["exp"].is_i64());
+}
+
+#[actix_rt::test]
+async fn test_authorize_jarm_error() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(2).returning(|_| Ok(test_client1()));
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(app_state(oauth_db, Box::new(core::MockUserDatabase::new()))))
+            .route("/authorize", web::get().to(authorize::auth_get)),
+    )
+    .await;
+    let req = test::TestRequest::get()
+        .uri("/authorize?response_type=code&response_mode=fragment.jwt&client_id=test1&scope=admin&redirect_uri=http://localhost:8080/callback")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+
+    let location = Url::parse(resp.headers().get("location").unwrap().to_str().unwrap()).unwrap();
+    let jwt = location.fragment().unwrap().strip_prefix("response=").unwrap();
+    let claims = decode_jwt_payload(jwt);
+    assert_eq!(claims["error"], "invalid_scope");
+    assert_eq!(claims["aud"], "test1");
+}
+
+#[actix_rt::test]
+async fn test_authorize_request_object() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    // request object verification + validate_auth
+    oauth_db.expect_fetch_client_config().times(2).returning(|_| Ok(jar_client()));
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(app_state(oauth_db, Box::new(core::MockUserDatabase::new()))))
+            .route("/authorize", web::get().to(authorize::auth_get)),
+    )
+    .await;
+
+    let request = request_object(serde_json::json!({
+        "iss": "test1",
+        "aud": common::test_config().oauth.issuer,
+        "client_id": "test1",
+        "response_type": "code",
+        "scope": "openid profile",
+        "max_age": 3600,
+        "redirect_uri": "http://localhost:8080/callback",
+    }));
+    // the plain parameters are ignored
+    let req = test::TestRequest::get()
+        .uri(&format!("/authorize?client_id=test1&scope=admin&request={}", request))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK); // login page
+
+    let set_cookie = resp.headers().get("set-cookie").unwrap().to_str().unwrap();
+    let mut jar = CookieJar::new();
+    jar.add_original(Cookie::parse(set_cookie.to_string()).unwrap());
+    let auth_ses = jar.private(&common::test_key()).get("flip_auth").unwrap();
+    let auth_ses: serde_json::Value = serde_json::from_str(auth_ses.value()).unwrap();
+    assert_eq!(auth_ses["scopes"], "openid profile");
+}
+
+#[actix_rt::test]
+async fn test_authorize_request_object_tampered() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(2).returning(|_| Ok(jar_client()));
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(app_state(oauth_db, Box::new(core::MockUserDatabase::new()))))
+            .route("/authorize", web::get().to(authorize::auth_get)),
+    )
+    .await;
+
+    let request = request_object(serde_json::json!({"scope": "openid", "redirect_uri": "http://localhost:8080/callback"}));
+    let mut parts: Vec<&str> = request.split('.').collect();
+    let tampered = BASE64_URL_SAFE_NO_PAD.encode(r#"{"scope":"openid admin","redirect_uri":"http://localhost:8080/callback"}"#);
+    parts[1] = &tampered;
+    let req = test::TestRequest::get()
+        .uri(&format!(
+            "/authorize?client_id=test1&response_type=code&redirect_uri=http://localhost:8080/callback&request={}",
+            parts.join(".")
+        ))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+
+    let location = resp.headers().get("location").unwrap().to_str().unwrap();
+    assert!(location.contains("error=invalid_request_object"), "{}", location);
+}
+
+#[actix_rt::test]
+async fn test_authorize_request_object_error_not_redirected() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().returning(|_| Ok(jar_client()));
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(app_state(oauth_db, Box::new(core::MockUserDatabase::new()))))
+            .route("/authorize", web::get().to(authorize::auth_get)),
+    )
+    .await;
+
+    // the redirect_uri is not registered for the client: no redirect
+    let req = test::TestRequest::get()
+        .uri("/authorize?client_id=test1&response_type=code&redirect_uri=https://evil.example.com&request=x")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    assert!(resp.headers().get("location").is_none());
+
+    // no client_id to check the redirect_uri against
+    let req = test::TestRequest::get()
+        .uri("/authorize?response_type=code&redirect_uri=http://localhost:8080/callback&request=x")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_rt::test]
+async fn test_authorize_request_uri_not_registered() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().returning(|_| {
+        Ok(OauthClient {
+            request_uris: vec!["http://client.example.org/request.jwt".into()],
+            ..jar_client()
+        })
+    });
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(app_state(oauth_db, Box::new(core::MockUserDatabase::new()))))
+            .route("/authorize", web::get().to(authorize::auth_get)),
+    )
+    .await;
+
+    // only the registered request_uris are fetched, and only with https
+    for request_uri in ["https://internal.example.org/admin", "http://client.example.org/request.jwt"] {
+        let req = test::TestRequest::get()
+            .uri(&format!(
+                "/authorize?client_id=test1&redirect_uri=http://localhost:8080/callback&request_uri={}",
+                request_uri
+            ))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FOUND);
+        let location = resp.headers().get("location").unwrap().to_str().unwrap();
+        assert!(location.contains("error=invalid_request_uri"), "{}", location);
+    }
+}
+
+#[actix_rt::test]
+async fn test_authorize_request_object_required() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| {
+        Ok(OauthClient {
+            require_signed_request_object: true,
+            ..jar_client()
+        })
+    });
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(app_state(oauth_db, Box::new(core::MockUserDatabase::new()))))
+            .route("/authorize", web::get().to(authorize::auth_get)),
+    )
+    .await;
+    let req = test::TestRequest::get()
+        .uri("/authorize?response_type=code&client_id=test1&scope=openid&redirect_uri=http://localhost:8080/callback")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+
+    let location = resp.headers().get("location").unwrap().to_str().unwrap();
+    assert!(location.contains("error=invalid_request"), "{}", location);
+}
+
+/// a client with the public key of the test RSA key registered
+fn jar_client() -> OauthClient {
+    let pem = std::fs::read(common::TEST_RSA_PEM).unwrap();
+    let key = jsonwebtoken::EncodingKey::from_rsa_pem(&pem).unwrap();
+    let mut jwk = jsonwebtoken::jwk::Jwk::from_encoding_key(&key, jsonwebtoken::Algorithm::RS256).unwrap();
+    jwk.common.key_id = Some("client-key".into());
+    OauthClient {
+        jwks: Some(jsonwebtoken::jwk::JwkSet { keys: vec![jwk] }),
+        ..test_client1()
+    }
+}
+
+fn request_object(claims: serde_json::Value) -> String {
+    let pem = std::fs::read(common::TEST_RSA_PEM).unwrap();
+    let key = jsonwebtoken::EncodingKey::from_rsa_pem(&pem).unwrap();
+    let mut header = jsonwebtoken::Header::new(jsonwebtoken::Algorithm::RS256);
+    header.kid = Some("client-key".into());
+    jsonwebtoken::encode(&header, &claims, &key).unwrap()
+}
+
+#[actix_rt::test]
+async fn test_authorize_prompt_none_login_required() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(test_client1()));
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(app_state(oauth_db, Box::new(core::MockUserDatabase::new()))))
+            .route("/authorize", web::get().to(authorize::auth_get)),
+    )
+    .await;
+    let req = test::TestRequest::get()
+        .uri("/authorize?response_type=code&prompt=none&client_id=test1&scope=openid&state=s1&redirect_uri=http://localhost:8080/callback")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND); // no login page
+
+    let location = resp.headers().get("location").unwrap().to_str().unwrap();
+    assert!(location.contains("error=login_required"), "{}", location);
+    assert!(location.contains("state=s1"), "{}", location);
+}
+
+#[actix_rt::test]
+async fn test_authorize_prompt_none_consent_required() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    let mut user_db = Box::new(core::MockUserDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(test_client1()));
+    user_db
+        .expect_fetch_granted_scopes()
+        .times(1)
+        .returning(|_, _| Ok(HashSet::from(["openid".to_string()])));
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(app_state(oauth_db, user_db)))
+            .route("/authorize", web::get().to(authorize::auth_get)),
+    )
+    .await;
+    let req = test::TestRequest::get()
+        .uri("/authorize?response_type=code&prompt=none&client_id=test1&scope=openid%20profile&redirect_uri=http://localhost:8080/callback")
+        .insert_header(("Cookie", sso_cookie_header()))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+
+    let location = resp.headers().get("location").unwrap().to_str().unwrap();
+    assert!(location.contains("error=consent_required"), "{}", location);
+}
+
+#[actix_rt::test]
+async fn test_authorize_prompt_login_skips_sso() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    let mut user_db = Box::new(core::MockUserDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(test_client1()));
+    user_db.expect_fetch_granted_scopes().never();
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(app_state(oauth_db, user_db)))
+            .route("/authorize", web::get().to(authorize::auth_get)),
+    )
+    .await;
+    let req = test::TestRequest::get()
+        .uri("/authorize?response_type=code&prompt=login&client_id=test1&scope=openid&redirect_uri=http://localhost:8080/callback")
+        .insert_header(("Cookie", sso_cookie_header()))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK); // login page
+}
+
+#[actix_rt::test]
+async fn test_authorize_prompt_consent_keeps_sso() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    let mut user_db = Box::new(core::MockUserDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(test_client1()));
+    oauth_db.expect_save_oauth_session().never();
+    // the scopes already granted are not considered
+    user_db.expect_fetch_granted_scopes().never();
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(app_state(oauth_db, user_db)))
+            .route("/authorize", web::get().to(authorize::auth_get)),
+    )
+    .await;
+    let req = test::TestRequest::get()
+        .uri("/authorize?response_type=code&prompt=consent&client_id=test1&scope=openid&redirect_uri=http://localhost:8080/callback")
+        .insert_header(("Cookie", sso_cookie_header()))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // the auth-session already holds the authenticated user
+    let set_cookie = resp.headers().get("set-cookie").unwrap().to_str().unwrap();
+    let mut jar = CookieJar::new();
+    jar.add_original(Cookie::parse(set_cookie.to_string()).unwrap());
+    let auth_ses = jar.private(&common::test_key()).get("flip_auth").unwrap();
+    let auth_ses: serde_json::Value = serde_json::from_str(auth_ses.value()).unwrap();
+    assert_eq!(auth_ses["subject"], "user@example.com");
+
+    // the page starts with the consent step
+    let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    assert!(body.contains("step: 2,"), "{}", body);
+    assert!(body.contains(r#"reqScopes: ["openid"],"#), "{}", body);
+}
+
+#[actix_rt::test]
+async fn test_authorize_prompt_none_combined() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(test_client1()));
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(app_state(oauth_db, Box::new(core::MockUserDatabase::new()))))
+            .route("/authorize", web::get().to(authorize::auth_get)),
+    )
+    .await;
+    let req = test::TestRequest::get()
+        .uri("/authorize?response_type=code&prompt=none%20login&client_id=test1&scope=openid&redirect_uri=http://localhost:8080/callback")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+
+    let location = resp.headers().get("location").unwrap().to_str().unwrap();
+    assert!(location.contains("error=invalid_request"), "{}", location);
+}
+
+#[actix_rt::test]
+async fn test_authorize_max_age_exceeded() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    let mut user_db = Box::new(core::MockUserDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(test_client1()));
+    user_db.expect_fetch_granted_scopes().never();
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(app_state(oauth_db, user_db)))
+            .route("/authorize", web::get().to(authorize::auth_get)),
+    )
+    .await;
+    let req = test::TestRequest::get()
+        .uri("/authorize?response_type=code&prompt=none&max_age=900&client_id=test1&scope=openid&redirect_uri=http://localhost:8080/callback")
+        .insert_header(("Cookie", sso_cookie_header_at(chrono::Utc::now().timestamp() - 1000)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+
+    let location = resp.headers().get("location").unwrap().to_str().unwrap();
+    assert!(location.contains("error=login_required"), "{}", location);
+}
+
+#[actix_rt::test]
+async fn test_authorize_client_default_max_age() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    let mut user_db = Box::new(core::MockUserDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| {
+        Ok(OauthClient {
+            default_max_age: Some(900),
+            ..test_client1()
+        })
+    });
+    user_db.expect_fetch_granted_scopes().never();
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(app_state(oauth_db, user_db)))
+            .route("/authorize", web::get().to(authorize::auth_get)),
+    )
+    .await;
+    let req = test::TestRequest::get()
+        .uri("/authorize?response_type=code&client_id=test1&scope=openid&redirect_uri=http://localhost:8080/callback")
+        .insert_header(("Cookie", sso_cookie_header_at(chrono::Utc::now().timestamp() - 1000)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK); // login page
+}
+
+#[actix_rt::test]
+async fn test_authorize_max_age_auth_time() {
+    let auth_time = chrono::Utc::now().timestamp() - 60;
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    let mut user_db = Box::new(core::MockUserDatabase::new());
+    oauth_db.expect_fetch_client_config().times(2).returning(|_| Ok(implicit_client()));
+    oauth_db.expect_save_oauth_token().times(1).returning(|_| Ok(()));
+    user_db
+        .expect_fetch_granted_scopes()
+        .times(1)
+        .returning(|_, _| Ok(HashSet::from(["openid".to_string()])));
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(app_state(oauth_db, user_db)))
+            .route("/authorize", web::get().to(authorize::auth_get)),
+    )
+    .await;
+    let req = test::TestRequest::get()
+        .uri("/authorize?response_type=id_token%20token&max_age=900&nonce=n&client_id=test1&scope=openid&redirect_uri=http://localhost:8080/callback")
+        .insert_header(("Cookie", sso_cookie_header_at(auth_time)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+
+    let location = Url::parse(resp.headers().get("location").unwrap().to_str().unwrap()).unwrap();
+    let params: std::collections::HashMap<String, String> = url::form_urlencoded::parse(location.fragment().unwrap().as_bytes())
+        .into_owned()
+        .collect();
+    let claims = decode_jwt_payload(&params["id_token"]);
+    assert_eq!(claims["auth_time"], auth_time);
+}
+
+#[actix_rt::test]
+async fn test_authorize_invalid_max_age() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(test_client1()));
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(app_state(oauth_db, Box::new(core::MockUserDatabase::new()))))
+            .route("/authorize", web::get().to(authorize::auth_get)),
+    )
+    .await;
+    let req = test::TestRequest::get()
+        .uri("/authorize?response_type=code&max_age=-1&client_id=test1&scope=openid&redirect_uri=http://localhost:8080/callback")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+
+    let location = resp.headers().get("location").unwrap().to_str().unwrap();
+    assert!(location.contains("error=invalid_request"), "{}", location);
+}
+
+#[actix_rt::test]
+async fn test_authorize_acr_values_sso() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    let mut user_db = Box::new(core::MockUserDatabase::new());
+    oauth_db.expect_fetch_client_config().times(2).returning(|_| Ok(implicit_client()));
+    oauth_db.expect_save_oauth_token().times(1).returning(|_| Ok(()));
+    user_db
+        .expect_fetch_granted_scopes()
+        .times(1)
+        .returning(|_, _| Ok(HashSet::from(["openid".to_string()])));
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(app_state(oauth_db, user_db)))
+            .route("/authorize", web::get().to(authorize::auth_get)),
+    )
+    .await;
+    // acr_values is voluntary: the mfa class is not reached, the pwd class is issued
+    let req = test::TestRequest::get()
+        .uri("/authorize?response_type=id_token%20token&acr_values=urn:flipid:acr:mfa%20urn:flipid:acr:pwd&nonce=n&client_id=test1&scope=openid&redirect_uri=http://localhost:8080/callback")
+        .insert_header(("Cookie", sso_cookie_header()))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+
+    let location = Url::parse(resp.headers().get("location").unwrap().to_str().unwrap()).unwrap();
+    let params: std::collections::HashMap<String, String> = url::form_urlencoded::parse(location.fragment().unwrap().as_bytes())
+        .into_owned()
+        .collect();
+    let claims = decode_jwt_payload(&params["id_token"]);
+    assert_eq!(claims["acr"], "urn:flipid:acr:pwd");
+    assert_eq!(claims["amr"], serde_json::json!(["pwd"]));
+}
+
+#[actix_rt::test]
+async fn test_authorize_essential_acr_needs_login() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(test_client1()));
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(app_state(oauth_db, Box::new(core::MockUserDatabase::new()))))
+            .route("/authorize", web::get().to(authorize::auth_get)),
+    )
+    .await;
+    let claims =
+        url::form_urlencoded::byte_serialize(br#"{"id_token":{"acr":{"essential":true,"values":["urn:flipid:acr:mfa"]}}}"#).collect::<String>();
+    let req = test::TestRequest::get()
+        .uri(&format!(
+            "/authorize?response_type=code&prompt=none&claims={}&client_id=test1&scope=openid&redirect_uri=http://localhost:8080/callback",
+            claims
+        ))
+        .insert_header(("Cookie", sso_cookie_header()))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+
+    let location = resp.headers().get("location").unwrap().to_str().unwrap();
+    assert!(location.contains("error=login_required"), "{}", location);
+}
+
+#[actix_rt::test]
+async fn test_authorize_claims_request() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    let mut user_db = Box::new(core::MockUserDatabase::new());
+    oauth_db.expect_fetch_client_config().times(2).returning(|_| Ok(implicit_client()));
+    // the userinfo claims are kept with the access token
+    oauth_db
+        .expect_save_oauth_token()
+        .withf(|t| t.claims.as_deref().is_some_and(|c| c.contains("userinfo")))
+        .times(1)
+        .returning(|_| Ok(()));
+    // the user consented to the scopes of the requested claims before
+    user_db
+        .expect_fetch_granted_scopes()
+        .times(1)
+        .returning(|_, _| Ok(HashSet::from(["openid".to_string(), "profile".to_string(), "email".to_string()])));
+    user_db.expect_fetch_granted_claims().times(1).returning(|_, _| Ok(HashSet::new()));
+    user_db.expect_fetch_user_by_id().with(eq("user@example.com")).times(1).returning(|_| {
+        Ok(User {
+            id: "user@example.com".into(),
+            password: "hashed".into(),
+            email: Some("user@example.com".into()),
+            email_verified: false,
+            phone: None,
+            phone_number_verified: false,
+            given_name: "John".into(),
+            family_name: "Doe".into(),
+            middle_name: None,
+            nickname: None,
+            preferred_username: None,
+            preferred_display_name: None,
+            address: None,
+            birthdate: None,
+            locale: None,
+            profile: None,
+            picture: None,
+            website: None,
+            gender: None,
+            zoneinfo: None,
+            updated_at: None,
+            attributes: None,
+        })
+    });
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(app_state(oauth_db, user_db)))
+            .route("/authorize", web::get().to(authorize::auth_get)),
+    )
+    .await;
+    let claims =
+        url::form_urlencoded::byte_serialize(br#"{"id_token":{"email":null,"birthdate":null},"userinfo":{"email":null}}"#).collect::<String>();
+    let req = test::TestRequest::get()
+        .uri(&format!(
+            "/authorize?response_type=id_token%20token&claims={}&nonce=n&client_id=test1&scope=openid&redirect_uri=http://localhost:8080/callback",
+            claims
+        ))
+        .insert_header(("Cookie", sso_cookie_header()))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+
+    let location = Url::parse(resp.headers().get("location").unwrap().to_str().unwrap()).unwrap();
+    let params: std::collections::HashMap<String, String> = url::form_urlencoded::parse(location.fragment().unwrap().as_bytes())
+        .into_owned()
+        .collect();
+    let claims = decode_jwt_payload(&params["id_token"]);
+    assert_eq!(claims["email"], "user@example.com");
+    assert!(claims.get("birthdate").is_none(), "unset claims are left out");
+    assert!(claims.get("given_name").is_none());
+}
+
+#[actix_rt::test]
+async fn test_authorize_id_token_only_scope_claims() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    let mut user_db = Box::new(core::MockUserDatabase::new());
+    oauth_db.expect_fetch_client_config().times(2).returning(|_| {
+        Ok(OauthClient {
+            response_types: vec!["id_token".into()],
+            ..implicit_client()
+        })
+    });
+    // neither a code nor an access token is issued
+    oauth_db.expect_save_oauth_session().never();
+    oauth_db.expect_save_oauth_token().never();
+    user_db
+        .expect_fetch_granted_scopes()
+        .times(1)
+        .returning(|_, _| Ok(HashSet::from(["openid".to_string(), "email".to_string()])));
+    user_db.expect_fetch_user_by_id().with(eq("user@example.com")).times(1).returning(|_| {
+        Ok(User {
+            id: "user@example.com".into(),
+            password: "hashed".into(),
+            email: Some("user@example.com".into()),
+            email_verified: true,
+            phone: Some("+1234567890".into()),
+            phone_number_verified: false,
+            given_name: "John".into(),
+            family_name: "Doe".into(),
+            middle_name: None,
+            nickname: None,
+            preferred_username: None,
+            preferred_display_name: None,
+            address: None,
+            birthdate: None,
+            locale: None,
+            profile: None,
+            picture: None,
+            website: None,
+            gender: None,
+            zoneinfo: None,
+            updated_at: None,
+            attributes: None,
+        })
+    });
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(app_state(oauth_db, user_db)))
+            .route("/authorize", web::get().to(authorize::auth_get)),
+    )
+    .await;
+    let req = test::TestRequest::get()
+        .uri("/authorize?response_type=id_token&nonce=n&client_id=test1&scope=openid%20email&redirect_uri=http://localhost:8080/callback")
+        .insert_header(("Cookie", sso_cookie_header()))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+
+    let location = Url::parse(resp.headers().get("location").unwrap().to_str().unwrap()).unwrap();
+    let params: std::collections::HashMap<String, String> = url::form_urlencoded::parse(location.fragment().unwrap().as_bytes())
+        .into_owned()
+        .collect();
+    assert!(!params.contains_key("access_token"));
+    // the claims of the granted scopes are in the id_token
+    let claims = decode_jwt_payload(&params["id_token"]);
+    assert_eq!(claims["email"], "user@example.com");
+    assert_eq!(claims["email_verified"], true);
+    assert!(claims.get("phone_number").is_none());
+}
+
+#[actix_rt::test]
+async fn test_authorize_claims_request_consent_required() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    let mut user_db = Box::new(core::MockUserDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(implicit_client()));
+    oauth_db.expect_save_oauth_token().never();
+    // only the openid scope was granted, the email claim was never consented
+    user_db
+        .expect_fetch_granted_scopes()
+        .times(1)
+        .returning(|_, _| Ok(HashSet::from(["openid".to_string()])));
+    user_db.expect_fetch_granted_claims().times(1).returning(|_, _| Ok(HashSet::new()));
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(app_state(oauth_db, user_db)))
+            .route("/authorize", web::get().to(authorize::auth_get)),
+    )
+    .await;
+    let claims = url::form_urlencoded::byte_serialize(br#"{"userinfo":{"email":null}}"#).collect::<String>();
+    let req = test::TestRequest::get()
+        .uri(&format!(
+            "/authorize?response_type=id_token%20token&claims={}&nonce=n&prompt=none&client_id=test1&scope=openid&redirect_uri=http://localhost:8080/callback",
+            claims
+        ))
+        .insert_header(("Cookie", sso_cookie_header()))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+    let location = resp.headers().get("location").unwrap().to_str().unwrap();
+    assert!(location.contains("error=consent_required"), "{}", location);
+}
+
+#[actix_rt::test]
+async fn test_authorize_claims_request_consented_before() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    let mut user_db = Box::new(core::MockUserDatabase::new());
+    oauth_db.expect_fetch_client_config().times(2).returning(|_| Ok(implicit_client()));
+    oauth_db.expect_save_oauth_token().times(1).returning(|_| Ok(()));
+    // the email claim was consented individually, without the email scope
+    user_db
+        .expect_fetch_granted_scopes()
+        .times(1)
+        .returning(|_, _| Ok(HashSet::from(["openid".to_string()])));
+    user_db
+        .expect_fetch_granted_claims()
+        .with(eq("test1"), eq("user@example.com"))
+        .times(1)
+        .returning(|_, _| Ok(HashSet::from(["email".to_string()])));
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(app_state(oauth_db, user_db)))
+            .route("/authorize", web::get().to(authorize::auth_get)),
+    )
+    .await;
+    let claims = url::form_urlencoded::byte_serialize(br#"{"userinfo":{"email":null}}"#).collect::<String>();
+    let req = test::TestRequest::get()
+        .uri(&format!(
+            "/authorize?response_type=id_token%20token&claims={}&nonce=n&prompt=none&client_id=test1&scope=openid&redirect_uri=http://localhost:8080/callback",
+            claims
+        ))
+        .insert_header(("Cookie", sso_cookie_header()))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+    let location = resp.headers().get("location").unwrap().to_str().unwrap();
+    assert!(location.contains("access_token="), "{}", location);
+    assert!(!location.contains("error="), "{}", location);
+}
+
+#[actix_rt::test]
+async fn test_authorize_invalid_claims_request() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(test_client1()));
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(app_state(oauth_db, Box::new(core::MockUserDatabase::new()))))
+            .route("/authorize", web::get().to(authorize::auth_get)),
+    )
+    .await;
+    let req = test::TestRequest::get()
+        .uri("/authorize?response_type=code&claims=%5B%5D&client_id=test1&scope=openid&redirect_uri=http://localhost:8080/callback")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+
+    let location = resp.headers().get("location").unwrap().to_str().unwrap();
+    assert!(location.contains("error=invalid_request"), "{}", location);
+}
+
+fn app_state(oauth_db: Box<core::MockOauthDatabase>, user_db: Box<core::MockUserDatabase>) -> AppState {
+    AppState::new(
+        common::test_key(),
+        oauth_db,
+        user_db,
+        Arc::new(Secrets::load(&common::test_config().secrets).expect("test secrets")),
+        common::test_config(),
+    )
+}
+
+const SSO_SID: &str = "sso-session-1";
+
+/// Build a `Cookie: sso=<encrypted>` header value encrypted with the test key.
+fn sso_cookie_header() -> String {
+    sso_cookie_header_at(chrono::Utc::now().timestamp())
+}
+
+fn sso_cookie_header_at(auth_time: i64) -> String {
+    let sso = SSOCookie {
+        subject: "user@example.com".into(),
+        client_id: "test1".into(),
+        auth_time,
+        amr: vec!["pwd".into()],
+        sid: Some(SSO_SID.into()),
+    };
+    let mut jar = CookieJar::new();
+    jar.private_mut(&common::test_key())
+        .add(Cookie::new("sso", serde_json::to_string(&sso).unwrap()));
+    jar.delta().map(|c| format!("{}={}", c.name(), c.value())).next().unwrap()
+}
+
+/// Decodes the payload of a JWT without verifying the signature.
+fn decode_jwt_payload(token: &str) -> serde_json::Value {
+    let payload = token.split('.').nth(1).expect("JWT must have 3 parts");
+    serde_json::from_slice(&BASE64_URL_SAFE_NO_PAD.decode(payload).unwrap()).unwrap()
+}
+
+fn implicit_client() -> OauthClient {
+    OauthClient {
+        client_type: "public".into(),
+        token_endpoint_auth_method: "none".into(),
+        response_types: vec!["id_token token".into()],
+        ..test_client1()
+    }
 }
 
 fn mock_app_state() -> AppState {
@@ -74,5 +1373,6 @@ fn test_client1() -> OauthClient {
         name: "Test1".into(),
         callback_url: vec!["http://localhost:8080/callback".into()],
         allowed_scopes: "openid profile email phone address".into(),
+        ..Default::default()
     }
 }
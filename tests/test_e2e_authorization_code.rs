@@ -29,13 +29,25 @@ fn test_user() -> User {
         id: USERNAME.into(),
         password: "hashed".into(),
         email: Some(USERNAME.into()),
+        email_verified: false,
         phone: None,
+        phone_number_verified: false,
         given_name: "Test".into(),
         family_name: "User".into(),
+        middle_name: None,
+        nickname: None,
+        preferred_username: None,
         preferred_display_name: None,
         address: None,
         birthdate: None,
         locale: None,
+        profile: None,
+        picture: None,
+        website: None,
+        gender: None,
+        zoneinfo: None,
+        updated_at: None,
+        attributes: None,
     }
 }
 
@@ -47,6 +59,7 @@ fn test_client() -> OauthClient {
         name: "Test App".into(),
         callback_url: vec![REDIRECT_URI.into()],
         allowed_scopes: "openid email profile".into(),
+        ..Default::default()
     }
 }
 
@@ -95,8 +108,9 @@ async fn test_e2e_authorization_code_flow() {
     let mut oauth_db = Box::new(core::MockOauthDatabase::new());
     let mut user_db = Box::new(core::MockUserDatabase::new());
 
-    // authorize (validate_auth) + consent (generate_callback) + token (validate_credentials + redirect_uri check) = 4 calls
-    oauth_db.expect_fetch_client_config().times(4).returning(|_| Ok(test_client()));
+    // authorize (validate_auth) + consent (generate_callback) + token (validate_credentials + redirect_uri check)
+    // + userinfo (userinfo_signed_response_alg) = 5 calls
+    oauth_db.expect_fetch_client_config().times(5).returning(|_| Ok(test_client()));
 
     user_db.expect_fetch_user_by_id().times(1).returning(|_| {
         let hash = bcrypt::hash("pass", 4).unwrap();
@@ -104,13 +118,25 @@ async fn test_e2e_authorization_code_flow() {
             id: USERNAME.into(),
             password: format!("{{BCRYPT}}{}", hash),
             email: Some(USERNAME.into()),
+            email_verified: false,
             phone: None,
+            phone_number_verified: false,
             given_name: "Test".into(),
             family_name: "User".into(),
+            middle_name: None,
+            nickname: None,
+            preferred_username: None,
             preferred_display_name: None,
             address: None,
             birthdate: None,
             locale: None,
+            profile: None,
+            picture: None,
+            website: None,
+            gender: None,
+            zoneinfo: None,
+            updated_at: None,
+            attributes: None,
         })
     });
 
@@ -129,7 +155,7 @@ async fn test_e2e_authorization_code_flow() {
             nonce: Some(NONCE.into()),
             subject: USERNAME.into(),
             expiration: chrono::Utc::now().naive_utc() + Duration::minutes(60),
-            auth_time: None,
+            ..Default::default()
         })
     });
 
@@ -144,12 +170,13 @@ async fn test_e2e_authorization_code_flow() {
             subject: Some(USERNAME.into()),
             expiration: Some(chrono::Utc::now().timestamp() + 3600),
             created: chrono::Utc::now().naive_utc(),
+            ..Default::default()
         })
     });
 
     user_db.expect_fetch_user_by_id().times(1).returning(|_| Ok(test_user()));
 
-    let mut app = test::init_service(
+    let app = test::init_service(
         App::new()
             .app_data(Data::new(AppState::new(
                 common::test_key(),
@@ -169,7 +196,7 @@ async fn test_e2e_authorization_code_flow() {
     // ── Step 1: GET /oauth2/authorize ─────────────────────────────────────────
     // Uses percent-encoded redirect_uri to keep the query string unambiguous.
     let resp = test::call_service(
-        &mut app,
+        &app,
         test::TestRequest::get()
             .uri(&format!(
                 "/oauth2/authorize?client_id={}&redirect_uri=http%3A%2F%2Flocalhost%3A8080%2Fcallback\
@@ -186,7 +213,7 @@ async fn test_e2e_authorization_code_flow() {
 
     // ── Step 2: POST /idp/login (consent required) ────────────────────────────
     let resp = test::call_service(
-        &mut app,
+        &app,
         test::TestRequest::post()
             .uri("/idp/login")
             .insert_header(("Content-Type", "application/json"))
@@ -206,7 +233,7 @@ async fn test_e2e_authorization_code_flow() {
 
     // ── Step 3: POST /idp/consent ─────────────────────────────────────────────
     let resp = test::call_service(
-        &mut app,
+        &app,
         test::TestRequest::post()
             .uri("/idp/consent")
             .insert_header(("Content-Type", "application/json"))
@@ -231,7 +258,7 @@ async fn test_e2e_authorization_code_flow() {
 
     // ── Step 4: POST /oauth2/token ────────────────────────────────────────────
     let resp = test::call_service(
-        &mut app,
+        &app,
         test::TestRequest::post()
             .uri("/oauth2/token")
             .insert_header(("Authorization", BASIC_AUTH))
@@ -264,7 +291,7 @@ async fn test_e2e_authorization_code_flow() {
 
     // ── Step 5: GET /oauth2/userinfo ──────────────────────────────────────────
     let resp = test::call_service(
-        &mut app,
+        &app,
         test::TestRequest::get()
             .uri("/oauth2/userinfo")
             .insert_header(("Authorization", format!("Bearer {}", access_token)))
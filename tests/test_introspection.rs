@@ -21,6 +21,7 @@ fn test_client() -> OauthClient {
         name: "Test1".into(),
         callback_url: vec!["http://localhost:8080/callback".into()],
         allowed_scopes: "openid profile email".into(),
+        ..Default::default()
     }
 }
 
@@ -31,8 +32,8 @@ fn active_token() -> OauthToken {
         client_id: CLIENT_ID.into(),
         scopes: Some("openid profile".into()),
         subject: Some("user@example.com".into()),
-        expiration: None,
         created: chrono::Utc::now().naive_utc(),
+        ..Default::default()
     }
 }
 
@@ -47,6 +48,7 @@ fn expiring_token(secs_from_now: i64) -> OauthToken {
         subject: Some("user@example.com".into()),
         expiration: Some(expiration),
         created,
+        ..Default::default()
     }
 }
 
@@ -56,7 +58,7 @@ async fn call_introspect(
     auth_header: Option<&str>,
     body: &str,
 ) -> actix_web::dev::ServiceResponse {
-    let mut app = test::init_service(
+    let app = test::init_service(
         App::new()
             .app_data(Data::new(AppState::new(
                 common::test_key(),
@@ -76,7 +78,7 @@ async fn call_introspect(
     if let Some(h) = auth_header {
         req = req.insert_header(("Authorization", h));
     }
-    test::call_service(&mut app, req.to_request()).await
+    test::call_service(&app, req.to_request()).await
 }
 
 fn valid_auth() -> String {
@@ -194,6 +196,27 @@ async fn test_introspect_invalid_credentials() {
     assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
 }
 
+#[actix_rt::test]
+async fn test_introspect_public_client() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    let user_db = Box::new(core::MockUserDatabase::new());
+
+    oauth_db.expect_fetch_client_config().with(eq("spa")).times(1).returning(|_| {
+        Ok(OauthClient {
+            id: "spa".into(),
+            secret: String::new(),
+            client_type: "public".into(),
+            token_endpoint_auth_method: "none".into(),
+            ..test_client()
+        })
+    });
+    oauth_db.expect_load_token_data().times(0);
+
+    let body = format!("{}&client_id=spa", token_body(ACCESS_TOKEN));
+    let resp = call_introspect(oauth_db, user_db, None, &body).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+}
+
 #[actix_rt::test]
 async fn test_introspect_unsupported_token_type_hint() {
     let mut oauth_db = Box::new(core::MockOauthDatabase::new());
@@ -241,3 +264,41 @@ async fn test_introspect_active_token_with_expiration() {
     assert!(body["exp"].is_number());
     assert!(body["iat"].is_number());
 }
+
+#[actix_rt::test]
+async fn test_introspect_pairwise_subject() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    let user_db = Box::new(core::MockUserDatabase::new());
+
+    // the resource server introspects a token of a pairwise client
+    oauth_db
+        .expect_fetch_client_config()
+        .with(eq(CLIENT_ID))
+        .times(1)
+        .returning(|_| Ok(test_client()));
+    oauth_db.expect_fetch_client_config().with(eq("partner")).times(1).returning(|_| {
+        Ok(OauthClient {
+            id: "partner".into(),
+            callback_url: vec!["https://partner.example.org/callback".into()],
+            subject_type: "pairwise".into(),
+            ..test_client()
+        })
+    });
+    oauth_db.expect_load_token_data().times(1).returning(|_| {
+        Ok(OauthToken {
+            client_id: "partner".into(),
+            ..active_token()
+        })
+    });
+
+    let auth = valid_auth();
+    let resp = call_introspect(oauth_db, user_db, Some(&auth), &token_body(ACCESS_TOKEN)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["active"], true);
+    assert_eq!(body["client_id"], "partner");
+    let sub = body["sub"].as_str().unwrap();
+    assert_ne!(sub, "user@example.com");
+    assert_eq!(sub.len(), 43);
+}
@@ -4,11 +4,11 @@ use actix_web::cookie::{Cookie, CookieJar};
 use actix_web::http::{header::SET_COOKIE, StatusCode};
 use actix_web::web::Data;
 use actix_web::{test, web, App};
-use flipid::core::cookies::AuthSessionCookie;
+use flipid::core::cookies::{AuthSessionCookie, SSOCookie};
 use flipid::core::error::InternalError;
 use flipid::core::models::{OauthClient, User};
 use flipid::core::{self, AppState, Secrets};
-use flipid::idp::login;
+use flipid::idp::{consent, login};
 use mockall::predicate::*;
 use std::collections::HashSet;
 use std::sync::Arc;
@@ -23,20 +23,32 @@ fn test_client() -> OauthClient {
         name: "Test".into(),
         callback_url: vec![REDIRECT_URI.into()],
         allowed_scopes: "openid profile".into(),
+        ..Default::default()
     }
 }
 
 /// Build a `Cookie: flip_auth=<encrypted>` header value encrypted with the test key.
 fn flip_auth_cookie_header(scopes: &str) -> String {
+    flip_auth_cookie_header_with_prompt(scopes, None)
+}
+
+fn flip_auth_cookie_header_with_prompt(scopes: &str, prompt: Option<&str>) -> String {
     let auth_ses = AuthSessionCookie {
         client_id: CLIENT_ID.into(),
+        response_type: "code".into(),
         scopes: scopes.into(),
         redirect_uri: REDIRECT_URI.into(),
         nonce: None,
         state: None,
+        prompt: prompt.map(String::from),
         subject: None,
+        ..Default::default()
     };
-    let json = serde_json::to_string(&auth_ses).unwrap();
+    encrypted_auth_session(&auth_ses)
+}
+
+fn encrypted_auth_session(auth_ses: &AuthSessionCookie) -> String {
+    let json = serde_json::to_string(auth_ses).unwrap();
     let key = common::test_key();
     let mut jar = CookieJar::new();
     jar.private_mut(&key).add(Cookie::new("flip_auth", json));
@@ -79,13 +91,25 @@ async fn test_login_happy_path_all_scopes_granted() {
             id: "user@example.com".into(),
             password: format!("{{BCRYPT}}{}", hash),
             email: Some("user@example.com".into()),
+            email_verified: false,
             phone: None,
+            phone_number_verified: false,
             given_name: "Test".into(),
             family_name: "User".into(),
+            middle_name: None,
+            nickname: None,
+            preferred_username: None,
             preferred_display_name: None,
             address: None,
             birthdate: None,
             locale: None,
+            profile: None,
+            picture: None,
+            website: None,
+            gender: None,
+            zoneinfo: None,
+            updated_at: None,
+            attributes: None,
         })
     });
     user_db
@@ -95,7 +119,7 @@ async fn test_login_happy_path_all_scopes_granted() {
     oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(test_client()));
     oauth_db.expect_save_oauth_session().times(1).returning(|_| Ok(()));
 
-    let mut app = test::init_service(
+    let app = test::init_service(
         App::new()
             .app_data(Data::new(make_app_state(oauth_db, user_db)))
             .route("/idp/login", web::post().to(login)),
@@ -109,7 +133,7 @@ async fn test_login_happy_path_all_scopes_granted() {
         .set_payload(r#"{"username":"user@example.com","password":"pass"}"#)
         .to_request();
 
-    let resp = test::call_service(&mut app, req).await;
+    let resp = test::call_service(&app, req).await;
     assert_eq!(resp.status(), StatusCode::FOUND);
 
     // flip_auth must be cleared (Max-Age=0)
@@ -131,19 +155,31 @@ async fn test_login_consent_needed() {
             id: "user@example.com".into(),
             password: format!("{{BCRYPT}}{}", hash),
             email: Some("user@example.com".into()),
+            email_verified: false,
             phone: None,
+            phone_number_verified: false,
             given_name: "Test".into(),
             family_name: "User".into(),
+            middle_name: None,
+            nickname: None,
+            preferred_username: None,
             preferred_display_name: None,
             address: None,
             birthdate: None,
             locale: None,
+            profile: None,
+            picture: None,
+            website: None,
+            gender: None,
+            zoneinfo: None,
+            updated_at: None,
+            attributes: None,
         })
     });
     // no scopes granted yet — handler should respond with scopes to grant
     user_db.expect_fetch_granted_scopes().times(1).returning(|_, _| Ok(HashSet::new()));
 
-    let mut app = test::init_service(
+    let app = test::init_service(
         App::new()
             .app_data(Data::new(make_app_state(oauth_db, user_db)))
             .route("/idp/login", web::post().to(login)),
@@ -157,7 +193,7 @@ async fn test_login_consent_needed() {
         .set_payload(r#"{"username":"user@example.com","password":"pass"}"#)
         .to_request();
 
-    let resp = test::call_service(&mut app, req).await;
+    let resp = test::call_service(&app, req).await;
     assert_eq!(resp.status(), StatusCode::OK);
 
     // sso must NOT be set when consent is still needed
@@ -168,12 +204,69 @@ async fn test_login_consent_needed() {
     assert!(!body["scopes"].as_array().unwrap().is_empty());
 }
 
+#[actix_rt::test]
+async fn test_login_prompt_consent() {
+    let oauth_db = Box::new(core::MockOauthDatabase::new());
+    let mut user_db = Box::new(core::MockUserDatabase::new());
+
+    user_db.expect_fetch_user_by_id().times(1).returning(|_| {
+        let hash = bcrypt::hash("pass", 4).unwrap();
+        Ok(User {
+            id: "user@example.com".into(),
+            password: format!("{{BCRYPT}}{}", hash),
+            email: Some("user@example.com".into()),
+            email_verified: false,
+            phone: None,
+            phone_number_verified: false,
+            given_name: "Test".into(),
+            family_name: "User".into(),
+            middle_name: None,
+            nickname: None,
+            preferred_username: None,
+            preferred_display_name: None,
+            address: None,
+            birthdate: None,
+            locale: None,
+            profile: None,
+            picture: None,
+            website: None,
+            gender: None,
+            zoneinfo: None,
+            updated_at: None,
+            attributes: None,
+        })
+    });
+    // the scopes already granted are not considered
+    user_db.expect_fetch_granted_scopes().never();
+
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(make_app_state(oauth_db, user_db)))
+            .route("/idp/login", web::post().to(login)),
+    )
+    .await;
+
+    let req = test::TestRequest::post()
+        .uri("/idp/login")
+        .insert_header(("Content-Type", "application/json"))
+        .insert_header(("Cookie", flip_auth_cookie_header_with_prompt("openid profile", Some("consent"))))
+        .set_payload(r#"{"username":"user@example.com","password":"pass"}"#)
+        .to_request();
+
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["op"], "GRANT");
+    assert_eq!(body["scopes"].as_array().unwrap().len(), 2);
+}
+
 #[actix_rt::test]
 async fn test_login_missing_auth_session() {
     let oauth_db = Box::new(core::MockOauthDatabase::new());
     let user_db = Box::new(core::MockUserDatabase::new());
 
-    let mut app = test::init_service(
+    let app = test::init_service(
         App::new()
             .app_data(Data::new(make_app_state(oauth_db, user_db)))
             .route("/idp/login", web::post().to(login)),
@@ -187,7 +280,7 @@ async fn test_login_missing_auth_session() {
         .set_payload(r#"{"username":"user@example.com","password":"pass"}"#)
         .to_request();
 
-    let resp = test::call_service(&mut app, req).await;
+    let resp = test::call_service(&app, req).await;
     // InvalidAuthSession → 412 Precondition Failed
     assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
 }
@@ -199,7 +292,7 @@ async fn test_login_invalid_credentials() {
 
     user_db.expect_fetch_user_by_id().times(1).returning(|_| Err(InternalError::NotFound));
 
-    let mut app = test::init_service(
+    let app = test::init_service(
         App::new()
             .app_data(Data::new(make_app_state(oauth_db, user_db)))
             .route("/idp/login", web::post().to(login)),
@@ -213,10 +306,303 @@ async fn test_login_invalid_credentials() {
         .set_payload(r#"{"username":"user@example.com","password":"wrong"}"#)
         .to_request();
 
-    let resp = test::call_service(&mut app, req).await;
+    let resp = test::call_service(&app, req).await;
     assert!(
         resp.status().is_client_error(),
         "expected 4xx for invalid credentials, got {}",
         resp.status()
     );
 }
+
+#[actix_rt::test]
+async fn test_login_essential_acr_unmet() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    let mut user_db = Box::new(core::MockUserDatabase::new());
+
+    user_db.expect_fetch_user_by_id().times(1).returning(|_| {
+        let hash = bcrypt::hash("pass", 4).unwrap();
+        Ok(User {
+            id: "user@example.com".into(),
+            password: format!("{{BCRYPT}}{}", hash),
+            email: None,
+            email_verified: false,
+            phone: None,
+            phone_number_verified: false,
+            given_name: "Test".into(),
+            family_name: "User".into(),
+            middle_name: None,
+            nickname: None,
+            preferred_username: None,
+            preferred_display_name: None,
+            address: None,
+            birthdate: None,
+            locale: None,
+            profile: None,
+            picture: None,
+            website: None,
+            gender: None,
+            zoneinfo: None,
+            updated_at: None,
+            attributes: None,
+        })
+    });
+    user_db
+        .expect_fetch_granted_scopes()
+        .times(1)
+        .returning(|_, _| Ok(HashSet::from(["openid".to_string()])));
+    user_db.expect_fetch_granted_claims().times(1).returning(|_, _| Ok(HashSet::new()));
+    oauth_db.expect_fetch_client_config().times(2).returning(|_| Ok(test_client()));
+    // no code is issued
+    oauth_db.expect_save_oauth_session().never();
+
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(make_app_state(oauth_db, user_db)))
+            .route("/idp/login", web::post().to(login)),
+    )
+    .await;
+
+    // the password alone does not reach the mfa class
+    let auth_ses = AuthSessionCookie {
+        client_id: CLIENT_ID.into(),
+        response_type: "code".into(),
+        scopes: "openid".into(),
+        redirect_uri: REDIRECT_URI.into(),
+        claims: Some(r#"{"id_token":{"acr":{"essential":true,"value":"urn:flipid:acr:mfa"}}}"#.into()),
+        ..Default::default()
+    };
+    let req = test::TestRequest::post()
+        .uri("/idp/login")
+        .insert_header(("Content-Type", "application/json"))
+        .insert_header(("Cookie", encrypted_auth_session(&auth_ses)))
+        .set_payload(r#"{"username":"user@example.com","password":"pass"}"#)
+        .to_request();
+
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+    let location = resp.headers().get("content-location").unwrap().to_str().unwrap();
+    assert!(location.contains("error=unmet_authentication_requirements"), "{}", location);
+}
+
+#[actix_rt::test]
+async fn test_login_consent_requested_claims() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    let mut user_db = Box::new(core::MockUserDatabase::new());
+
+    user_db.expect_fetch_user_by_id().times(1).returning(|_| {
+        let hash = bcrypt::hash("pass", 4).unwrap();
+        Ok(User {
+            id: "user@example.com".into(),
+            password: format!("{{BCRYPT}}{}", hash),
+            email: Some("user@example.com".into()),
+            email_verified: false,
+            phone: None,
+            phone_number_verified: false,
+            given_name: "Test".into(),
+            family_name: "User".into(),
+            middle_name: None,
+            nickname: None,
+            preferred_username: None,
+            preferred_display_name: None,
+            address: None,
+            birthdate: None,
+            locale: None,
+            profile: None,
+            picture: None,
+            website: None,
+            gender: None,
+            zoneinfo: None,
+            updated_at: None,
+            attributes: None,
+        })
+    });
+    user_db
+        .expect_fetch_granted_scopes()
+        .times(1)
+        .returning(|_, _| Ok(HashSet::from(["openid".to_string()])));
+    user_db.expect_fetch_granted_claims().times(1).returning(|_, _| Ok(HashSet::new()));
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(test_client()));
+    oauth_db.expect_save_oauth_session().never();
+
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(make_app_state(oauth_db, user_db)))
+            .route("/idp/login", web::post().to(login)),
+    )
+    .await;
+
+    // the scope is granted, but the name is requested without the profile scope (the client is not allowed the email)
+    let auth_ses = AuthSessionCookie {
+        client_id: CLIENT_ID.into(),
+        response_type: "code".into(),
+        scopes: "openid".into(),
+        redirect_uri: REDIRECT_URI.into(),
+        claims: Some(r#"{"userinfo":{"name":null,"email":{"essential":true}}}"#.into()),
+        ..Default::default()
+    };
+    let req = test::TestRequest::post()
+        .uri("/idp/login")
+        .insert_header(("Content-Type", "application/json"))
+        .insert_header(("Cookie", encrypted_auth_session(&auth_ses)))
+        .set_payload(r#"{"username":"user@example.com","password":"pass"}"#)
+        .to_request();
+
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(find_set_cookie(&resp, "sso").is_none(), "sso should not be set before consent");
+
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["op"], "GRANT");
+    assert!(body["scopes"].as_array().unwrap().is_empty());
+    assert_eq!(body["claims"], serde_json::json!(["name"]));
+}
+
+#[actix_rt::test]
+async fn test_consent_saves_requested_claims() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    let mut user_db = Box::new(core::MockUserDatabase::new());
+
+    user_db
+        .expect_save_granted_scopes()
+        .with(eq("user@example.com"), eq(CLIENT_ID), eq(vec!["openid".to_string()]))
+        .times(1)
+        .returning(|_, _, _| Ok(()));
+    // the email is not allowed for the client, it was not shown on the consent screen
+    user_db
+        .expect_save_granted_claims()
+        .with(eq("user@example.com"), eq(CLIENT_ID), eq(vec!["name".to_string()]))
+        .times(1)
+        .returning(|_, _, _| Ok(()));
+    oauth_db.expect_fetch_client_config().times(2).returning(|_| Ok(test_client()));
+    oauth_db.expect_save_oauth_session().times(1).returning(|_| Ok(()));
+
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(make_app_state(oauth_db, user_db)))
+            .route("/idp/consent", web::post().to(consent)),
+    )
+    .await;
+
+    let auth_ses = AuthSessionCookie {
+        client_id: CLIENT_ID.into(),
+        response_type: "code".into(),
+        scopes: "openid".into(),
+        redirect_uri: REDIRECT_URI.into(),
+        claims: Some(r#"{"userinfo":{"name":null,"email":{"essential":true}}}"#.into()),
+        subject: Some("user@example.com".into()),
+        login: Some(SSOCookie {
+            subject: "user@example.com".into(),
+            client_id: CLIENT_ID.into(),
+            auth_time: chrono::Utc::now().timestamp(),
+            amr: vec!["pwd".into()],
+            sid: Some("sid-1".into()),
+        }),
+        ..Default::default()
+    };
+    let req = test::TestRequest::post()
+        .uri("/idp/consent")
+        .insert_header(("Content-Type", "application/json"))
+        .insert_header(("Cookie", encrypted_auth_session(&auth_ses)))
+        .set_payload(r#"["openid"]"#)
+        .to_request();
+
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+    assert!(find_set_cookie(&resp, "sso").is_some());
+}
+
+#[actix_rt::test]
+async fn test_login_prompt_login_consent_renews_authentication() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    let mut user_db = Box::new(core::MockUserDatabase::new());
+    let old_auth_time = chrono::Utc::now().timestamp() - 3600;
+
+    user_db.expect_fetch_user_by_id().times(1).returning(|_| {
+        let hash = bcrypt::hash("pass", 4).unwrap();
+        Ok(User {
+            id: "user@example.com".into(),
+            password: format!("{{BCRYPT}}{}", hash),
+            email: None,
+            email_verified: false,
+            phone: None,
+            phone_number_verified: false,
+            given_name: "Test".into(),
+            family_name: "User".into(),
+            middle_name: None,
+            nickname: None,
+            preferred_username: None,
+            preferred_display_name: None,
+            address: None,
+            birthdate: None,
+            locale: None,
+            profile: None,
+            picture: None,
+            website: None,
+            gender: None,
+            zoneinfo: None,
+            updated_at: None,
+            attributes: None,
+        })
+    });
+    user_db.expect_save_granted_scopes().times(1).returning(|_, _, _| Ok(()));
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(test_client()));
+    // the code carries the fresh login, not the one of the SSO session
+    oauth_db
+        .expect_save_oauth_session()
+        .withf(move |s| s.auth_time.is_some_and(|t| t.and_utc().timestamp() > old_auth_time) && s.sid.as_deref() != Some("old-sid"))
+        .times(1)
+        .returning(|_| Ok(()));
+
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(make_app_state(oauth_db, user_db)))
+            .route("/idp/login", web::post().to(login))
+            .route("/idp/consent", web::post().to(consent)),
+    )
+    .await;
+
+    let key = common::test_key();
+    let old_sso = SSOCookie {
+        subject: "user@example.com".into(),
+        client_id: CLIENT_ID.into(),
+        auth_time: old_auth_time,
+        amr: vec!["pwd".into()],
+        sid: Some("old-sid".into()),
+    };
+    let mut jar = CookieJar::new();
+    jar.private_mut(&key).add(Cookie::new("sso", serde_json::to_string(&old_sso).unwrap()));
+    let sso_cookie = jar.delta().map(|c| format!("{}={}", c.name(), c.value())).next().unwrap();
+
+    let req = test::TestRequest::post()
+        .uri("/idp/login")
+        .insert_header(("Content-Type", "application/json"))
+        .insert_header((
+            "Cookie",
+            format!(
+                "{}; {}",
+                flip_auth_cookie_header_with_prompt("openid profile", Some("login consent")),
+                sso_cookie
+            ),
+        ))
+        .set_payload(r#"{"username":"user@example.com","password":"pass"}"#)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let flip_auth = Cookie::parse(find_set_cookie(&resp, "flip_auth").unwrap()).unwrap();
+
+    let req = test::TestRequest::post()
+        .uri("/idp/consent")
+        .insert_header(("Content-Type", "application/json"))
+        .insert_header(("Cookie", format!("flip_auth={}; {}", flip_auth.value(), sso_cookie)))
+        .set_payload(r#"["openid","profile"]"#)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+
+    // the SSO session is renewed with the fresh login
+    let mut jar = CookieJar::new();
+    jar.add_original(Cookie::parse(find_set_cookie(&resp, "sso").expect("expected sso Set-Cookie")).unwrap());
+    let sso: SSOCookie = serde_json::from_str(jar.private(&key).get("sso").unwrap().value()).unwrap();
+    assert!(sso.auth_time > old_auth_time, "{} <= {}", sso.auth_time, old_auth_time);
+    assert_ne!(sso.sid.as_deref(), Some("old-sid"));
+}
@@ -0,0 +1,193 @@
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::web::Data;
+use actix_web::{test, web, App};
+use chrono::Duration;
+use flipid::core::error::InternalError;
+use flipid::core::models::{OauthClient, PushedRequest};
+use flipid::core::{self, basic_auth, AppState, Secrets};
+use flipid::oidc::authorize::auth_get;
+use flipid::oidc::par::push_request;
+use mockall::predicate::*;
+use std::sync::Arc;
+
+const CLIENT_ID: &str = "test1";
+const CLIENT_SECRET: &str = "secret";
+const REQUEST_URI: &str = "urn:ietf:params:oauth:request_uri:6esc_11ACC5bwc014ltc14eY22c";
+
+fn test_client() -> OauthClient {
+    let hash = bcrypt::hash(CLIENT_SECRET, 4).unwrap();
+    OauthClient {
+        id: CLIENT_ID.into(),
+        secret: format!("{{BCRYPT}}{}", hash),
+        name: "Test1".into(),
+        callback_url: vec!["http://localhost:8080/callback".into()],
+        allowed_scopes: "openid profile email".into(),
+        ..Default::default()
+    }
+}
+
+fn pushed_request(client_id: &str, expires_in: i64) -> PushedRequest {
+    PushedRequest {
+        request_uri: REQUEST_URI.into(),
+        client_id: client_id.into(),
+        params: serde_json::json!({
+            "response_type": "code",
+            "client_id": client_id,
+            "scope": "openid profile",
+            "redirect_uri": "http://localhost:8080/callback",
+        })
+        .to_string(),
+        expiration: chrono::Utc::now().naive_utc() + Duration::seconds(expires_in),
+    }
+}
+
+fn app_state(oauth_db: Box<core::MockOauthDatabase>) -> AppState {
+    AppState::new(
+        common::test_key(),
+        oauth_db,
+        Box::new(core::MockUserDatabase::new()),
+        Arc::new(Secrets::load(&common::test_config().secrets).expect("test secrets")),
+        common::test_config(),
+    )
+}
+
+async fn call_par(oauth_db: Box<core::MockOauthDatabase>, auth_header: Option<&str>, body: &str) -> actix_web::dev::ServiceResponse {
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(app_state(oauth_db)))
+            .route("/oauth2/par", web::post().to(push_request)),
+    )
+    .await;
+
+    let mut req = test::TestRequest::post()
+        .uri("/oauth2/par")
+        .insert_header(("Content-Type", "application/x-www-form-urlencoded"))
+        .set_payload(body.to_owned());
+    if let Some(h) = auth_header {
+        req = req.insert_header(("Authorization", h));
+    }
+    test::call_service(&app, req.to_request()).await
+}
+
+async fn call_authorize(oauth_db: Box<core::MockOauthDatabase>, query: &str) -> actix_web::dev::ServiceResponse {
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(app_state(oauth_db)))
+            .route("/authorize", web::get().to(auth_get)),
+    )
+    .await;
+    test::call_service(&app, test::TestRequest::get().uri(&format!("/authorize?{}", query)).to_request()).await
+}
+
+#[actix_rt::test]
+async fn test_par_push_request() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    // client authentication + validate_auth
+    oauth_db.expect_fetch_client_config().times(2).returning(|_| Ok(test_client()));
+    oauth_db
+        .expect_save_pushed_request()
+        .withf(|r| r.client_id == CLIENT_ID && r.params.contains("openid profile"))
+        .times(1)
+        .returning(|_| Ok(()));
+
+    let body = "response_type=code&scope=openid%20profile&redirect_uri=http%3A%2F%2Flocalhost%3A8080%2Fcallback&state=xyz";
+    let resp = call_par(oauth_db, Some(&basic_auth(CLIENT_ID, CLIENT_SECRET)), body).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+
+    let json: serde_json::Value = test::read_body_json(resp).await;
+    assert!(json["request_uri"].as_str().unwrap().starts_with("urn:ietf:params:oauth:request_uri:"));
+    assert_eq!(json["expires_in"], 60);
+}
+
+#[actix_rt::test]
+async fn test_par_requires_client_authentication() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(test_client()));
+    oauth_db.expect_save_pushed_request().never();
+
+    let body = "client_id=test1&response_type=code&scope=openid&redirect_uri=http%3A%2F%2Flocalhost%3A8080%2Fcallback";
+    let resp = call_par(oauth_db, None, body).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+    let json: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(json["error"], "invalid_client");
+}
+
+#[actix_rt::test]
+async fn test_par_invalid_request() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(2).returning(|_| Ok(test_client()));
+    oauth_db.expect_save_pushed_request().never();
+
+    let body = "response_type=code&scope=admin&redirect_uri=http%3A%2F%2Flocalhost%3A8080%2Fcallback";
+    let resp = call_par(oauth_db, Some(&basic_auth(CLIENT_ID, CLIENT_SECRET)), body).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let json: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(json["error"], "invalid_scope");
+}
+
+#[actix_rt::test]
+async fn test_authorize_with_pushed_request() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db
+        .expect_consume_pushed_request()
+        .with(eq(REQUEST_URI), eq(CLIENT_ID))
+        .times(1)
+        .returning(|_, _| Ok(pushed_request(CLIENT_ID, 60)));
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(test_client()));
+
+    let resp = call_authorize(oauth_db, &format!("client_id=test1&request_uri={}", REQUEST_URI)).await;
+    assert_eq!(resp.status(), StatusCode::OK); // login page
+}
+
+#[actix_rt::test]
+async fn test_authorize_with_expired_pushed_request() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db
+        .expect_consume_pushed_request()
+        .with(eq(REQUEST_URI), eq(CLIENT_ID))
+        .times(1)
+        .returning(|_, _| Ok(pushed_request(CLIENT_ID, -1)));
+
+    let resp = call_authorize(oauth_db, &format!("client_id=test1&request_uri={}", REQUEST_URI)).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_rt::test]
+async fn test_authorize_with_pushed_request_of_another_client() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db
+        .expect_consume_pushed_request()
+        .with(eq(REQUEST_URI), eq("test2"))
+        .times(1)
+        .returning(|_, _| Err(InternalError::NotFound));
+
+    // the request of test1 is not consumed
+    let resp = call_authorize(oauth_db, &format!("client_id=test2&request_uri={}", REQUEST_URI)).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_rt::test]
+async fn test_authorize_with_pushed_request_without_client_id() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_consume_pushed_request().never();
+
+    let resp = call_authorize(oauth_db, &format!("request_uri={}", REQUEST_URI)).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_rt::test]
+async fn test_authorize_with_unknown_pushed_request() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db
+        .expect_consume_pushed_request()
+        .with(eq(REQUEST_URI), eq(CLIENT_ID))
+        .times(1)
+        .returning(|_, _| Err(InternalError::NotFound));
+
+    let resp = call_authorize(oauth_db, &format!("client_id=test1&request_uri={}", REQUEST_URI)).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,150 @@
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::web::Data;
+use actix_web::{test, web, App};
+use flipid::core::models::{OauthClient, OauthToken};
+use flipid::core::{self, basic_auth, AppState, Secrets};
+use flipid::oidc::revocation::revoke;
+use mockall::predicate::*;
+use std::sync::Arc;
+
+const CLIENT_ID: &str = "test1";
+const CLIENT_SECRET: &str = "secret";
+
+fn test_client() -> OauthClient {
+    let hash = bcrypt::hash(CLIENT_SECRET, 4).unwrap();
+    OauthClient {
+        id: CLIENT_ID.into(),
+        secret: format!("{{BCRYPT}}{}", hash),
+        name: "Test1".into(),
+        callback_url: vec!["http://localhost:8080/callback".into()],
+        allowed_scopes: "openid profile email".into(),
+        ..Default::default()
+    }
+}
+
+fn token(value: &str, token_type: &str, client_id: &str) -> OauthToken {
+    OauthToken {
+        token: value.into(),
+        token_type: token_type.into(),
+        client_id: client_id.into(),
+        scopes: Some("openid offline_access".into()),
+        subject: Some("user@example.com".into()),
+        expiration: Some(3600),
+        created: chrono::Utc::now().naive_utc(),
+        family: Some("family-1".into()),
+        ..Default::default()
+    }
+}
+
+async fn call_revoke(oauth_db: Box<core::MockOauthDatabase>, auth_header: Option<&str>, body: &str) -> actix_web::dev::ServiceResponse {
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(AppState::new(
+                common::test_key(),
+                oauth_db,
+                Box::new(core::MockUserDatabase::new()),
+                Arc::new(Secrets::load(&common::test_config().secrets).expect("test secrets")),
+                common::test_config(),
+            )))
+            .route("/oauth2/revoke", web::post().to(revoke)),
+    )
+    .await;
+
+    let mut req = test::TestRequest::post()
+        .uri("/oauth2/revoke")
+        .insert_header(("Content-Type", "application/x-www-form-urlencoded"))
+        .set_payload(body.to_owned());
+    if let Some(h) = auth_header {
+        req = req.insert_header(("Authorization", h));
+    }
+    test::call_service(&app, req.to_request()).await
+}
+
+#[actix_rt::test]
+async fn test_revoke_access_token() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db
+        .expect_fetch_client_config()
+        .with(eq(CLIENT_ID))
+        .times(1)
+        .returning(|_| Ok(test_client()));
+    oauth_db
+        .expect_load_token_data()
+        .with(eq("at-1"))
+        .times(1)
+        .returning(|t| Ok(token(t, "access", CLIENT_ID)));
+    oauth_db.expect_revoke_token().with(eq("at-1")).times(1).returning(|_| Ok(true));
+
+    let auth = basic_auth(CLIENT_ID, CLIENT_SECRET);
+    let resp = call_revoke(oauth_db, Some(&auth), "token=at-1&token_type_hint=access_token").await;
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[actix_rt::test]
+async fn test_revoke_refresh_token_revokes_family() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(test_client()));
+    oauth_db
+        .expect_load_token_data()
+        .with(eq("rt-1"))
+        .times(1)
+        .returning(|t| Ok(token(t, "refresh", CLIENT_ID)));
+    oauth_db.expect_revoke_token_family().with(eq("family-1")).times(1).returning(|_| Ok(2));
+
+    let auth = basic_auth(CLIENT_ID, CLIENT_SECRET);
+    let resp = call_revoke(oauth_db, Some(&auth), "token=rt-1&token_type_hint=refresh_token").await;
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[actix_rt::test]
+async fn test_revoke_unknown_token() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(test_client()));
+    oauth_db
+        .expect_load_token_data()
+        .times(1)
+        .returning(|_| Err(core::error::InternalError::NotFound));
+
+    let auth = basic_auth(CLIENT_ID, CLIENT_SECRET);
+    let resp = call_revoke(oauth_db, Some(&auth), "token=unknown").await;
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[actix_rt::test]
+async fn test_revoke_token_of_other_client() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(test_client()));
+    oauth_db
+        .expect_load_token_data()
+        .times(1)
+        .returning(|t| Ok(token(t, "access", "other-client")));
+
+    let auth = basic_auth(CLIENT_ID, CLIENT_SECRET);
+    let resp = call_revoke(oauth_db, Some(&auth), "token=at-1").await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_rt::test]
+async fn test_revoke_unknown_token_type_hint() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(test_client()));
+    oauth_db
+        .expect_load_token_data()
+        .with(eq("at-1"))
+        .times(1)
+        .returning(|t| Ok(token(t, "access", CLIENT_ID)));
+    oauth_db.expect_revoke_token().with(eq("at-1")).times(1).returning(|_| Ok(true));
+
+    let auth = basic_auth(CLIENT_ID, CLIENT_SECRET);
+    let resp = call_revoke(oauth_db, Some(&auth), "token=at-1&token_type_hint=id_token").await;
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[actix_rt::test]
+async fn test_revoke_no_auth_header() {
+    let oauth_db = Box::new(core::MockOauthDatabase::new());
+    let resp = call_revoke(oauth_db, None, "token=at-1").await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+}
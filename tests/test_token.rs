@@ -2,7 +2,7 @@ mod common;
 
 use actix_web::http::StatusCode;
 use actix_web::{test, web, web::Data, App};
-use flipid::core::models::{OauthClient, OauthSession};
+use flipid::core::models::{OauthClient, OauthSession, OauthToken};
 use flipid::core::{self, AppState, Secrets};
 use flipid::oidc::token::token_endpoint;
 use mockall::predicate::*;
@@ -16,6 +16,7 @@ fn test_client() -> OauthClient {
         name: "Test1".into(),
         callback_url: vec!["http://localhost:8080/callback".into()],
         allowed_scopes: "openid profile email phone address".into(),
+        ..Default::default()
     }
 }
 
@@ -24,10 +25,9 @@ fn future_session(code: &str) -> OauthSession {
         auth_code: code.into(),
         client_id: "test1".into(),
         scopes: "openid profile".into(),
-        nonce: None,
         subject: "user@example.com".into(),
         expiration: chrono::Utc::now().naive_utc() + chrono::Duration::minutes(60),
-        auth_time: None,
+        ..Default::default()
     }
 }
 
@@ -36,10 +36,9 @@ fn expired_session(code: &str) -> OauthSession {
         auth_code: code.into(),
         client_id: "test1".into(),
         scopes: "openid profile".into(),
-        nonce: None,
         subject: "user@example.com".into(),
         expiration: chrono::Utc::now().naive_utc() - chrono::Duration::minutes(1),
-        auth_time: None,
+        ..Default::default()
     }
 }
 
@@ -73,7 +72,7 @@ async fn test_token_happy_path() {
 
     oauth_db.expect_save_oauth_token().times(1).returning(|_| Ok(()));
 
-    let mut app = test::init_service(
+    let app = test::init_service(
         App::new()
             .app_data(Data::new(AppState::new(
                 common::test_key(),
@@ -94,7 +93,7 @@ async fn test_token_happy_path() {
         .set_payload(body)
         .to_request();
 
-    let resp = test::call_service(&mut app, req).await;
+    let resp = test::call_service(&app, req).await;
     assert_eq!(resp.status(), StatusCode::OK);
 
     let body: serde_json::Value = test::read_body_json(resp).await;
@@ -123,7 +122,7 @@ async fn test_token_expired_code() {
         .times(1)
         .returning(|c| Ok(expired_session(c)));
 
-    let mut app = test::init_service(
+    let app = test::init_service(
         App::new()
             .app_data(Data::new(AppState::new(
                 common::test_key(),
@@ -144,7 +143,7 @@ async fn test_token_expired_code() {
         .set_payload(body)
         .to_request();
 
-    let resp = test::call_service(&mut app, req).await;
+    let resp = test::call_service(&app, req).await;
     assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
 }
 
@@ -166,7 +165,7 @@ async fn test_token_redirect_mismatch() {
         .times(2)
         .returning(|_| Ok(test_client()));
 
-    let mut app = test::init_service(
+    let app = test::init_service(
         App::new()
             .app_data(Data::new(AppState::new(
                 common::test_key(),
@@ -190,7 +189,7 @@ async fn test_token_redirect_mismatch() {
         .set_payload(body)
         .to_request();
 
-    let resp = test::call_service(&mut app, req).await;
+    let resp = test::call_service(&app, req).await;
     assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
 }
 
@@ -206,7 +205,7 @@ async fn test_token_invalid_credentials() {
         .times(1)
         .returning(|_| Ok(test_client()));
 
-    let mut app = test::init_service(
+    let app = test::init_service(
         App::new()
             .app_data(Data::new(AppState::new(
                 common::test_key(),
@@ -229,7 +228,7 @@ async fn test_token_invalid_credentials() {
         .set_payload(body)
         .to_request();
 
-    let resp = test::call_service(&mut app, req).await;
+    let resp = test::call_service(&app, req).await;
     assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
 }
 
@@ -245,7 +244,7 @@ async fn test_token_unsupported_grant_type() {
         .times(1)
         .returning(|_| Ok(test_client()));
 
-    let mut app = test::init_service(
+    let app = test::init_service(
         App::new()
             .app_data(Data::new(AppState::new(
                 common::test_key(),
@@ -258,7 +257,7 @@ async fn test_token_unsupported_grant_type() {
     )
     .await;
 
-    let body = format!("grant_type=refresh_token&code={}&redirect_uri={}", CODE, REDIRECT);
+    let body = format!("grant_type=password&code={}&redirect_uri={}", CODE, REDIRECT);
     let req = test::TestRequest::post()
         .uri("/oauth2/token")
         .insert_header(("Authorization", VALID_AUTH))
@@ -266,7 +265,7 @@ async fn test_token_unsupported_grant_type() {
         .set_payload(body)
         .to_request();
 
-    let resp = test::call_service(&mut app, req).await;
+    let resp = test::call_service(&app, req).await;
     assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
 }
 
@@ -288,7 +287,7 @@ async fn test_token_code_not_found() {
         .times(1)
         .returning(|_| Err(core::error::InternalError::NotFound));
 
-    let mut app = test::init_service(
+    let app = test::init_service(
         App::new()
             .app_data(Data::new(AppState::new(
                 common::test_key(),
@@ -309,6 +308,685 @@ async fn test_token_code_not_found() {
         .set_payload(body)
         .to_request();
 
-    let resp = test::call_service(&mut app, req).await;
-    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"], "invalid_grant");
+}
+
+#[actix_rt::test]
+async fn test_token_code_replay_revokes_tokens() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(test_client()));
+    oauth_db.expect_consume_oauth_session_by_code().times(1).returning(|c| {
+        Ok(OauthSession {
+            consumed: true,
+            family: Some("family-7".into()),
+            ..future_session(c)
+        })
+    });
+    oauth_db.expect_revoke_token_family().with(eq("family-7")).times(1).returning(|_| Ok(2));
+    oauth_db.expect_save_oauth_token().never();
+
+    let resp = call_token(
+        oauth_db,
+        &format!("grant_type=authorization_code&code={}&redirect_uri={}", CODE, REDIRECT),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"], "invalid_grant");
+}
+
+#[actix_rt::test]
+async fn test_token_code_family() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(2).returning(|_| Ok(test_client()));
+    oauth_db.expect_consume_oauth_session_by_code().times(1).returning(|c| {
+        Ok(OauthSession {
+            family: Some("family-7".into()),
+            ..future_session(c)
+        })
+    });
+    // the access token is linked to the code, to be revoked on a replay
+    oauth_db
+        .expect_save_oauth_token()
+        .withf(|t| t.token_type == "access" && t.family.as_deref() == Some("family-7"))
+        .times(1)
+        .returning(|_| Ok(()));
+
+    let resp = call_token(
+        oauth_db,
+        &format!("grant_type=authorization_code&code={}&redirect_uri={}", CODE, REDIRECT),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+fn refresh_token(token: &str, revoked: bool) -> OauthToken {
+    OauthToken {
+        token: token.into(),
+        token_type: "refresh".into(),
+        client_id: "test1".into(),
+        scopes: Some("openid profile offline_access".into()),
+        subject: Some("user@example.com".into()),
+        expiration: Some(3600),
+        created: chrono::Utc::now().naive_utc(),
+        family: Some("family-1".into()),
+        revoked,
+        ..Default::default()
+    }
+}
+
+async fn call_token(oauth_db: Box<core::MockOauthDatabase>, body: &str) -> actix_web::dev::ServiceResponse {
+    call_token_with_config(oauth_db, common::test_config(), body).await
+}
+
+async fn call_token_with_config(oauth_db: Box<core::MockOauthDatabase>, config: core::Config, body: &str) -> actix_web::dev::ServiceResponse {
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(AppState::new(
+                common::test_key(),
+                oauth_db,
+                Box::new(core::MockUserDatabase::new()),
+                test_secrets(),
+                config,
+            )))
+            .route("/oauth2/token", web::post().to(token_endpoint)),
+    )
+    .await;
+
+    let req = test::TestRequest::post()
+        .uri("/oauth2/token")
+        .insert_header(("Authorization", VALID_AUTH))
+        .insert_header(("Content-Type", "application/x-www-form-urlencoded"))
+        .set_payload(body.to_owned())
+        .to_request();
+    test::call_service(&app, req).await
+}
+
+#[actix_rt::test]
+async fn test_token_offline_access_issues_refresh_token() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(2).returning(|_| Ok(test_client()));
+    oauth_db.expect_consume_oauth_session_by_code().times(1).returning(|c| {
+        Ok(OauthSession {
+            scopes: "openid offline_access".into(),
+            ..future_session(c)
+        })
+    });
+    oauth_db
+        .expect_save_oauth_token()
+        .withf(|t| t.token_type == "access" && t.family.is_some())
+        .times(1)
+        .returning(|_| Ok(()));
+    oauth_db
+        .expect_save_oauth_token()
+        .withf(|t| t.token_type == "refresh" && t.family.is_some() && t.parent.is_none())
+        .times(1)
+        .returning(|_| Ok(()));
+
+    let resp = call_token(
+        oauth_db,
+        &format!("grant_type=authorization_code&code={}&redirect_uri={}", CODE, REDIRECT),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert!(body["refresh_token"].is_string());
+}
+
+#[actix_rt::test]
+async fn test_token_id_token_claims() {
+    use base64::prelude::*;
+
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(2).returning(|_| Ok(test_client()));
+    oauth_db.expect_consume_oauth_session_by_code().times(1).returning(|c| {
+        Ok(OauthSession {
+            sid: Some("sso-session-1".into()),
+            ..future_session(c)
+        })
+    });
+    oauth_db.expect_save_oauth_token().times(1).returning(|_| Ok(()));
+
+    let mut config = common::test_config();
+    config.oauth.id_token.audiences = vec!["https://api.example.com".into()];
+    let resp = call_token_with_config(
+        oauth_db,
+        config,
+        &format!("grant_type=authorization_code&code={}&redirect_uri={}", CODE, REDIRECT),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let payload = body["id_token"].as_str().unwrap().split('.').nth(1).unwrap();
+    let claims: serde_json::Value = serde_json::from_slice(&BASE64_URL_SAFE_NO_PAD.decode(payload).unwrap()).unwrap();
+    assert_eq!(claims["aud"], serde_json::json!(["test1", "https://api.example.com"]));
+    assert_eq!(claims["azp"], "test1");
+    assert_eq!(claims["sid"], "sso-session-1");
+    assert!(claims["jti"].is_string());
+
+    // RS256: the left half of the SHA-256 hash of the access token
+    let digest = openssl::sha::sha256(body["access_token"].as_str().unwrap().as_bytes());
+    assert_eq!(claims["at_hash"], BASE64_URL_SAFE_NO_PAD.encode(&digest[..16]));
+}
+
+#[actix_rt::test]
+async fn test_token_refresh_rotates_token() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(test_client()));
+    oauth_db
+        .expect_load_token_data()
+        .with(eq("rt-1"))
+        .times(1)
+        .returning(|t| Ok(refresh_token(t, false)));
+    oauth_db.expect_revoke_token().with(eq("rt-1")).times(1).returning(|_| Ok(true));
+    oauth_db
+        .expect_save_oauth_token()
+        .withf(|t| t.token_type == "access" && t.scopes.as_deref() == Some("openid"))
+        .times(1)
+        .returning(|_| Ok(()));
+    oauth_db
+        .expect_save_oauth_token()
+        .withf(|t| {
+            t.token_type == "refresh"
+                && t.scopes.as_deref() == Some("openid profile offline_access")
+                && t.family.as_deref() == Some("family-1")
+                && t.parent.as_deref() == Some("rt-1")
+        })
+        .times(1)
+        .returning(|_| Ok(()));
+
+    let resp = call_token(oauth_db, "grant_type=refresh_token&refresh_token=rt-1&scope=openid").await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert!(body["access_token"].is_string());
+    assert!(body["refresh_token"].is_string());
+    assert_ne!(body["refresh_token"], "rt-1");
+}
+
+#[actix_rt::test]
+async fn test_token_refresh_reuse_revokes_family() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(test_client()));
+    oauth_db
+        .expect_load_token_data()
+        .with(eq("rt-old"))
+        .times(1)
+        .returning(|t| Ok(refresh_token(t, true)));
+    oauth_db.expect_revoke_token_family().with(eq("family-1")).times(1).returning(|_| Ok(3));
+
+    let resp = call_token(oauth_db, "grant_type=refresh_token&refresh_token=rt-old").await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"], "invalid_grant");
+}
+
+#[actix_rt::test]
+async fn test_token_refresh_concurrent_reuse_revokes_family() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(test_client()));
+    oauth_db.expect_load_token_data().times(1).returning(|t| Ok(refresh_token(t, false)));
+    // another request rotated the token after it was loaded
+    oauth_db.expect_revoke_token().with(eq("rt-1")).times(1).returning(|_| Ok(false));
+    oauth_db.expect_revoke_token_family().with(eq("family-1")).times(1).returning(|_| Ok(2));
+    oauth_db.expect_save_oauth_token().never();
+
+    let resp = call_token(oauth_db, "grant_type=refresh_token&refresh_token=rt-1").await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"], "invalid_grant");
+}
+
+#[actix_rt::test]
+async fn test_token_refresh_scope_escalation() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(test_client()));
+    oauth_db.expect_load_token_data().times(1).returning(|t| Ok(refresh_token(t, false)));
+
+    let resp = call_token(oauth_db, "grant_type=refresh_token&refresh_token=rt-1&scope=openid%20email").await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"], "invalid_scope");
+}
+
+const PKCE_VERIFIER: &str = "dBjftJeZ4CVP-mJ92K3fE5vOcNBK7z8aRcRAHJiakWk";
+const PKCE_CHALLENGE: &str = "BGRdnTHjMfKkm_fK-RIa_dCS3vi4GjsEkjaKJgwLoEo";
+
+fn pkce_session(code: &str) -> OauthSession {
+    OauthSession {
+        code_challenge: Some(PKCE_CHALLENGE.into()),
+        code_challenge_method: Some("S256".into()),
+        ..future_session(code)
+    }
+}
+
+#[actix_rt::test]
+async fn test_token_pkce_s256() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(2).returning(|_| Ok(test_client()));
+    oauth_db
+        .expect_consume_oauth_session_by_code()
+        .times(1)
+        .returning(|c| Ok(pkce_session(c)));
+    oauth_db.expect_save_oauth_token().times(1).returning(|_| Ok(()));
+
+    let body = format!(
+        "grant_type=authorization_code&code={}&redirect_uri={}&code_verifier={}",
+        CODE, REDIRECT, PKCE_VERIFIER
+    );
+    let resp = call_token(oauth_db, &body).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[actix_rt::test]
+async fn test_token_pkce_verifier_mismatch() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(test_client()));
+    oauth_db
+        .expect_consume_oauth_session_by_code()
+        .times(1)
+        .returning(|c| Ok(pkce_session(c)));
+
+    let body = format!(
+        "grant_type=authorization_code&code={}&redirect_uri={}&code_verifier={}",
+        CODE,
+        REDIRECT,
+        "x".repeat(43)
+    );
+    let resp = call_token(oauth_db, &body).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"], "invalid_grant");
+}
+
+#[actix_rt::test]
+async fn test_token_pkce_verifier_missing() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(test_client()));
+    oauth_db
+        .expect_consume_oauth_session_by_code()
+        .times(1)
+        .returning(|c| Ok(pkce_session(c)));
+
+    let resp = call_token(
+        oauth_db,
+        &format!("grant_type=authorization_code&code={}&redirect_uri={}", CODE, REDIRECT),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+fn public_client() -> OauthClient {
+    OauthClient {
+        secret: String::new(),
+        client_type: "public".into(),
+        token_endpoint_auth_method: "none".into(),
+        ..test_client()
+    }
+}
+
+async fn call_token_without_auth(oauth_db: Box<core::MockOauthDatabase>, body: &str) -> actix_web::dev::ServiceResponse {
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(AppState::new(
+                common::test_key(),
+                oauth_db,
+                Box::new(core::MockUserDatabase::new()),
+                test_secrets(),
+                common::test_config(),
+            )))
+            .route("/oauth2/token", web::post().to(token_endpoint)),
+    )
+    .await;
+
+    let req = test::TestRequest::post()
+        .uri("/oauth2/token")
+        .insert_header(("Content-Type", "application/x-www-form-urlencoded"))
+        .set_payload(body.to_owned())
+        .to_request();
+    test::call_service(&app, req).await
+}
+
+#[actix_rt::test]
+async fn test_token_public_client_with_pkce() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(2).returning(|_| Ok(public_client()));
+    oauth_db
+        .expect_consume_oauth_session_by_code()
+        .times(1)
+        .returning(|c| Ok(pkce_session(c)));
+    oauth_db.expect_save_oauth_token().times(1).returning(|_| Ok(()));
+
+    let body = format!(
+        "grant_type=authorization_code&client_id=test1&code={}&redirect_uri={}&code_verifier={}",
+        CODE, REDIRECT, PKCE_VERIFIER
+    );
+    let resp = call_token_without_auth(oauth_db, &body).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[actix_rt::test]
+async fn test_token_public_client_without_pkce() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(public_client()));
+    oauth_db
+        .expect_consume_oauth_session_by_code()
+        .times(1)
+        .returning(|c| Ok(future_session(c)));
+
+    let body = format!("grant_type=authorization_code&client_id=test1&code={}&redirect_uri={}", CODE, REDIRECT);
+    let resp = call_token_without_auth(oauth_db, &body).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_rt::test]
+async fn test_token_confidential_client_without_secret() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(test_client()));
+
+    let body = format!("grant_type=authorization_code&client_id=test1&code={}&redirect_uri={}", CODE, REDIRECT);
+    let resp = call_token_without_auth(oauth_db, &body).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+}
+
+fn private_key_jwt_client() -> OauthClient {
+    let pem = std::fs::read(common::TEST_RSA_PEM).unwrap();
+    let key = jsonwebtoken::EncodingKey::from_rsa_pem(&pem).unwrap();
+    let mut jwk = jsonwebtoken::jwk::Jwk::from_encoding_key(&key, jsonwebtoken::Algorithm::RS256).unwrap();
+    jwk.common.key_id = Some("client-key".into());
+    OauthClient {
+        token_endpoint_auth_method: "private_key_jwt".into(),
+        jwks: Some(jsonwebtoken::jwk::JwkSet { keys: vec![jwk] }),
+        ..test_client()
+    }
+}
+
+fn client_assertion(aud: &str) -> String {
+    let now = chrono::Utc::now().timestamp();
+    signed_assertion(serde_json::json!({
+        "iss": "test1",
+        "sub": "test1",
+        "aud": aud,
+        "jti": "assertion-1",
+        "iat": now,
+        "exp": now + 60,
+    }))
+}
+
+fn signed_assertion(claims: serde_json::Value) -> String {
+    let pem = std::fs::read(common::TEST_RSA_PEM).unwrap();
+    let key = jsonwebtoken::EncodingKey::from_rsa_pem(&pem).unwrap();
+    let mut header = jsonwebtoken::Header::new(jsonwebtoken::Algorithm::RS256);
+    header.kid = Some("client-key".into());
+    jsonwebtoken::encode(&header, &claims, &key).unwrap()
+}
+
+fn assertion_body(assertion: &str) -> String {
+    format!(
+        "grant_type=authorization_code&code={}&redirect_uri={}&client_assertion_type={}&client_assertion={}",
+        CODE, REDIRECT, "urn:ietf:params:oauth:client-assertion-type:jwt-bearer", assertion
+    )
+}
+
+#[actix_rt::test]
+async fn test_token_client_secret_post() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(2).returning(|_| {
+        Ok(OauthClient {
+            token_endpoint_auth_method: "client_secret_post".into(),
+            ..test_client()
+        })
+    });
+    oauth_db
+        .expect_consume_oauth_session_by_code()
+        .times(1)
+        .returning(|c| Ok(future_session(c)));
+    oauth_db.expect_save_oauth_token().times(1).returning(|_| Ok(()));
+
+    let body = format!(
+        "grant_type=authorization_code&client_id=test1&client_secret=test1&code={}&redirect_uri={}",
+        CODE, REDIRECT
+    );
+    let resp = call_token_without_auth(oauth_db, &body).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[actix_rt::test]
+async fn test_token_private_key_jwt() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(2).returning(|_| Ok(private_key_jwt_client()));
+    oauth_db
+        .expect_consume_oauth_session_by_code()
+        .times(1)
+        .returning(|c| Ok(future_session(c)));
+    oauth_db.expect_save_oauth_token().times(1).returning(|_| Ok(()));
+    oauth_db
+        .expect_save_client_assertion()
+        .withf(|a| a.client_id == "test1" && a.jti == "assertion-1")
+        .times(1)
+        .returning(|_| Ok(true));
+
+    let body = assertion_body(&client_assertion("https://flipid.local:9000"));
+    let resp = call_token_without_auth(oauth_db, &body).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[actix_rt::test]
+async fn test_token_private_key_jwt_replayed() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(private_key_jwt_client()));
+    // the jti was already used
+    oauth_db.expect_save_client_assertion().times(1).returning(|_| Ok(false));
+    oauth_db.expect_consume_oauth_session_by_code().never();
+
+    let body = assertion_body(&client_assertion("https://flipid.local:9000"));
+    let resp = call_token_without_auth(oauth_db, &body).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[actix_rt::test]
+async fn test_token_private_key_jwt_without_jti() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(private_key_jwt_client()));
+    oauth_db.expect_save_client_assertion().never();
+
+    let now = chrono::Utc::now().timestamp();
+    let assertion = signed_assertion(serde_json::json!({
+        "iss": "test1",
+        "sub": "test1",
+        "aud": "https://flipid.local:9000",
+        "iat": now,
+        "exp": now + 60,
+    }));
+    let resp = call_token_without_auth(oauth_db, &assertion_body(&assertion)).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[actix_rt::test]
+async fn test_token_encrypted_id_token() {
+    use base64::prelude::*;
+    use openssl::pkey::PKey;
+    use openssl::symm::{decrypt_aead, Cipher};
+
+    let pem = std::fs::read(common::TEST_RSA_PEM).unwrap();
+    let key = openssl::rsa::Rsa::private_key_from_pem(&pem).unwrap();
+    let jwks = serde_json::json!({"keys": [{
+        "kty": "RSA",
+        "use": "enc",
+        "kid": "client-enc",
+        "n": BASE64_URL_SAFE_NO_PAD.encode(key.n().to_vec()),
+        "e": BASE64_URL_SAFE_NO_PAD.encode(key.e().to_vec()),
+    }]});
+
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(2).returning(move |_| {
+        Ok(OauthClient {
+            jwks: Some(serde_json::from_value(jwks.clone()).unwrap()),
+            id_token_encrypted_response_alg: Some("RSA-OAEP-256".into()),
+            id_token_encrypted_response_enc: Some("A256GCM".into()),
+            ..test_client()
+        })
+    });
+    oauth_db
+        .expect_consume_oauth_session_by_code()
+        .times(1)
+        .returning(|c| Ok(future_session(c)));
+    oauth_db.expect_save_oauth_token().times(1).returning(|_| Ok(()));
+
+    let resp = call_token(
+        oauth_db,
+        &format!("grant_type=authorization_code&code={}&redirect_uri={}", CODE, REDIRECT),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let jwe = body["id_token"].as_str().unwrap();
+    let parts: Vec<Vec<u8>> = jwe.split('.').map(|p| BASE64_URL_SAFE_NO_PAD.decode(p).unwrap()).collect();
+    assert_eq!(parts.len(), 5);
+    let header: serde_json::Value = serde_json::from_slice(&parts[0]).unwrap();
+    assert_eq!(
+        header,
+        serde_json::json!({"alg": "RSA-OAEP-256", "enc": "A256GCM", "kid": "client-enc", "cty": "JWT"})
+    );
+
+    // unwrap the content encryption key and decrypt the signed id_token
+    let pkey = PKey::from_rsa(key).unwrap();
+    let mut ctx = openssl::pkey_ctx::PkeyCtx::new(&pkey).unwrap();
+    ctx.decrypt_init().unwrap();
+    ctx.set_rsa_padding(openssl::rsa::Padding::PKCS1_OAEP).unwrap();
+    ctx.set_rsa_oaep_md(openssl::md::Md::sha256()).unwrap();
+    ctx.set_rsa_mgf1_md(openssl::md::Md::sha256()).unwrap();
+    let mut cek = Vec::new();
+    ctx.decrypt_to_vec(&parts[1], &mut cek).unwrap();
+    let aad = jwe.split('.').next().unwrap().as_bytes();
+    let id_token = decrypt_aead(Cipher::aes_256_gcm(), &cek, Some(&parts[2]), aad, &parts[3], &parts[4]).unwrap();
+
+    let id_token = String::from_utf8(id_token).unwrap();
+    assert_eq!(jsonwebtoken::decode_header(&id_token).unwrap().alg, jsonwebtoken::Algorithm::RS256);
+    let payload = id_token.split('.').nth(1).unwrap();
+    let claims: serde_json::Value = serde_json::from_slice(&BASE64_URL_SAFE_NO_PAD.decode(payload).unwrap()).unwrap();
+    assert_eq!(claims["aud"], "test1");
+    assert_eq!(claims["sub"], future_session(CODE).subject);
+}
+
+#[actix_rt::test]
+async fn test_token_private_key_jwt_wrong_audience() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(private_key_jwt_client()));
+
+    let body = assertion_body(&client_assertion("https://evil.example.com"));
+    let resp = call_token_without_auth(oauth_db, &body).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[actix_rt::test]
+async fn test_token_auth_method_mismatch() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(private_key_jwt_client()));
+
+    // the client registered private_key_jwt, it must not fall back to its secret
+    let resp = call_token(
+        oauth_db,
+        &format!("grant_type=authorization_code&code={}&redirect_uri={}", CODE, REDIRECT),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[actix_rt::test]
+async fn test_token_client_credentials() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(test_client()));
+    oauth_db
+        .expect_save_oauth_token()
+        .withf(|t| t.token_type == "access" && t.subject.is_none() && t.scopes.as_deref() == Some("profile email"))
+        .times(1)
+        .returning(|_| Ok(()));
+
+    let resp = call_token(oauth_db, "grant_type=client_credentials&scope=profile%20email").await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert!(body["access_token"].is_string());
+    assert!(body["id_token"].is_null());
+    assert!(body.get("refresh_token").is_none());
+}
+
+#[actix_rt::test]
+async fn test_token_client_credentials_scope_not_allowed() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(test_client()));
+
+    let resp = call_token(oauth_db, "grant_type=client_credentials&scope=admin").await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"], "invalid_scope");
+}
+
+#[actix_rt::test]
+async fn test_token_client_credentials_public_client() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(1).returning(|_| Ok(public_client()));
+
+    let resp = call_token_without_auth(oauth_db, "grant_type=client_credentials&client_id=test1").await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"], "unauthorized_client");
+}
+
+#[actix_rt::test]
+async fn test_token_jwt_access_token() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    oauth_db.expect_fetch_client_config().times(2).returning(|_| Ok(test_client()));
+    oauth_db
+        .expect_consume_oauth_session_by_code()
+        .times(1)
+        .returning(|c| Ok(future_session(c)));
+    oauth_db.expect_save_oauth_token().times(1).returning(|_| Ok(()));
+
+    let mut config = common::test_config();
+    config.oauth.access_token = Some(core::config::AccessTokenConfig {
+        kind: "JWT".into(),
+        signing_alg: Some(jsonwebtoken::Algorithm::RS256),
+        available_signing: None,
+        audience: Some("https://api.example.com".into()),
+    });
+
+    let body = format!("grant_type=authorization_code&code={}&redirect_uri={}", CODE, REDIRECT);
+    let resp = call_token_with_config(oauth_db, config, &body).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let access_token = body["access_token"].as_str().unwrap();
+
+    let header = jsonwebtoken::decode_header(access_token).unwrap();
+    assert_eq!(header.typ.as_deref(), Some("at+jwt"));
+    assert_eq!(header.kid.as_deref(), Some(common::TEST_SECRET_NAME));
+
+    let pem = std::fs::read(common::TEST_RSA_PEM).unwrap();
+    let jwk =
+        jsonwebtoken::jwk::Jwk::from_encoding_key(&jsonwebtoken::EncodingKey::from_rsa_pem(&pem).unwrap(), jsonwebtoken::Algorithm::RS256).unwrap();
+    let mut validation = jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::RS256);
+    validation.set_audience(&["https://api.example.com"]);
+    let claims = jsonwebtoken::decode::<serde_json::Value>(access_token, &jsonwebtoken::DecodingKey::from_jwk(&jwk).unwrap(), &validation)
+        .unwrap()
+        .claims;
+    assert_eq!(claims["sub"], "user@example.com");
+    assert_eq!(claims["client_id"], "test1");
+    assert_eq!(claims["scope"], "openid profile");
+    assert!(claims["jti"].is_string());
 }
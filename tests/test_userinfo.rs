@@ -3,7 +3,8 @@ mod common;
 use actix_web::http::StatusCode;
 use actix_web::web::Data;
 use actix_web::{test, web, App};
-use flipid::core::models::{OauthToken, User};
+use base64::prelude::*;
+use flipid::core::models::{Address, OauthClient, OauthToken, User};
 use flipid::core::{self, AppState, Secrets};
 use flipid::oidc::userinfo::userinfo_endpoint;
 use mockall::predicate::*;
@@ -18,8 +19,8 @@ fn token_with_scopes(scopes: &str) -> OauthToken {
         client_id: "test1".into(),
         scopes: Some(scopes.into()),
         subject: Some("user@example.com".into()),
-        expiration: None,
         created: chrono::Utc::now().naive_utc(),
+        ..Default::default()
     }
 }
 
@@ -28,13 +29,30 @@ fn test_user() -> User {
         id: "user@example.com".into(),
         password: "hashed".into(),
         email: Some("user@example.com".into()),
+        email_verified: false,
         phone: Some("+1234567890".into()),
+        phone_number_verified: false,
         given_name: "John".into(),
         family_name: "Doe".into(),
+        middle_name: None,
+        nickname: None,
+        preferred_username: None,
         preferred_display_name: None,
-        address: Some("123 Main St".into()),
+        address: Some(Address {
+            formatted: Some("123 Main St, Springfield".into()),
+            street_address: Some("123 Main St".into()),
+            locality: Some("Springfield".into()),
+            ..Default::default()
+        }),
         birthdate: Some("1990-01-01".into()),
         locale: Some("en-US".into()),
+        profile: None,
+        picture: None,
+        website: None,
+        gender: None,
+        zoneinfo: None,
+        updated_at: None,
+        attributes: None,
     }
 }
 
@@ -42,12 +60,33 @@ fn bearer(token: &str) -> String {
     format!("Bearer {}", token)
 }
 
+fn test_client() -> OauthClient {
+    OauthClient {
+        id: "test1".into(),
+        secret: "secret".into(),
+        name: "Test".into(),
+        callback_url: vec!["http://localhost:8080/callback".into()],
+        allowed_scopes: "openid profile email phone address".into(),
+        ..Default::default()
+    }
+}
+
 async fn call_userinfo(
     oauth_db: Box<core::MockOauthDatabase>,
     user_db: Box<core::MockUserDatabase>,
     auth_header: Option<&str>,
 ) -> actix_web::dev::ServiceResponse {
-    let mut app = test::init_service(
+    call_userinfo_for(oauth_db, user_db, auth_header, test_client()).await
+}
+
+async fn call_userinfo_for(
+    mut oauth_db: Box<core::MockOauthDatabase>,
+    user_db: Box<core::MockUserDatabase>,
+    auth_header: Option<&str>,
+    client: OauthClient,
+) -> actix_web::dev::ServiceResponse {
+    oauth_db.expect_fetch_client_config().returning(move |_| Ok(client.clone()));
+    let app = test::init_service(
         App::new()
             .app_data(Data::new(AppState::new(
                 common::test_key(),
@@ -64,7 +103,7 @@ async fn call_userinfo(
     if let Some(h) = auth_header {
         req = req.insert_header(("Authorization", h));
     }
-    test::call_service(&mut app, req.to_request()).await
+    test::call_service(&app, req.to_request()).await
 }
 
 #[actix_rt::test]
@@ -95,6 +134,7 @@ async fn test_userinfo_happy_path() {
     assert_eq!(body["email_verified"], false);
     assert!(body.get("phone_number").is_none());
     assert!(body.get("address").is_none());
+    assert!(body.get("preferred_username").is_none()); // the user has none, the login name is not released
 }
 
 #[actix_rt::test]
@@ -108,11 +148,17 @@ async fn test_userinfo_all_scopes() {
         .times(1)
         .returning(|_| Ok(token_with_scopes("openid profile email phone address")));
 
-    user_db
-        .expect_fetch_user_by_id()
-        .with(eq("user@example.com"))
-        .times(1)
-        .returning(|_| Ok(test_user()));
+    user_db.expect_fetch_user_by_id().with(eq("user@example.com")).times(1).returning(|_| {
+        Ok(User {
+            email_verified: true,
+            nickname: Some("Johnny".into()),
+            preferred_username: Some("j.doe".into()),
+            picture: Some("https://example.com/john.png".into()),
+            zoneinfo: Some("Europe/Berlin".into()),
+            updated_at: chrono::DateTime::from_timestamp(1700000000, 0).map(|d| d.naive_utc()),
+            ..test_user()
+        })
+    });
 
     let resp = call_userinfo(oauth_db, user_db, Some(&bearer(ACCESS_TOKEN))).await;
     assert_eq!(resp.status(), StatusCode::OK);
@@ -120,10 +166,21 @@ async fn test_userinfo_all_scopes() {
     let body: serde_json::Value = test::read_body_json(resp).await;
     assert_eq!(body["sub"], "user@example.com");
     assert_eq!(body["email"], "user@example.com");
+    assert_eq!(body["email_verified"], true);
     assert_eq!(body["phone_number"], "+1234567890");
-    assert_eq!(body["address"], "123 Main St");
+    assert_eq!(
+        body["address"],
+        serde_json::json!({"formatted": "123 Main St, Springfield", "street_address": "123 Main St", "locality": "Springfield"})
+    );
+    assert_eq!(body["phone_number_verified"], false);
     assert_eq!(body["locale"], "en-US");
     assert_eq!(body["birthdate"], "1990-01-01");
+    assert_eq!(body["preferred_username"], "j.doe"); // not the login name
+    assert_eq!(body["nickname"], "Johnny");
+    assert_eq!(body["picture"], "https://example.com/john.png");
+    assert_eq!(body["zoneinfo"], "Europe/Berlin");
+    assert_eq!(body["updated_at"], 1700000000);
+    assert!(body.get("website").is_none());
 }
 
 #[actix_rt::test]
@@ -147,8 +204,8 @@ async fn test_userinfo_invalid_token_type() {
             client_id: "test1".into(),
             scopes: Some("openid".into()),
             subject: Some("user@example.com".into()),
-            expiration: None,
             created: chrono::Utc::now().naive_utc(),
+            ..Default::default()
         })
     });
 
@@ -194,3 +251,155 @@ async fn test_userinfo_invalid_bearer_format() {
     let resp = call_userinfo(oauth_db, user_db, Some("Token abc123")).await;
     assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
 }
+
+#[actix_rt::test]
+async fn test_userinfo_claims_request() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    let mut user_db = Box::new(core::MockUserDatabase::new());
+
+    // the email and the phone_number are requested without their scopes, the client is only allowed the email
+    oauth_db.expect_load_token_data().with(eq(ACCESS_TOKEN)).times(1).returning(|_| {
+        Ok(OauthToken {
+            claims: Some(r#"{"userinfo":{"email":{"essential":true},"phone_number":null},"id_token":{"given_name":null}}"#.into()),
+            ..token_with_scopes("openid")
+        })
+    });
+    user_db.expect_fetch_user_by_id().times(1).returning(|_| Ok(test_user()));
+
+    let client = OauthClient {
+        allowed_scopes: "openid email".into(),
+        ..test_client()
+    };
+    let resp = call_userinfo_for(oauth_db, user_db, Some(&bearer(ACCESS_TOKEN)), client).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["sub"], "user@example.com");
+    assert_eq!(body["email"], "user@example.com");
+    assert!(body.get("email_verified").is_none());
+    assert!(body.get("phone_number").is_none());
+    assert!(body.get("given_name").is_none());
+}
+
+#[actix_rt::test]
+async fn test_userinfo_custom_scope() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    let mut user_db = Box::new(core::MockUserDatabase::new());
+
+    oauth_db
+        .expect_load_token_data()
+        .with(eq(ACCESS_TOKEN))
+        .times(1)
+        .returning(|_| Ok(token_with_scopes("openid employee")));
+    user_db.expect_fetch_user_by_id().times(1).returning(|_| {
+        Ok(User {
+            attributes: Some(r#"{"department":"R&D","cc":"CC-1001","salary":1}"#.into()),
+            ..test_user()
+        })
+    });
+
+    let resp = call_userinfo(oauth_db, user_db, Some(&bearer(ACCESS_TOKEN))).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // department is read from the attribute with the same name, cost_center is mapped to attributes.cc
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["department"], "R&D");
+    assert_eq!(body["cost_center"], "CC-1001");
+    assert!(body.get("salary").is_none());
+    assert!(body.get("given_name").is_none());
+}
+
+#[actix_rt::test]
+async fn test_userinfo_signed() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    let mut user_db = Box::new(core::MockUserDatabase::new());
+    oauth_db
+        .expect_load_token_data()
+        .times(1)
+        .returning(|_| Ok(token_with_scopes("openid email")));
+    user_db.expect_fetch_user_by_id().times(1).returning(|_| Ok(test_user()));
+
+    let client = OauthClient {
+        userinfo_signed_response_alg: Some(jsonwebtoken::Algorithm::RS256),
+        ..test_client()
+    };
+    let resp = call_userinfo_for(oauth_db, user_db, Some(&bearer(ACCESS_TOKEN)), client).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get("content-type").unwrap(), "application/jwt");
+
+    let jwt = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    let header = jsonwebtoken::decode_header(&jwt).unwrap();
+    assert_eq!(header.alg, jsonwebtoken::Algorithm::RS256);
+    assert_eq!(header.kid.as_deref(), Some(common::TEST_SECRET_NAME));
+
+    let payload = jwt.split('.').nth(1).unwrap();
+    let claims: serde_json::Value = serde_json::from_slice(&BASE64_URL_SAFE_NO_PAD.decode(payload).unwrap()).unwrap();
+    assert_eq!(claims["sub"], "user@example.com");
+    assert_eq!(claims["iss"], common::test_config().oauth.issuer);
+    assert_eq!(claims["aud"], "test1");
+    assert_eq!(claims["email"], "user@example.com");
+}
+
+#[actix_rt::test]
+async fn test_userinfo_signed_and_encrypted() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    let mut user_db = Box::new(core::MockUserDatabase::new());
+    oauth_db.expect_load_token_data().times(1).returning(|_| Ok(token_with_scopes("openid")));
+    user_db.expect_fetch_user_by_id().times(1).returning(|_| Ok(test_user()));
+
+    let key = openssl::rsa::Rsa::generate(2048).unwrap();
+    let jwks = serde_json::json!({"keys": [{
+        "kty": "RSA",
+        "use": "enc",
+        "kid": "client-enc",
+        "n": BASE64_URL_SAFE_NO_PAD.encode(key.n().to_vec()),
+        "e": BASE64_URL_SAFE_NO_PAD.encode(key.e().to_vec()),
+    }]});
+    let client = OauthClient {
+        jwks: Some(serde_json::from_value(jwks).unwrap()),
+        userinfo_signed_response_alg: Some(jsonwebtoken::Algorithm::RS256),
+        userinfo_encrypted_response_alg: Some("RSA-OAEP-256".into()),
+        userinfo_encrypted_response_enc: Some("A256GCM".into()),
+        ..test_client()
+    };
+    let resp = call_userinfo_for(oauth_db, user_db, Some(&bearer(ACCESS_TOKEN)), client).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get("content-type").unwrap(), "application/jwt");
+
+    let jwe = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    assert_eq!(jwe.split('.').count(), 5);
+    let header: serde_json::Value = serde_json::from_slice(&BASE64_URL_SAFE_NO_PAD.decode(jwe.split('.').next().unwrap()).unwrap()).unwrap();
+    assert_eq!(
+        header,
+        serde_json::json!({"alg": "RSA-OAEP-256", "enc": "A256GCM", "kid": "client-enc", "cty": "JWT"})
+    );
+}
+
+#[actix_rt::test]
+async fn test_userinfo_pairwise_subject() {
+    let mut oauth_db = Box::new(core::MockOauthDatabase::new());
+    let mut user_db = Box::new(core::MockUserDatabase::new());
+    oauth_db
+        .expect_load_token_data()
+        .times(1)
+        .returning(|_| Ok(token_with_scopes("openid email")));
+    // the token keeps the internal user id
+    user_db
+        .expect_fetch_user_by_id()
+        .with(eq("user@example.com"))
+        .times(1)
+        .returning(|_| Ok(test_user()));
+
+    let client = OauthClient {
+        subject_type: "pairwise".into(),
+        ..test_client()
+    };
+    let resp = call_userinfo_for(oauth_db, user_db, Some(&bearer(ACCESS_TOKEN)), client).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let sub = body["sub"].as_str().unwrap();
+    assert_ne!(sub, "user@example.com");
+    assert_eq!(sub.len(), 43); // base64url SHA-256
+    assert_eq!(body["email"], "user@example.com");
+}